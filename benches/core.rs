@@ -0,0 +1,180 @@
+//! Benchmarks of the emulation core: decoding, running each kind of
+//! instruction, drawing sprites and running whole frames of a few programs
+//! with each engine. Run with `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use chip_8::chip8::{
+    decode_instruction, emulation_step_until, execute_instruction, Chip8, StackFrame,
+};
+use chip_8::jit::Jit;
+
+/// An instruction of each kind, named like the disassembler does
+const INSTRUCTIONS: [(&str, u16); 34] = [
+    ("CLS", 0x00E0),
+    ("RET", 0x00EE),
+    ("JP 0x200", 0x1200),
+    ("CALL 0x200", 0x2200),
+    ("SE V1, 0x03", 0x3103),
+    ("SNE V1, 0x03", 0x4103),
+    ("SE V1, V2", 0x5120),
+    ("LD V1, 0x05", 0x6105),
+    ("ADD V1, 0x05", 0x7105),
+    ("LD V1, V2", 0x8120),
+    ("OR V1, V2", 0x8121),
+    ("AND V1, V2", 0x8122),
+    ("XOR V1, V2", 0x8123),
+    ("ADD V1, V2", 0x8124),
+    ("SUB V1, V2", 0x8125),
+    ("SHR V1, V2", 0x8126),
+    ("SUBN V1, V2", 0x8127),
+    ("SHL V1, V2", 0x812E),
+    ("SNE V1, V2", 0x9120),
+    ("LD I, 0x300", 0xA300),
+    ("JP V0, 0x200", 0xB200),
+    ("RND V1, 0xFF", 0xC1FF),
+    ("DRW V1, V2, 5", 0xD125),
+    ("SKP V1", 0xE19E),
+    ("SKNP V1", 0xE1A1),
+    ("LD V1, DT", 0xF107),
+    ("LD V1, K", 0xF10A),
+    ("LD DT, V1", 0xF115),
+    ("LD ST, V1", 0xF118),
+    ("ADD I, V1", 0xF11E),
+    ("LD F, V1", 0xF129),
+    ("LD B, V1", 0xF133),
+    ("LD [I], VF", 0xFF55),
+    ("LD VF, [I]", 0xFF65),
+];
+
+/// Programs that keep running, each busy with different instructions
+const ROMS: [(&str, &[u8]); 3] = [
+    (
+        "arithmetic",
+        &[
+            0x60, 0x00, // V0 = 0
+            0x61, 0x00, // V1 = 0
+            0x70, 0x01, // V0 += 1
+            0x71, 0x02, // V1 += 2
+            0x80, 0x14, // V0 += V1
+            0x62, 0x05, // V2 = 5
+            0x72, 0x01, // V2 += 1
+            0xA3, 0x00, // I = 0x300
+            0x12, 0x04, // loop
+        ],
+    ),
+    (
+        "sprites",
+        &[
+            0x60, 0x00, // V0 = 0
+            0x61, 0x00, // V1 = 0
+            0x62, 0x00, // V2 = 0
+            0xF2, 0x29, // I = digit V2
+            0xD0, 0x15, // draw it at V0, V1
+            0x70, 0x05, // V0 += 5
+            0x71, 0x03, // V1 += 3
+            0x72, 0x01, // V2 += 1
+            0x42, 0x10, // skip unless V2 == 16
+            0x62, 0x00, // V2 = 0
+            0x12, 0x06, // loop
+        ],
+    ),
+    (
+        "subroutines",
+        &[
+            0xA3, 0x00, // I = 0x300
+            0x22, 0x0A, // call 0x20A
+            0xF5, 0x55, // store V0 - V5 at I
+            0xF5, 0x65, // load V0 - V5 from I
+            0x12, 0x02, // loop
+            0xC0, 0xFF, // 0x20A: V0 = random
+            0xF0, 0x33, // V0 as decimal digits at I
+            0x00, 0xEE, // return
+        ],
+    ),
+];
+
+// a fast program's worth, 60000 instructions per second
+const INSTRUCTIONS_PER_FRAME: u32 = 1000;
+
+/// A machine with the font loaded, V1 and V2 set and I pointing at a sprite
+fn machine() -> Chip8 {
+    let mut chip8 = Chip8::new();
+    chip8.load_font();
+    chip8.load_into_mem(&[0xFF; 16], 0x300);
+    chip8.regs.general[1] = 3;
+    chip8.regs.general[2] = 4;
+    chip8
+}
+
+fn decode(c: &mut Criterion) {
+    c.bench_function("decode", |b| {
+        b.iter(|| {
+            for (_, opcode) in INSTRUCTIONS {
+                let _ = black_box(decode_instruction(black_box(opcode)));
+            }
+        })
+    });
+}
+
+fn execute(c: &mut Criterion) {
+    let mut group = c.benchmark_group("execute");
+    for (name, opcode) in INSTRUCTIONS {
+        let instruction = decode_instruction(opcode).unwrap();
+        let mut chip8 = machine();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                // so calls don't overflow the stack, returns have a call to
+                // return from and I stays in memory
+                chip8.regs.pc = 0x202;
+                chip8.stack.clear();
+                chip8.stack.push(StackFrame {
+                    caller: 0x200,
+                    target: 0x200,
+                });
+                chip8.regs.i = 0x300;
+                execute_instruction(black_box(&instruction), &mut chip8).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn draw(c: &mut Criterion) {
+    let mut group = c.benchmark_group("draw");
+    for rows in [1, 5, 8, 15] {
+        let instruction = decode_instruction(0xD120 | rows).unwrap();
+        let mut chip8 = machine();
+        chip8.regs.i = 0x300;
+        group.bench_with_input(BenchmarkId::from_parameter(rows), &instruction, |b, ins| {
+            b.iter(|| execute_instruction(black_box(ins), &mut chip8).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    for (name, rom) in ROMS {
+        for engine in ["interpreter", "uncached", "jit"] {
+            let mut chip8 = machine();
+            chip8.load_rom(rom, 0x200).unwrap();
+            chip8.regs.pc = 0x200;
+            match engine {
+                "uncached" => chip8.instruction_cache = None,
+                "jit" => {
+                    chip8.instruction_cache = None;
+                    chip8.jit = Some(Jit::new(chip8.memory.len()));
+                }
+                _ => {}
+            }
+            group.bench_function(BenchmarkId::new(engine, name), |b| {
+                b.iter(|| emulation_step_until(&mut chip8, INSTRUCTIONS_PER_FRAME, |_| false))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode, execute, draw, frame);
+criterion_main!(benches);
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+
+use crate::chip8::Instruction;
+use crate::symbols::Symbols;
+
+/// Assembles a program written in the syntax the disassembler prints
+/// (Cowgod's reference), for a ROM loaded at `start_address`.
+/// Besides instructions a line can have:
+/// - a label, `name:`, which can be used in place of an address
+/// - `DB` and `DW` followed by a list of bytes or words, for data
+/// - a comment, from `;` to the end of the line
+///
+/// Numbers can be decimal, hexadecimal (`0x` or `#`) or binary (`0b`).
+/// # Example
+/// ```text
+/// start:
+///     LD I, sprite
+///     DRW V0, V1, 2
+///     JP start   ; loop forever
+/// sprite:
+///     DB 0b11000000, 0b11000000
+/// ```
+///
+/// Gives the ROM and its symbols: the labels, and the bytes written with
+/// `DB` and `DW` as data.
+pub fn assemble(source: &str, start_address: u16) -> Result<(Vec<u8>, Symbols), String> {
+    // first pass works out where the labels are, the second encodes
+    let mut labels = HashMap::new();
+    let mut symbols = Symbols::default();
+    let mut address = start_address as usize;
+    for (line_no, line) in source.lines().enumerate() {
+        let (label, statement) = split_line(line);
+        if let Some(label) = label {
+            if labels.insert(label.to_string(), address as u16).is_some() {
+                return Err(format!(
+                    "line {}: label {} defined twice",
+                    line_no + 1,
+                    label
+                ));
+            }
+        }
+        if let Some((mnemonic, operands)) = statement {
+            let (length, data) = match mnemonic.to_uppercase().as_str() {
+                "DB" => (operands.len(), true),
+                "DW" => (operands.len() * 2, true),
+                _ => (2, false),
+            };
+            if data {
+                symbols.add_data(address..address + length);
+            }
+            address += length;
+        }
+    }
+
+    let mut rom = Vec::new();
+    for (line_no, line) in source.lines().enumerate() {
+        if let (_, Some((mnemonic, operands))) = split_line(line) {
+            assemble_statement(&mut rom, mnemonic, &operands, &labels)
+                .map_err(|err| format!("line {}: {}", line_no + 1, err))?;
+        }
+    }
+    Ok((rom, Symbols::from_labels(&labels).merge(symbols)))
+}
+
+/// Splits a line into its label and its statement (mnemonic and operands)
+fn split_line(line: &str) -> (Option<&str>, Option<(&str, Vec<&str>)>) {
+    let line = line.split(';').next().unwrap_or("").trim();
+    let (label, rest) = match line.split_once(':') {
+        Some((label, rest)) => (Some(label.trim()), rest.trim()),
+        None => (None, line),
+    };
+    if rest.is_empty() {
+        return (label, None);
+    }
+    let (mnemonic, operands) = match rest.split_once(char::is_whitespace) {
+        Some((mnemonic, operands)) => (
+            mnemonic,
+            operands.split(',').map(|operand| operand.trim()).collect(),
+        ),
+        None => (rest, vec![]),
+    };
+    (label, Some((mnemonic, operands)))
+}
+
+fn assemble_statement(
+    rom: &mut Vec<u8>,
+    mnemonic: &str,
+    operands: &[&str],
+    labels: &HashMap<String, u16>,
+) -> Result<(), String> {
+    match mnemonic.to_uppercase().as_str() {
+        "DB" => {
+            for operand in operands {
+                rom.push(number(operand, labels, 0xFF)? as u8);
+            }
+        }
+        "DW" => {
+            for operand in operands {
+                let word = number(operand, labels, 0xFFFF)?;
+                rom.extend(word.to_be_bytes());
+            }
+        }
+        mnemonic => {
+            let ins = parse_instruction(mnemonic, operands, labels)?;
+            rom.extend(encode_instruction(&ins).to_be_bytes());
+        }
+    }
+    Ok(())
+}
+
+fn parse_instruction(
+    mnemonic: &str,
+    operands: &[&str],
+    labels: &HashMap<String, u16>,
+) -> Result<Instruction, String> {
+    let upper: Vec<String> = operands.iter().map(|op| op.to_uppercase()).collect();
+    let upper: Vec<&str> = upper.iter().map(|op| op.as_str()).collect();
+    let address = |op: &str| number(op, labels, 0xFFF);
+    let byte = |op: &str| number(op, labels, 0xFF).map(|val| val as u8);
+
+    let ins = match (mnemonic, upper.as_slice()) {
+        ("CLS", []) => Instruction::Cls,
+        ("RET", []) => Instruction::Ret,
+        ("JP", ["V0", _]) => Instruction::JmpV0 {
+            address: address(operands[1])?,
+        },
+        ("JP", [_]) => Instruction::Jmp {
+            address: address(operands[0])?,
+        },
+        ("CALL", [_]) => Instruction::Call {
+            address: address(operands[0])?,
+        },
+        ("SE", [x, y]) => match (register(x), register(y)) {
+            (Some(reg1), Some(reg2)) => Instruction::SeReg { reg1, reg2 },
+            (Some(reg), None) => Instruction::Se {
+                reg,
+                val: byte(operands[1])?,
+            },
+            _ => return Err(format!("expected a register, got {}", operands[0])),
+        },
+        ("SNE", [x, y]) => match (register(x), register(y)) {
+            (Some(reg1), Some(reg2)) => Instruction::SneReg { reg1, reg2 },
+            (Some(reg), None) => Instruction::Sne {
+                reg,
+                val: byte(operands[1])?,
+            },
+            _ => return Err(format!("expected a register, got {}", operands[0])),
+        },
+        ("LD", ["I", _]) => Instruction::Ldi {
+            address: address(operands[1])?,
+        },
+        ("LD", ["DT", y]) => Instruction::LdIntoDt { reg: reg(y)? },
+        ("LD", ["ST", y]) => Instruction::LdSt { reg: reg(y)? },
+        ("LD", ["F", y]) => Instruction::LdF { reg: reg(y)? },
+        ("LD", ["B", y]) => Instruction::LdB { reg: reg(y)? },
+        ("LD", ["[I]", y]) => Instruction::LdRegsMem { end_reg: reg(y)? },
+        ("LD", [x, "DT"]) => Instruction::LdFromDt { reg: reg(x)? },
+        ("LD", [x, "K"]) => Instruction::LdKey { reg: reg(x)? },
+        ("LD", [x, "[I]"]) => Instruction::LdMemRegs { end_reg: reg(x)? },
+        ("LD", [x, y]) => match register(y) {
+            Some(reg2) => Instruction::LdReg {
+                reg1: reg(x)?,
+                reg2,
+            },
+            None => Instruction::Ld {
+                reg: reg(x)?,
+                val: byte(operands[1])?,
+            },
+        },
+        ("ADD", ["I", y]) => Instruction::AddI { reg: reg(y)? },
+        ("ADD", [x, y]) => match register(y) {
+            Some(reg2) => Instruction::AddReg {
+                reg1: reg(x)?,
+                reg2,
+            },
+            None => Instruction::Add {
+                reg: reg(x)?,
+                val: byte(operands[1])?,
+            },
+        },
+        ("OR", [x, y]) => Instruction::Or {
+            reg1: reg(x)?,
+            reg2: reg(y)?,
+        },
+        ("AND", [x, y]) => Instruction::And {
+            reg1: reg(x)?,
+            reg2: reg(y)?,
+        },
+        ("XOR", [x, y]) => Instruction::Xor {
+            reg1: reg(x)?,
+            reg2: reg(y)?,
+        },
+        ("SUB", [x, y]) => Instruction::SubReg {
+            reg1: reg(x)?,
+            reg2: reg(y)?,
+        },
+        ("SUBN", [x, y]) => Instruction::SubRegN {
+            reg1: reg(x)?,
+            reg2: reg(y)?,
+        },
+        // the second register is only used without the shift quirk
+        ("SHR", [x]) => Instruction::Shr {
+            reg1: reg(x)?,
+            reg2: reg(x)?,
+        },
+        ("SHR", [x, y]) => Instruction::Shr {
+            reg1: reg(x)?,
+            reg2: reg(y)?,
+        },
+        ("SHL", [x]) => Instruction::Shl {
+            reg1: reg(x)?,
+            reg2: reg(x)?,
+        },
+        ("SHL", [x, y]) => Instruction::Shl {
+            reg1: reg(x)?,
+            reg2: reg(y)?,
+        },
+        ("RND", [x, _]) => Instruction::Rnd {
+            reg: reg(x)?,
+            mask: byte(operands[1])?,
+        },
+        ("DRW", [x, y, _]) => Instruction::Drw {
+            reg1: reg(x)?,
+            reg2: reg(y)?,
+            n_bytes: number(operands[2], labels, 0xF)? as u8,
+        },
+        ("SKP", [x]) => Instruction::Skp { reg: reg(x)? },
+        ("SKNP", [x]) => Instruction::SkpNp { reg: reg(x)? },
+        _ => {
+            let text = format!("{} {}", mnemonic, operands.join(", "));
+            return Err(format!("unknown instruction {}", text.trim_end()));
+        }
+    };
+    Ok(ins)
+}
+
+/// Encodes an instruction into its opcode, the reverse of `decode_instruction`
+pub fn encode_instruction(ins: &Instruction) -> u16 {
+    let xy = |op: u16, x: u8, y: u8, n: u16| op << 12 | (x as u16) << 8 | (y as u16) << 4 | n;
+    let xnn = |op: u16, x: u8, nn: u8| op << 12 | (x as u16) << 8 | nn as u16;
+    match ins {
+        Instruction::Cls => 0x00E0,
+        Instruction::Ret => 0x00EE,
+        Instruction::Jmp { address } => 0x1000 | address,
+        Instruction::Call { address } => 0x2000 | address,
+        Instruction::Se { reg, val } => xnn(0x3, *reg, *val),
+        Instruction::Sne { reg, val } => xnn(0x4, *reg, *val),
+        Instruction::SeReg { reg1, reg2 } => xy(0x5, *reg1, *reg2, 0x0),
+        Instruction::Ld { reg, val } => xnn(0x6, *reg, *val),
+        Instruction::Add { reg, val } => xnn(0x7, *reg, *val),
+        Instruction::LdReg { reg1, reg2 } => xy(0x8, *reg1, *reg2, 0x0),
+        Instruction::Or { reg1, reg2 } => xy(0x8, *reg1, *reg2, 0x1),
+        Instruction::And { reg1, reg2 } => xy(0x8, *reg1, *reg2, 0x2),
+        Instruction::Xor { reg1, reg2 } => xy(0x8, *reg1, *reg2, 0x3),
+        Instruction::AddReg { reg1, reg2 } => xy(0x8, *reg1, *reg2, 0x4),
+        Instruction::SubReg { reg1, reg2 } => xy(0x8, *reg1, *reg2, 0x5),
+        Instruction::Shr { reg1, reg2 } => xy(0x8, *reg1, *reg2, 0x6),
+        Instruction::SubRegN { reg1, reg2 } => xy(0x8, *reg1, *reg2, 0x7),
+        Instruction::Shl { reg1, reg2 } => xy(0x8, *reg1, *reg2, 0xE),
+        Instruction::SneReg { reg1, reg2 } => xy(0x9, *reg1, *reg2, 0x0),
+        Instruction::Ldi { address } => 0xA000 | address,
+        Instruction::JmpV0 { address } => 0xB000 | address,
+        Instruction::Rnd { reg, mask } => xnn(0xC, *reg, *mask),
+        Instruction::Drw {
+            reg1,
+            reg2,
+            n_bytes,
+        } => xy(0xD, *reg1, *reg2, *n_bytes as u16),
+        Instruction::Skp { reg } => xnn(0xE, *reg, 0x9E),
+        Instruction::SkpNp { reg } => xnn(0xE, *reg, 0xA1),
+        Instruction::LdFromDt { reg } => xnn(0xF, *reg, 0x07),
+        Instruction::LdKey { reg } => xnn(0xF, *reg, 0x0A),
+        Instruction::LdIntoDt { reg } => xnn(0xF, *reg, 0x15),
+        Instruction::LdSt { reg } => xnn(0xF, *reg, 0x18),
+        Instruction::AddI { reg } => xnn(0xF, *reg, 0x1E),
+        Instruction::LdF { reg } => xnn(0xF, *reg, 0x29),
+        Instruction::LdB { reg } => xnn(0xF, *reg, 0x33),
+        Instruction::LdRegsMem { end_reg } => xnn(0xF, *end_reg, 0x55),
+        Instruction::LdMemRegs { end_reg } => xnn(0xF, *end_reg, 0x65),
+    }
+}
+
+/// Parses a register name like "VA", operands are uppercased already
+fn register(operand: &str) -> Option<u8> {
+    let digit = operand.strip_prefix('V')?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn reg(operand: &str) -> Result<u8, String> {
+    register(operand).ok_or_else(|| format!("expected a register, got {}", operand))
+}
+
+/// Parses a number or a label, which has to be at most `max`
+fn number(operand: &str, labels: &HashMap<String, u16>, max: u32) -> Result<u16, String> {
+    let lower = operand.to_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x").or(lower.strip_prefix('#')) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        u32::from_str_radix(bin, 2).ok()
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        lower.parse().ok()
+    } else {
+        labels.get(operand).map(|address| *address as u32)
+    };
+    match parsed {
+        Some(val) if val <= max => Ok(val as u16),
+        Some(val) => Err(format!("{} is too large, at most {:#X} fits", val, max)),
+        None => Err(format!("expected a number or a label, got {}", operand)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::decode_instruction;
+    use crate::disasm::format_instruction;
+
+    #[test]
+    fn assemble_works() {
+        let source = "
+            ; draws a sprite and loops forever
+            start:
+                LD I, sprite
+                ld v0, #1F
+                DRW V0, V1, 2
+            loop: JP loop
+            sprite:
+                DB 0b11000000, 0xC0
+                DW 0x1234
+        ";
+        assert_eq!(
+            assemble(source, 0x200).map(|(rom, _)| rom),
+            Ok(vec![
+                0xA2, 0x08, 0x60, 0x1F, 0xD0, 0x12, 0x12, 0x06, 0xC0, 0xC0, 0x12, 0x34
+            ])
+        );
+    }
+
+    #[test]
+    fn assemble_gives_symbols() {
+        let source = "
+            start: CLS
+            loop: JP loop
+            sprite: DB 0xC0, 0xC0
+                DW 0x1234
+            CLS
+        ";
+        let (_, symbols) = assemble(source, 0x200).unwrap();
+        assert_eq!(
+            symbols.to_text(),
+            "0x200 start\n0x202 loop\n0x204 sprite\ndata 0x204 4\n"
+        );
+    }
+
+    #[test]
+    fn assemble_reports_errors() {
+        assert!(assemble("JP nowhere", 0x200).is_err());
+        assert!(assemble("LD V0, 256", 0x200).is_err());
+        assert!(assemble("LD VG, 1", 0x200).is_err());
+        assert!(assemble("MOV V0, V1", 0x200).is_err());
+        assert!(assemble("a:\na:", 0x200).is_err());
+        assert_eq!(
+            assemble("CLS\nCLS\nFOO", 0x200),
+            Err("line 3: unknown instruction FOO".to_string())
+        );
+    }
+
+    #[test]
+    fn disassembled_instructions_assemble_back() {
+        for opcode in 0..=0xFFFF {
+            if let Ok(ins) = decode_instruction(opcode) {
+                let text = format_instruction(&ins);
+                assert_eq!(
+                    assemble(&text, 0x200).map(|(rom, _)| rom),
+                    Ok(opcode.to_be_bytes().to_vec()),
+                    "{}",
+                    text
+                );
+            }
+        }
+    }
+}
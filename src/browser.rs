@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::overlay::{Ink, Overlay, COLUMNS, ROWS};
+use crate::platform::Platform;
+
+/// Extensions of the files listed from the ROM directory, `gif` is for
+/// Octo cartridges
+const ROM_EXTENSIONS: [&str; 6] = ["ch8", "c8", "sc8", "xo8", "hc8", "gif"];
+
+// number of recently played ROMs that are remembered
+const MAX_RECENT: usize = 20;
+
+// the tabs and a blank row above the list, the keys below it
+const LIST_TOP: usize = 2;
+const LIST_ROWS: usize = ROWS - LIST_TOP - 1;
+
+// width of the file name in a row of the list, the rest goes to the
+// favorite mark, the size and the platform
+const NAME_WIDTH: usize = COLUMNS - 17;
+
+/// A ROM listed in the browser
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub path: PathBuf,
+    /// size of the file in bytes
+    pub size: u64,
+    /// platform the ROM would be run as
+    pub platform: Option<Platform>,
+}
+
+/// The entries of ROM files described before, each kept until its file is
+/// modified, so opening the browser again doesn't read and compile every ROM
+/// again
+#[derive(Default)]
+pub struct EntryCache {
+    entries: HashMap<PathBuf, (SystemTime, Option<Entry>)>,
+}
+
+impl EntryCache {
+    /// The entry of the file, from `describe` unless the file is the same
+    /// as the last time. Files that can't be found have none.
+    pub fn get(
+        &mut self,
+        path: &Path,
+        describe: impl FnOnce(&Path) -> Option<Entry>,
+    ) -> Option<Entry> {
+        let modified = match std::fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(_) => {
+                self.entries.remove(path);
+                return None;
+            }
+        };
+        match self.entries.get(path) {
+            Some((described, entry)) if *described == modified => entry.clone(),
+            _ => {
+                let entry = describe(path);
+                self.entries
+                    .insert(path.to_path_buf(), (modified, entry.clone()));
+                entry
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tab {
+    All,
+    Recent,
+    Favorites,
+}
+
+const TABS: [Tab; 3] = [Tab::All, Tab::Recent, Tab::Favorites];
+
+impl Tab {
+    fn name(&self) -> &'static str {
+        match self {
+            Tab::All => "ALL",
+            Tab::Recent => "RECENT",
+            Tab::Favorites => "FAVORITES",
+        }
+    }
+
+    fn empty_text(&self) -> &'static str {
+        match self {
+            Tab::All => "NO ROMS FOUND, SET ROM_DIR IN THE CONFIG FILE",
+            Tab::Recent => "NO ROMS PLAYED YET",
+            Tab::Favorites => "NO FAVORITES YET, PRESS SPACE ON A ROM TO ADD IT",
+        }
+    }
+}
+
+/// What can be done in the browser, from the keyboard or a controller
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    First,
+    Last,
+    NextTab,
+    PreviousTab,
+    ToggleFavorite,
+    Play,
+    Close,
+}
+
+/// What the browser asks of the emulator after an action
+#[derive(Clone, Debug, PartialEq)]
+pub enum Choice {
+    Play(PathBuf),
+    Close,
+    /// the history needs to be saved
+    FavoritesChanged,
+}
+
+/// The recently played and favorite ROMs, kept between runs
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+#[serde(default)]
+pub struct History {
+    /// most recent first
+    pub recent: Vec<PathBuf>,
+    pub favorites: Vec<PathBuf>,
+}
+
+impl History {
+    /// Loads the history, a missing file results in an empty one
+    pub fn load(path: &Path) -> Result<History, String> {
+        if !path.exists() {
+            return Ok(History::default());
+        }
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read history {}: {}", path.display(), err))?;
+        toml::from_str(&text).map_err(|err| format!("Invalid history {}: {}", path.display(), err))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|err| err.to_string())?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|err| format!("Failed to create directory {}: {}", dir.display(), err))?;
+        }
+        std::fs::write(path, text)
+            .map_err(|err| format!("Failed to write history {}: {}", path.display(), err))
+    }
+
+    pub fn played(&mut self, path: PathBuf) {
+        self.recent.retain(|recent| *recent != path);
+        self.recent.insert(0, path);
+        self.recent.truncate(MAX_RECENT);
+    }
+
+    pub fn is_favorite(&self, path: &Path) -> bool {
+        self.favorites.iter().any(|favorite| favorite == path)
+    }
+
+    pub fn toggle_favorite(&mut self, path: &Path) {
+        if self.is_favorite(path) {
+            self.favorites.retain(|favorite| favorite != path);
+        } else {
+            self.favorites.push(path.to_path_buf());
+        }
+    }
+}
+
+/// Lists the ROM files in a directory and its subdirectories, sorted by
+/// file name
+pub fn scan(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let list_error = |dir: &Path, err: std::io::Error| {
+        format!("Failed to list ROMs in {}: {}", dir.display(), err)
+    };
+    // absolute paths, so they match the ones in the history
+    let dir = dir.canonicalize().map_err(|err| list_error(dir, err))?;
+    let mut roms = Vec::new();
+    let mut dirs = vec![dir];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir).map_err(|err| list_error(&dir, err))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => dirs.push(path),
+                Ok(_) if is_rom_file(&path) => roms.push(path),
+                _ => {}
+            }
+        }
+    }
+    roms.sort_by_key(|path| file_name(path).to_lowercase());
+    Ok(roms)
+}
+
+fn is_rom_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            ROM_EXTENSIONS
+                .iter()
+                .any(|rom_extension| extension.eq_ignore_ascii_case(rom_extension))
+        })
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().to_string())
+}
+
+fn format_size(size: u64) -> String {
+    if size < 100_000 {
+        format!("{}B", size)
+    } else {
+        format!("{}K", size / 1024)
+    }
+}
+
+/// A menu for picking a ROM to play, from the ROM directory, the recently
+/// played ROMs or the favorites. It's drawn on the overlay, in place of the
+/// display.
+pub struct Browser {
+    all: Vec<PathBuf>,
+    entries: HashMap<PathBuf, Entry>,
+    tab: Tab,
+    selected: usize,
+    /// first entry shown
+    scroll: usize,
+}
+
+impl Browser {
+    /// Makes a browser with the ROMs of the ROM directory, `remembered` has
+    /// the ROMs of the history. Files that are missing from both aren't
+    /// listed.
+    pub fn new(all: Vec<Entry>, remembered: Vec<Entry>) -> Browser {
+        let paths = all.iter().map(|entry| entry.path.clone()).collect();
+        let entries = remembered
+            .into_iter()
+            .chain(all)
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+        Browser {
+            all: paths,
+            entries,
+            tab: Tab::All,
+            selected: 0,
+            scroll: 0,
+        }
+    }
+
+    /// Entries of the current tab
+    fn list(&self, history: &History) -> Vec<&Entry> {
+        let paths = match self.tab {
+            Tab::All => &self.all,
+            Tab::Recent => &history.recent,
+            Tab::Favorites => &history.favorites,
+        };
+        paths
+            .iter()
+            .filter_map(|path| self.entries.get(path))
+            .collect()
+    }
+
+    pub fn handle(&mut self, action: Action, history: &mut History) -> Option<Choice> {
+        let list = self.list(history);
+        let last = list.len().saturating_sub(1);
+        let selected = list.get(self.selected).map(|entry| entry.path.clone());
+        match action {
+            Action::Up => self.selected = self.selected.saturating_sub(1),
+            Action::Down => self.selected = (self.selected + 1).min(last),
+            Action::PageUp => self.selected = self.selected.saturating_sub(LIST_ROWS),
+            Action::PageDown => self.selected = (self.selected + LIST_ROWS).min(last),
+            Action::First => self.selected = 0,
+            Action::Last => self.selected = last,
+            Action::NextTab | Action::PreviousTab => {
+                let index = TABS.iter().position(|tab| *tab == self.tab).unwrap_or(0);
+                let offset = if action == Action::NextTab {
+                    1
+                } else {
+                    TABS.len() - 1
+                };
+                self.tab = TABS[(index + offset) % TABS.len()];
+                self.selected = 0;
+            }
+            Action::ToggleFavorite => {
+                let path = selected?;
+                history.toggle_favorite(&path);
+                // the entry can be gone from the favorites
+                let last = self.list(history).len().saturating_sub(1);
+                self.selected = self.selected.min(last);
+                self.scroll_to_selected();
+                return Some(Choice::FavoritesChanged);
+            }
+            Action::Play => return selected.map(Choice::Play),
+            Action::Close => return Some(Choice::Close),
+        }
+        self.scroll_to_selected();
+        None
+    }
+
+    fn scroll_to_selected(&mut self) {
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + LIST_ROWS {
+            self.scroll = self.selected + 1 - LIST_ROWS;
+        }
+    }
+
+    pub fn draw(&self, history: &History, overlay: &mut Overlay) {
+        let list = self.list(history);
+
+        // the rows of text leave a few pixels at the bottom
+        overlay.fill_all(Ink::Background);
+        let mut column = 0;
+        for tab in TABS {
+            if tab == self.tab {
+                overlay.fill(column, 0, tab.name().len() + 2, Ink::Foreground);
+                overlay.text(column + 1, 0, tab.name(), Ink::Background);
+            } else {
+                overlay.text(column + 1, 0, tab.name(), Ink::Foreground);
+            }
+            column += tab.name().len() + 2;
+        }
+        if !list.is_empty() {
+            let position = format!("{}/{}", self.selected + 1, list.len());
+            overlay.text(COLUMNS - position.len(), 0, &position, Ink::Foreground);
+        }
+
+        for row in 0..LIST_ROWS {
+            let text = match list.get(self.scroll + row) {
+                Some(entry) => {
+                    let name: String = file_name(&entry.path).chars().take(NAME_WIDTH).collect();
+                    format!(
+                        "{} {:<name_width$} {:>6} {:<7}",
+                        if history.is_favorite(&entry.path) {
+                            '*'
+                        } else {
+                            ' '
+                        },
+                        name,
+                        format_size(entry.size),
+                        entry
+                            .platform
+                            .map_or("CHIP-8", |platform| platform.short_name()),
+                        name_width = NAME_WIDTH,
+                    )
+                }
+                None if row == 0 && list.is_empty() => format!("  {}", self.tab.empty_text()),
+                None => String::new(),
+            };
+            let highlighted = !list.is_empty() && self.scroll + row == self.selected;
+            overlay.line(LIST_TOP + row, &text, highlighted);
+        }
+
+        overlay.line(
+            ROWS - 1,
+            "ENTER PLAY  SPACE FAVORITE  TAB LIST  ESC BACK",
+            false,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str) -> Entry {
+        Entry {
+            path: PathBuf::from(path),
+            size: 246,
+            platform: None,
+        }
+    }
+
+    #[test]
+    fn history_works() {
+        let mut history = History::default();
+        history.played(PathBuf::from("/roms/pong.ch8"));
+        history.played(PathBuf::from("/roms/tetris.ch8"));
+        history.played(PathBuf::from("/roms/pong.ch8"));
+        assert_eq!(
+            history.recent,
+            [
+                PathBuf::from("/roms/pong.ch8"),
+                PathBuf::from("/roms/tetris.ch8")
+            ]
+        );
+        for i in 0..MAX_RECENT {
+            history.played(PathBuf::from(format!("/roms/{}.ch8", i)));
+        }
+        assert_eq!(history.recent.len(), MAX_RECENT);
+
+        history.toggle_favorite(Path::new("/roms/pong.ch8"));
+        assert!(history.is_favorite(Path::new("/roms/pong.ch8")));
+        history.toggle_favorite(Path::new("/roms/pong.ch8"));
+        assert!(!history.is_favorite(Path::new("/roms/pong.ch8")));
+
+        let text = toml::to_string(&history).unwrap();
+        assert_eq!(toml::from_str::<History>(&text).unwrap(), history);
+    }
+
+    #[test]
+    fn browser_navigation_works() {
+        let all: Vec<Entry> = (0..30)
+            .map(|i| entry(&format!("/roms/{}.ch8", i)))
+            .collect();
+        let mut history = History::default();
+        history.played(PathBuf::from("/elsewhere/pong.ch8"));
+        let mut browser = Browser::new(all, vec![entry("/elsewhere/pong.ch8")]);
+
+        assert_eq!(browser.handle(Action::Up, &mut history), None);
+        assert_eq!(browser.selected, 0);
+        browser.handle(Action::PageDown, &mut history);
+        browser.handle(Action::Down, &mut history);
+        assert_eq!(browser.selected, LIST_ROWS + 1);
+        assert_eq!(browser.scroll, 2);
+        browser.handle(Action::Last, &mut history);
+        assert_eq!(browser.selected, 29);
+        assert_eq!(
+            browser.handle(Action::Play, &mut history),
+            Some(Choice::Play(PathBuf::from("/roms/29.ch8")))
+        );
+
+        assert_eq!(
+            browser.handle(Action::ToggleFavorite, &mut history),
+            Some(Choice::FavoritesChanged)
+        );
+        assert_eq!(history.favorites, [PathBuf::from("/roms/29.ch8")]);
+
+        browser.handle(Action::NextTab, &mut history);
+        assert_eq!(browser.tab, Tab::Recent);
+        assert_eq!(browser.selected, 0);
+        assert_eq!(
+            browser.handle(Action::Play, &mut history),
+            Some(Choice::Play(PathBuf::from("/elsewhere/pong.ch8")))
+        );
+
+        // unfavoriting empties the list, there's nothing left to play
+        browser.handle(Action::NextTab, &mut history);
+        assert_eq!(browser.tab, Tab::Favorites);
+        browser.handle(Action::ToggleFavorite, &mut history);
+        assert!(history.favorites.is_empty());
+        assert_eq!(browser.handle(Action::Play, &mut history), None);
+        assert_eq!(browser.handle(Action::ToggleFavorite, &mut history), None);
+
+        browser.handle(Action::PreviousTab, &mut history);
+        browser.handle(Action::PreviousTab, &mut history);
+        assert_eq!(browser.tab, Tab::All);
+        assert_eq!(
+            browser.handle(Action::Close, &mut history),
+            Some(Choice::Close)
+        );
+    }
+
+    #[test]
+    fn scan_works() {
+        let dir = std::env::temp_dir().join(format!("chip8-scan-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("schip")).unwrap();
+        for name in ["Tetris.ch8", "readme.txt", "schip/blinky.SC8", "pong.c8"] {
+            std::fs::write(dir.join(name), [0x12, 0x00]).unwrap();
+        }
+        let roms = scan(&dir).unwrap();
+        let names: Vec<String> = roms.iter().map(|path| file_name(path)).collect();
+        assert_eq!(names, ["blinky.SC8", "pong.c8", "Tetris.ch8"]);
+        assert!(roms.iter().all(|path| path.is_absolute()));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(scan(&dir).is_err());
+    }
+
+    #[test]
+    fn entry_cache_works() {
+        let dir = std::env::temp_dir().join(format!("chip8-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("pong.ch8");
+        std::fs::write(&path, [0x12, 0x00]).unwrap();
+
+        let mut cache = EntryCache::default();
+        let mut described = 0;
+        let mut describe = |path: &Path| {
+            described += 1;
+            Some(entry(&path.to_string_lossy()))
+        };
+        assert!(cache.get(&path, &mut describe).is_some());
+        assert!(cache.get(&path, &mut describe).is_some());
+        // described again once the file changes
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        assert!(cache.get(&path, &mut describe).is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(cache.get(&path, &mut describe).is_none());
+        assert_eq!(described, 2);
+    }
+
+    #[test]
+    fn format_size_works() {
+        assert_eq!(format_size(246), "246B");
+        assert_eq!(format_size(65024), "65024B");
+        assert_eq!(format_size(131072), "128K");
+    }
+}
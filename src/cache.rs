@@ -0,0 +1,91 @@
+use crate::chip8::{decode_instruction, Instruction};
+
+/// Instructions decoded before, by the address they're at. Most instructions
+/// run over and over, so they're only decoded the first time. Entries are
+/// dropped when the memory they were decoded from is written, which keeps
+/// self-modifying code working.
+pub struct InstructionCache {
+    entries: Vec<Option<Instruction>>,
+}
+
+impl InstructionCache {
+    pub fn new(memory_size: usize) -> InstructionCache {
+        InstructionCache {
+            entries: vec![None; memory_size],
+        }
+    }
+
+    /// The instruction at the address, decoded from memory unless it was
+    /// before. The address and the one after it have to be in memory.
+    pub fn get(&mut self, memory: &[u8], address: u16) -> Result<Instruction, String> {
+        let address = address as usize;
+        if let Some(instruction) = self.entries[address] {
+            return Ok(instruction);
+        }
+        let raw_instruction = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+        let instruction = decode_instruction(raw_instruction)?;
+        self.entries[address] = Some(instruction);
+        Ok(instruction)
+    }
+
+    /// Forgets the instructions that overlap the bytes written, including
+    /// the one starting the byte before
+    pub fn invalidate(&mut self, start: usize, len: usize) {
+        let end = (start + len).min(self.entries.len());
+        let first = start.saturating_sub(1).min(end);
+        self.entries[first..end].fill(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_decodes_once() {
+        let mut memory = vec![0; 16];
+        memory[2..6].copy_from_slice(&[0x60, 0x05, 0x12, 0x34]);
+        let mut cache = InstructionCache::new(memory.len());
+        assert_eq!(
+            cache.get(&memory, 2),
+            Ok(Instruction::Ld { reg: 0, val: 5 })
+        );
+        // changed behind the cache's back, so the old instruction is kept
+        memory[2] = 0x61;
+        assert_eq!(
+            cache.get(&memory, 2),
+            Ok(Instruction::Ld { reg: 0, val: 5 })
+        );
+        assert_eq!(
+            cache.get(&memory, 4),
+            Ok(Instruction::Jmp { address: 0x234 })
+        );
+        assert!(cache.get(&memory, 0).is_err());
+    }
+
+    #[test]
+    fn invalidate_works() {
+        let mut memory = vec![0; 16];
+        memory[2..8].copy_from_slice(&[0x60, 0x05, 0x12, 0x34, 0x00, 0xE0]);
+        let mut cache = InstructionCache::new(memory.len());
+        for address in [2, 4, 6] {
+            cache.get(&memory, address).unwrap();
+        }
+        // a write to the second byte of an instruction drops it too
+        memory[3..5].copy_from_slice(&[0x07, 0x13]);
+        cache.invalidate(3, 2);
+        assert_eq!(
+            cache.get(&memory, 2),
+            Ok(Instruction::Ld { reg: 0, val: 7 })
+        );
+        assert_eq!(
+            cache.get(&memory, 4),
+            Ok(Instruction::Jmp { address: 0x334 })
+        );
+        assert_eq!(cache.entries[6], Some(Instruction::Cls));
+
+        // writes running past the end of memory are cut off
+        cache.invalidate(15, 4);
+        cache.invalidate(20, 4);
+    }
+}
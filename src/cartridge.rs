@@ -0,0 +1,175 @@
+use serde::Deserialize;
+
+use crate::config::{QuirkOverrides, RomConfig};
+use crate::octo;
+use crate::symbols::Symbols;
+
+/// An Octo cartridge: a GIF image with the source of a program and its
+/// settings hidden in the pixels. Each pixel holds 2 bits of data in the low
+/// bits of its color index, 4 pixels make a byte (highest bits first). The
+/// data is a 4 byte big-endian length followed by that many bytes of JSON:
+/// `{ "program": "<Octo source>", "options": { ... } }`.
+pub struct Cartridge {
+    pub program: String,
+    pub options: OctoOptions,
+}
+
+/// The settings of a cartridge, named like in Octo. Octo has more of them,
+/// the ones without an equivalent here are ignored.
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct OctoOptions {
+    /// instructions per frame
+    pub tickrate: Option<u32>,
+    pub background_color: Option<String>,
+    pub fill_color: Option<String>,
+    pub fill_color2: Option<String>,
+    pub blend_color: Option<String>,
+    pub shift_quirks: Option<bool>,
+    pub load_store_quirks: Option<bool>,
+    pub clip_quirks: Option<bool>,
+    pub jump_quirks: Option<bool>,
+    pub v_blank_quirks: Option<bool>,
+    pub logic_quirks: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    program: String,
+    #[serde(default)]
+    options: OctoOptions,
+}
+
+pub fn is_cartridge(data: &[u8]) -> bool {
+    data.starts_with(b"GIF8")
+}
+
+impl Cartridge {
+    pub fn decode(gif: &[u8]) -> Result<Cartridge, String> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(gif).map_err(|err| err.to_string())?;
+
+        let mut bits = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().map_err(|err| err.to_string())? {
+            bits.extend(frame.buffer.iter().map(|index| index & 0b11));
+        }
+        let bytes: Vec<u8> = bits
+            .chunks_exact(4)
+            .map(|pairs| pairs.iter().fold(0, |byte, pair| byte << 2 | pair))
+            .collect();
+
+        let (length, json) = match bytes.split_first_chunk::<4>() {
+            Some((length, json)) => (u32::from_be_bytes(*length) as usize, json),
+            None => return Err("no program in the image".to_string()),
+        };
+        if length > json.len() {
+            return Err("no program in the image".to_string());
+        }
+        let payload: Payload = serde_json::from_slice(&json[..length])
+            .map_err(|err| format!("no program in the image ({})", err))?;
+        Ok(Cartridge {
+            program: payload.program,
+            options: payload.options,
+        })
+    }
+
+    /// Compiles the program into a ROM, its labels are the symbols
+    pub fn rom(&self) -> Result<(Vec<u8>, Symbols), String> {
+        let (rom, labels) = octo::compile_with_labels(&self.program)?;
+        Ok((rom, Symbols::from_labels(&labels)))
+    }
+
+    /// The settings of the cartridge, in the form of a ROM's section of the
+    /// config file
+    pub fn rom_config(&self) -> RomConfig {
+        let options = &self.options;
+        let colors: Vec<String> = [
+            &options.background_color,
+            &options.fill_color,
+            &options.fill_color2,
+            &options.blend_color,
+        ]
+        .into_iter()
+        .map_while(|color| color.clone())
+        .collect();
+        RomConfig {
+            colors: Some(colors).filter(|colors| colors.len() >= 2),
+            ips: options.tickrate.map(|tickrate| tickrate * 60),
+            quirks: QuirkOverrides {
+                shift: options.shift_quirks,
+                memory_leave_i_unchanged: options.load_store_quirks,
+                wrap: options.clip_quirks.map(|clip| !clip),
+                jump: options.jump_quirks,
+                vblank: options.v_blank_quirks,
+                logic: options.logic_quirks,
+                ..QuirkOverrides::default()
+            },
+            ..RomConfig::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a cartridge the way Octo does, without the label art
+    fn encode(json: &str) -> Vec<u8> {
+        let mut data = (json.len() as u32).to_be_bytes().to_vec();
+        data.extend(json.as_bytes());
+        let pixels: Vec<u8> = data
+            .iter()
+            .flat_map(|byte| [byte >> 6, byte >> 4 & 3, byte >> 2 & 3, byte & 3])
+            .collect();
+        let (width, height) = (32, pixels.len().div_ceil(32) as u16);
+        let mut indices = vec![0; width as usize * height as usize];
+        indices[..pixels.len()].copy_from_slice(&pixels);
+
+        let mut gif = Vec::new();
+        let palette = [0, 0, 0, 85, 85, 85, 170, 170, 170, 255, 255, 255];
+        let mut encoder = gif::Encoder::new(&mut gif, width, height, &palette).unwrap();
+        let frame = gif::Frame::from_indexed_pixels(width, height, indices, None);
+        encoder.write_frame(&frame).unwrap();
+        drop(encoder);
+        gif
+    }
+
+    #[test]
+    fn decode_works() {
+        let gif = encode(
+            r##"{
+                "program": ": main\n  v0 := 1\n  loop again",
+                "options": {
+                    "tickrate": 20,
+                    "backgroundColor": "#000000",
+                    "fillColor": "#FFCC00",
+                    "clipQuirks": true,
+                    "logicQuirks": false,
+                    "screenRotation": 0
+                }
+            }"##,
+        );
+        assert!(is_cartridge(&gif));
+        let cartridge = Cartridge::decode(&gif).unwrap();
+        let (rom, symbols) = cartridge.rom().unwrap();
+        assert_eq!(rom, [0x12, 0x02, 0x60, 0x01, 0x12, 0x04]);
+        assert_eq!(symbols.name(0x202), Some("main"));
+
+        let rom_config = cartridge.rom_config();
+        assert_eq!(
+            rom_config.colors,
+            Some(vec!["#000000".to_string(), "#FFCC00".to_string()])
+        );
+        assert_eq!(rom_config.ips, Some(1200));
+        assert_eq!(rom_config.quirks.wrap, Some(false));
+        assert_eq!(rom_config.quirks.logic, Some(false));
+        assert_eq!(rom_config.quirks.shift, None);
+    }
+
+    #[test]
+    fn decode_fails_without_program() {
+        assert!(Cartridge::decode(b"not a gif").is_err());
+        assert!(Cartridge::decode(&encode("[]")).is_err());
+    }
+}
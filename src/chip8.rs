@@ -1,27 +1,80 @@
-use fastrand;
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::cache::InstructionCache;
+use crate::display::Display;
+use crate::jit::{self, Jit};
+use crate::profile::Profile;
 
 pub const SCREEN_WIDTH: u8 = 64;
 pub const SCREEN_HEIGHT: u8 = 32;
-const DISP_BUFFER_SIZE: usize = SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize;
-const STACK_SIZE: usize = 16;
-const MEMORY_SIZE: usize = 4096;
+// calls SUPER-CHIP can nest, the COSMAC VIP only has room for 12
+pub const DEFAULT_STACK_DEPTH: usize = 16;
+// memory of the COSMAC VIP and the HP 48, XO-CHIP has more
+pub const MEMORY_SIZE: usize = 4096;
 
 pub struct Chip8 {
     pub regs: Registers,
-    pub disp_buffer: [u8; DISP_BUFFER_SIZE],
-    pub stack: [u16; 16],
-    pub memory: [u8; 4096],
+    pub display: Display,
+    /// the calls that haven't returned yet, the latest last
+    pub stack: Vec<StackFrame>,
+    /// calls that can be nested, one more overflows the stack
+    pub stack_depth: usize,
+    pub memory: Vec<u8>,
     pub key_down: [bool; 16],
+    pub quirks: Quirks,
+    pub key_wait: Option<KeyWait>,
+    pub vblank_wait: bool,  // set by DXYN with the vblank quirk, ends the frame
+    pub rng: fastrand::Rng, // used by CXNN, can be seeded for reproducible runs
+    /// decoded instructions, without it every instruction is decoded when it runs
+    pub instruction_cache: Option<InstructionCache>,
+    /// compiled code, it's run instead of interpreting instructions when set
+    pub jit: Option<Jit>,
+    /// counts of the instructions run, only kept when set
+    pub profile: Option<Profile>,
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
 }
 
 impl Chip8 {
     pub fn new() -> Chip8 {
         Chip8 {
             regs: Registers::new(),
-            disp_buffer: [0; DISP_BUFFER_SIZE],
-            stack: [0; STACK_SIZE],
-            memory: [0; MEMORY_SIZE],
+            display: Display::new(),
+            stack: Vec::new(),
+            stack_depth: DEFAULT_STACK_DEPTH,
+            memory: vec![0; MEMORY_SIZE],
             key_down: [false; 16],
+            quirks: Quirks::default(),
+            key_wait: None,
+            vblank_wait: false,
+            rng: fastrand::Rng::new(),
+            instruction_cache: Some(InstructionCache::new(MEMORY_SIZE)),
+            jit: None,
+            profile: None,
+        }
+    }
+
+    pub fn with_memory_size(memory_size: usize) -> Chip8 {
+        Chip8 {
+            memory: vec![0; memory_size],
+            instruction_cache: Some(InstructionCache::new(memory_size)),
+            ..Chip8::new()
+        }
+    }
+
+    /// Drops the decoded and compiled instructions of memory that was written
+    pub fn memory_written(&mut self, start: usize, len: usize) {
+        if let Some(cache) = self.instruction_cache.as_mut() {
+            cache.invalidate(start, len);
+        }
+        if let Some(jit) = self.jit.as_mut() {
+            jit.invalidate(start, len);
         }
     }
 
@@ -36,13 +89,10 @@ impl Chip8 {
     }
 
     pub fn get_px(&self, x: u8, y: u8) -> u8 {
-        return self.disp_buffer[y as usize * SCREEN_WIDTH as usize + x as usize];
-    }
-
-    pub fn set_px(&mut self, x: u8, y: u8, val: u8) {
-        return self.disp_buffer[y as usize * SCREEN_WIDTH as usize + x as usize] = val;
+        self.display.pixel(x, y)
     }
 
+    #[allow(dead_code)]
     pub fn dbg_print_display(&self) {
         for y in 0..SCREEN_HEIGHT {
             for x in 0..SCREEN_WIDTH {
@@ -53,10 +103,31 @@ impl Chip8 {
         println!()
     }
 
-    pub fn load_into_mem(&mut self, data: &Vec<u8>, start_addr: u16) {
+    pub fn load_into_mem(&mut self, data: &[u8], start_addr: u16) {
         for (i, byte) in data.iter().enumerate() {
             self.memory[start_addr as usize + i] = *byte;
         }
+        self.memory_written(start_addr as usize, data.len());
+    }
+
+    /// Loads a ROM at the start address, fails if it's empty or doesn't fit
+    /// into memory
+    pub fn load_rom(&mut self, rom: &[u8], start_address: u16) -> Result<(), String> {
+        let space = self.memory.len().saturating_sub(start_address as usize);
+        if rom.is_empty() {
+            return Err("the file is empty".to_string());
+        }
+        if rom.len() > space {
+            return Err(format!(
+                "it's {} bytes, only {} fit into {}K of memory from {:#05X}",
+                rom.len(),
+                space,
+                self.memory.len() / 1024,
+                start_address
+            ));
+        }
+        self.load_into_mem(rom, start_address);
+        Ok(())
     }
 
     pub fn load_font(&mut self) {
@@ -81,15 +152,72 @@ impl Chip8 {
         for (i, byte) in font.iter().enumerate() {
             self.memory[i] = *byte;
         }
+        self.memory_written(0, font.len());
+    }
+}
+
+/// Behaviors that differ between CHIP-8 interpreters, named like in the
+/// community chip-8-database. The defaults are how this emulator always
+/// behaved, `Platform::quirks` gives the ones of specific interpreters.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VX in place, instead of loading VY into VX first
+    pub shift: bool,
+    /// FX55/FX65 increment I by X, instead of X + 1
+    pub memory_increment_by_x: bool,
+    /// FX55/FX65 leave I unchanged
+    pub memory_leave_i_unchanged: bool,
+    /// sprites wrap around the edges of the screen, instead of being clipped
+    pub wrap: bool,
+    /// BNNN jumps to NNN + VX (X being the highest nibble of NNN), instead of NNN + V0
+    pub jump: bool,
+    /// DXYN waits for the vertical blank, so at most one sprite is drawn each frame
+    pub vblank: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub logic: bool,
+    /// FX0A waits for a key to be pressed and then released, instead of
+    /// finishing as soon as a key is pressed
+    pub key_wait_release: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift: true,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: true,
+            wrap: false,
+            jump: false,
+            vblank: false,
+            logic: false,
+            key_wait_release: true,
+        }
     }
 }
 
+/// State of an FX0A instruction waiting for a key. Only keys pressed after
+/// the wait started count, so a key still held from a previous prompt
+/// doesn't finish the next one right away.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyWait {
+    pub prev_key_down: [bool; 16],
+    pub pressed: Option<u8>,
+}
+
+/// A call that hasn't returned yet
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StackFrame {
+    /// address of the call, it returns to the instruction after it
+    pub caller: u16,
+    /// address that was called
+    pub target: u16,
+}
+
 pub struct Registers {
     pub general: [u8; 16], // general purpose registers
     pub dt: u8,            // delay timer
     pub st: u8,            // sound timer
     pub pc: u16,           // program counter
-    pub sp: u8,            // stack pointer
     pub i: u16,            // index register
 }
 
@@ -100,13 +228,12 @@ impl Registers {
             dt: 0,
             st: 0,
             pc: 0,
-            sp: 0,
             i: 0,
         }
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Instruction {
     Cls,
     Ret,
@@ -144,19 +271,57 @@ pub enum Instruction {
     LdMemRegs { end_reg: u8 },
 }
 
-pub fn fetch_instruction(registers: &mut Registers, memory: &[u8; 4096]) -> u16 {
-    let high_byte = memory[registers.pc as usize] as u16;
-    let low_byte = memory[registers.pc as usize + 1] as u16;
-    registers.pc += 2;
-    return high_byte << 8 | low_byte;
+impl Instruction {
+    /// The name of the variant, e.g. "Drw", which profiles count by
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::Cls => "Cls",
+            Instruction::Ret => "Ret",
+            Instruction::Jmp { .. } => "Jmp",
+            Instruction::Call { .. } => "Call",
+            Instruction::Se { .. } => "Se",
+            Instruction::Sne { .. } => "Sne",
+            Instruction::SeReg { .. } => "SeReg",
+            Instruction::Ld { .. } => "Ld",
+            Instruction::Add { .. } => "Add",
+            Instruction::LdReg { .. } => "LdReg",
+            Instruction::Or { .. } => "Or",
+            Instruction::And { .. } => "And",
+            Instruction::Xor { .. } => "Xor",
+            Instruction::AddReg { .. } => "AddReg",
+            Instruction::SubReg { .. } => "SubReg",
+            Instruction::Shr { .. } => "Shr",
+            Instruction::SubRegN { .. } => "SubRegN",
+            Instruction::Shl { .. } => "Shl",
+            Instruction::SneReg { .. } => "SneReg",
+            Instruction::Ldi { .. } => "Ldi",
+            Instruction::JmpV0 { .. } => "JmpV0",
+            Instruction::Rnd { .. } => "Rnd",
+            Instruction::Drw { .. } => "Drw",
+            Instruction::Skp { .. } => "Skp",
+            Instruction::SkpNp { .. } => "SkpNp",
+            Instruction::LdFromDt { .. } => "LdFromDt",
+            Instruction::LdKey { .. } => "LdKey",
+            Instruction::LdIntoDt { .. } => "LdIntoDt",
+            Instruction::LdSt { .. } => "LdSt",
+            Instruction::AddI { .. } => "AddI",
+            Instruction::LdF { .. } => "LdF",
+            Instruction::LdB { .. } => "LdB",
+            Instruction::LdRegsMem { .. } => "LdRegsMem",
+            Instruction::LdMemRegs { .. } => "LdMemRegs",
+        }
+    }
 }
 
-pub fn decode_instruction(instruction: u16) -> Instruction {
-    match get_nibble_u16(instruction, 3) {
+/// Decodes an instruction, fails for instructions that aren't part of CHIP-8
+/// (including 0NNN, which called machine code on the COSMAC VIP)
+pub fn decode_instruction(instruction: u16) -> Result<Instruction, String> {
+    let unknown = || Err(format!("Unknown instruction {:04X}", instruction));
+    let decoded = match get_nibble_u16(instruction, 3) {
         0x0 => match instruction {
             0x00E0 => Instruction::Cls,
             0x00EE => Instruction::Ret,
-            _ => panic!("Instruction {} not recognized", instruction),
+            _ => return unknown(),
         },
         0x1 => Instruction::Jmp {
             address: instruction & 0x0FFF,
@@ -172,7 +337,7 @@ pub fn decode_instruction(instruction: u16) -> Instruction {
             reg: get_nibble_u16(instruction, 2),
             val: (instruction & 0xFF) as u8,
         },
-        0x5 => Instruction::SeReg {
+        0x5 if get_nibble_u16(instruction, 0) == 0 => Instruction::SeReg {
             reg1: get_nibble_u16(instruction, 2),
             reg2: get_nibble_u16(instruction, 1),
         },
@@ -197,18 +362,18 @@ pub fn decode_instruction(instruction: u16) -> Instruction {
                 0x6 => Instruction::Shr { reg1, reg2 },
                 0x7 => Instruction::SubRegN { reg1, reg2 },
                 0xE => Instruction::Shl { reg1, reg2 },
-                unknown_op => panic!("Opcode {} not recognized for group 8", unknown_op),
+                _ => return unknown(),
             }
         }
-        0x9 => Instruction::SneReg {
+        0x9 if get_nibble_u16(instruction, 0) == 0 => Instruction::SneReg {
             reg1: get_nibble_u16(instruction, 2),
             reg2: get_nibble_u16(instruction, 1),
         },
         0xA => Instruction::Ldi {
-            address: (instruction & 0x0FFF) as u16,
+            address: instruction & 0x0FFF,
         },
         0xB => Instruction::JmpV0 {
-            address: (instruction & 0x0FFF) as u16,
+            address: instruction & 0x0FFF,
         },
         0xC => Instruction::Rnd {
             reg: get_nibble_u16(instruction, 2),
@@ -224,7 +389,7 @@ pub fn decode_instruction(instruction: u16) -> Instruction {
             match (instruction & 0xFF) as u8 {
                 0x9E => Instruction::Skp { reg },
                 0xA1 => Instruction::SkpNp { reg },
-                unknown_op => panic!("Opcode {} not recognized for group E", unknown_op),
+                _ => return unknown(),
             }
         }
         0xF => {
@@ -239,46 +404,58 @@ pub fn decode_instruction(instruction: u16) -> Instruction {
                 0x33 => Instruction::LdB { reg },
                 0x55 => Instruction::LdRegsMem { end_reg: reg },
                 0x65 => Instruction::LdMemRegs { end_reg: reg },
-                unknown_op => panic!("Opcode {} not recognized for group 8", unknown_op),
+                _ => return unknown(),
             }
         }
-        _ => panic!("Instruction {} not recognized", instruction),
-    }
+        _ => return unknown(),
+    };
+    Ok(decoded)
 }
 
-pub fn execute_instruction(ins: &Instruction, chip8: &mut Chip8) {
+/// Runs an instruction, the program counter has to point past it already.
+/// Fails when a call overflows the stack or a return has no call to return
+/// from, the machine is left as it was then.
+pub fn execute_instruction(ins: &Instruction, chip8: &mut Chip8) -> Result<(), String> {
     match ins {
-        Instruction::Cls => {
-            for byte in chip8.disp_buffer.iter_mut() {
-                *byte = 0;
-            }
-        }
+        Instruction::Cls => chip8.display.clear(),
         Instruction::Ret => {
-            let address = chip8.stack[chip8.regs.sp as usize];
-            chip8.regs.pc = address;
-            if chip8.regs.sp > 0 {
-                chip8.regs.sp -= 1;
-            }
+            let frame = chip8
+                .stack
+                .last()
+                .ok_or("Stack underflow, returned without a call")?;
+            chip8.regs.pc = frame
+                .caller
+                .checked_add(2)
+                .ok_or_else(|| end_of_memory(frame.caller as usize + 2))?;
+            chip8.stack.pop();
         }
         Instruction::Jmp { address } => chip8.regs.pc = *address,
         Instruction::Call { address } => {
-            chip8.regs.sp += 1;
-            chip8.stack[chip8.regs.sp as usize] = chip8.regs.pc;
+            if chip8.stack.len() >= chip8.stack_depth {
+                return Err(format!(
+                    "Stack overflow, more than {} nested calls",
+                    chip8.stack_depth
+                ));
+            }
+            chip8.stack.push(StackFrame {
+                caller: chip8.regs.pc.wrapping_sub(2),
+                target: *address,
+            });
             chip8.regs.pc = *address;
         }
         Instruction::Se { reg, val } => {
             if chip8.regs.general[*reg as usize] == *val {
-                chip8.regs.pc += 2;
+                skip(chip8)?;
             }
         }
         Instruction::Sne { reg, val } => {
             if chip8.regs.general[*reg as usize] != *val {
-                chip8.regs.pc += 2;
+                skip(chip8)?;
             }
         }
         Instruction::SeReg { reg1, reg2 } => {
             if chip8.regs.general[*reg1 as usize] == chip8.regs.general[*reg2 as usize] {
-                chip8.regs.pc += 2;
+                skip(chip8)?;
             }
         }
         Instruction::Ld { reg, val } => chip8.regs.general[*reg as usize] = *val,
@@ -293,62 +470,81 @@ pub fn execute_instruction(ins: &Instruction, chip8: &mut Chip8) {
         Instruction::Or { reg1, reg2 } => {
             let result = chip8.regs.general[*reg1 as usize] | chip8.regs.general[*reg2 as usize];
             chip8.regs.general[*reg1 as usize] = result;
+            if chip8.quirks.logic {
+                chip8.regs.general[0xF] = 0;
+            }
         }
         Instruction::And { reg1, reg2 } => {
             let result = chip8.regs.general[*reg1 as usize] & chip8.regs.general[*reg2 as usize];
             chip8.regs.general[*reg1 as usize] = result;
+            if chip8.quirks.logic {
+                chip8.regs.general[0xF] = 0;
+            }
         }
         Instruction::Xor { reg1, reg2 } => {
             let result = chip8.regs.general[*reg1 as usize] ^ chip8.regs.general[*reg2 as usize];
             chip8.regs.general[*reg1 as usize] = result;
+            if chip8.quirks.logic {
+                chip8.regs.general[0xF] = 0;
+            }
         }
         Instruction::AddReg { reg1, reg2 } => {
             let reg1_val = chip8.regs.general[*reg1 as usize] as u32;
             let reg2_val = chip8.regs.general[*reg2 as usize] as u32;
             let result = reg1_val + reg2_val;
-            chip8.regs.general[0xF] = if result > 0xFF { 0x1 } else { 0x0 };
             chip8.regs.general[*reg1 as usize] = result as u8;
+            // the flag is set last, so it wins when VF is the target
+            chip8.regs.general[0xF] = if result > 0xFF { 0x1 } else { 0x0 };
         }
         Instruction::SubReg { reg1, reg2 } => {
             let reg1_val = chip8.regs.general[*reg1 as usize] as i32;
             let reg2_val = chip8.regs.general[*reg2 as usize] as i32;
-            chip8.regs.general[0xF] = if reg1_val > reg2_val { 0x1 } else { 0x0 };
             let result = (reg1_val - reg2_val) as u8;
 
-            chip8.regs.general[*reg1 as usize] = result as u8;
+            chip8.regs.general[*reg1 as usize] = result;
+            chip8.regs.general[0xF] = if reg1_val >= reg2_val { 0x1 } else { 0x0 };
         }
-        Instruction::Shr { reg1, .. } => {
-            // TODO: CHIP-48 and SUPER-CHIP also do VX = VY first
+        Instruction::Shr { reg1, reg2 } => {
+            if !chip8.quirks.shift {
+                chip8.regs.general[*reg1 as usize] = chip8.regs.general[*reg2 as usize];
+            }
             let reg1_val = chip8.regs.general[*reg1 as usize];
-            chip8.regs.general[0xF] = reg1_val & 0x01;
             chip8.regs.general[*reg1 as usize] = reg1_val / 2;
+            chip8.regs.general[0xF] = reg1_val & 0x01;
         }
-        Instruction::Shl { reg1, .. } => {
-            // TODO: CHIP-48 and SUPER-CHIP also do VX = VY first
+        Instruction::Shl { reg1, reg2 } => {
+            if !chip8.quirks.shift {
+                chip8.regs.general[*reg1 as usize] = chip8.regs.general[*reg2 as usize];
+            }
             let reg1_val = chip8.regs.general[*reg1 as usize];
-            chip8.regs.general[0xF] = if reg1_val & 0x80 > 0 { 0x1 } else { 0x0 };
             chip8.regs.general[*reg1 as usize] = ((reg1_val as u16) * 2) as u8;
+            chip8.regs.general[0xF] = if reg1_val & 0x80 > 0 { 0x1 } else { 0x0 };
         }
         Instruction::SubRegN { reg1, reg2 } => {
             let reg1_val = chip8.regs.general[*reg1 as usize] as i32;
             let reg2_val = chip8.regs.general[*reg2 as usize] as i32;
-            chip8.regs.general[0xF] = if reg2_val > reg1_val { 0x1 } else { 0x0 };
             let result: u8 = (reg2_val - reg1_val) as u8;
 
-            chip8.regs.general[*reg1 as usize] = result as u8;
+            chip8.regs.general[*reg1 as usize] = result;
+            chip8.regs.general[0xF] = if reg2_val >= reg1_val { 0x1 } else { 0x0 };
         }
         Instruction::SneReg { reg1, reg2 } => {
             if chip8.regs.general[*reg1 as usize] != chip8.regs.general[*reg2 as usize] {
-                chip8.regs.pc += 2;
+                skip(chip8)?;
             }
         }
         Instruction::Ldi { address } => chip8.regs.i = *address,
         Instruction::JmpV0 { address } => {
-            chip8.regs.pc = chip8.regs.general[0] as u16 + *address;
+            let reg = if chip8.quirks.jump {
+                get_nibble_u16(*address, 2) as usize
+            } else {
+                0
+            };
+            chip8.regs.pc = chip8.regs.general[reg] as u16 + *address;
         }
         Instruction::Rnd { reg, mask } => {
-            let rnd_val = fastrand::u8(..);
-            let result = rnd_val as u8 & *mask;
+            let rnd_val = chip8.rng.u8(..);
+            let result = rnd_val & *mask;
             chip8.regs.general[*reg as usize] = result;
         }
         Instruction::Drw {
@@ -358,42 +554,34 @@ pub fn execute_instruction(ins: &Instruction, chip8: &mut Chip8) {
         } => {
             let x = chip8.regs.general[*reg1 as usize] % SCREEN_WIDTH;
             let y = chip8.regs.general[*reg2 as usize] % SCREEN_HEIGHT;
-            chip8.regs.general[0xF] = 0;
-
-            for row in 0..*n_bytes as usize {
-                let sprite_row = chip8.memory[chip8.regs.i as usize + row];
-                let cy = y + row as u8;
-                if cy >= SCREEN_HEIGHT {
-                    break;
-                };
-                for bit_i in 0..8 {
-                    let cx = x + bit_i;
-                    if cx >= SCREEN_WIDTH {
-                        break;
-                    }
-                    let sprite_bit = if sprite_row & 0x80 >> bit_i > 0 {
-                        0x1
-                    } else {
-                        0x0
-                    };
-                    let disp_bit = chip8.get_px(cx, cy);
-                    if sprite_bit > 0 && disp_bit > 0 {
-                        chip8.regs.general[0xF] = 0x1;
-                    }
-                    chip8.set_px(cx, cy, disp_bit ^ sprite_bit);
-                }
+            let wrap = chip8.quirks.wrap;
+            let mut collision = false;
+            // only the rows that are drawn are read, the others are clipped
+            let rows = if wrap {
+                *n_bytes
+            } else {
+                (*n_bytes).min(SCREEN_HEIGHT - y)
+            };
+            let sprite = memory_at_i(chip8, rows as usize)?;
+
+            for (row, address) in sprite.enumerate() {
+                let sprite_row = chip8.memory[address];
+                let cy = (y + row as u8) % SCREEN_HEIGHT;
+                collision |= chip8.display.draw_row(x, cy, sprite_row, wrap);
             }
+            chip8.regs.general[0xF] = collision as u8;
+            chip8.vblank_wait = chip8.quirks.vblank;
         }
         Instruction::Skp { reg } => {
-            let key = chip8.regs.general[*reg as usize];
-            if chip8.key_down[key as usize] {
-                chip8.regs.pc += 2;
+            let key = key_in(chip8, *reg)?;
+            if chip8.key_down[key] {
+                skip(chip8)?;
             }
         }
         Instruction::SkpNp { reg } => {
-            let key = chip8.regs.general[*reg as usize];
-            if !chip8.key_down[key as usize] {
-                chip8.regs.pc += 2;
+            let key = key_in(chip8, *reg)?;
+            if !chip8.key_down[key] {
+                skip(chip8)?;
             }
         }
         Instruction::LdFromDt { reg } => {
@@ -403,54 +591,226 @@ pub fn execute_instruction(ins: &Instruction, chip8: &mut Chip8) {
             chip8.regs.dt = chip8.regs.general[*reg as usize];
         }
         Instruction::LdKey { reg } => {
-            let mut was_key_pressed = false;
-            for (key, is_down) in chip8.key_down.iter().enumerate() {
-                if *is_down {
-                    chip8.regs.general[*reg as usize] = key as u8;
-                    was_key_pressed = true;
-                    break;
-                }
+            let mut wait = chip8.key_wait.unwrap_or(KeyWait {
+                prev_key_down: chip8.key_down,
+                pressed: None,
+            });
+            if wait.pressed.is_none() {
+                wait.pressed = (0..16)
+                    .find(|key| chip8.key_down[*key] && !wait.prev_key_down[*key])
+                    .map(|key| key as u8);
             }
-            // repeat instruction if no key was pressed
-            if !was_key_pressed {
+            wait.prev_key_down = chip8.key_down;
+
+            let done = match wait.pressed {
+                Some(key) => !chip8.quirks.key_wait_release || !chip8.key_down[key as usize],
+                None => false,
+            };
+            if done {
+                chip8.regs.general[*reg as usize] = wait.pressed.unwrap();
+                chip8.key_wait = None;
+            } else {
+                // repeat the instruction until the key wait is done,
+                // the timers keep counting down in the meantime
+                chip8.key_wait = Some(wait);
                 chip8.regs.pc -= 2;
             }
         }
         Instruction::LdSt { reg } => chip8.regs.st = chip8.regs.general[*reg as usize],
-        Instruction::AddI { reg } => chip8.regs.i += chip8.regs.general[*reg as usize] as u16,
+        Instruction::AddI { reg } => {
+            let val = chip8.regs.general[*reg as usize];
+            chip8.regs.i = add_to_i(chip8.regs.i, val as u16)?;
+        }
         Instruction::LdF { reg } => chip8.regs.i = chip8.regs.general[*reg as usize] as u16 * 0x5,
         Instruction::LdB { reg } => {
             let val = chip8.regs.general[*reg as usize];
             let hundreds = val / 100;
             let tens = (val - hundreds * 100) / 10;
             let ones = val % 10;
-            let i = chip8.regs.i as usize;
-            chip8.memory[i] = hundreds;
-            chip8.memory[i + 1] = tens;
-            chip8.memory[i + 2] = ones;
+            let digits = memory_at_i(chip8, 3)?;
+            chip8.memory[digits.clone()].copy_from_slice(&[hundreds, tens, ones]);
+            chip8.memory_written(digits.start, digits.len());
         }
         Instruction::LdRegsMem { end_reg } => {
-            for reg in 0..=*end_reg as usize {
-                let addr = chip8.regs.i as usize + reg as usize;
-                chip8.memory[addr] = chip8.regs.general[reg];
-            }
+            let regs = memory_at_i(chip8, *end_reg as usize + 1)?;
+            let i = increment_i_after_mem(chip8, *end_reg)?;
+            chip8.memory[regs.clone()].copy_from_slice(&chip8.regs.general[..regs.len()]);
+            chip8.memory_written(regs.start, regs.len());
+            chip8.regs.i = i;
         }
         Instruction::LdMemRegs { end_reg } => {
-            for reg in 0..=*end_reg as usize {
-                let addr = chip8.regs.i as usize + reg as usize;
-                chip8.regs.general[reg] = chip8.memory[addr];
-            }
+            let regs = memory_at_i(chip8, *end_reg as usize + 1)?;
+            let i = increment_i_after_mem(chip8, *end_reg)?;
+            chip8.regs.general[..regs.len()].copy_from_slice(&chip8.memory[regs]);
+            chip8.regs.i = i;
         }
     }
+    Ok(())
+}
+
+/// Runs the instructions of one frame and counts down the timers, gives the
+/// number of instructions that were run. Stops before an instruction when
+/// `stop` says so for its address, the frame is cut short then and the
+/// timers are left as they are. Also gives whether it stopped.
+pub fn emulation_step_until(
+    chip8: &mut Chip8,
+    instructions_per_frame: u32,
+    mut stop: impl FnMut(u16) -> bool,
+) -> Result<(u32, bool), String> {
+    let (executed, stopped) = if chip8.jit.is_some() {
+        jit::run(chip8, instructions_per_frame, &mut stop)?
+    } else {
+        interpret(chip8, instructions_per_frame, &mut stop)?
+    };
+    if stopped {
+        return Ok((executed, true));
+    }
+    chip8.vblank_wait = false;
+    chip8.decrement_timers();
+    Ok((executed, false))
+}
+
+/// Runs up to `budget` instructions one at a time, like `jit::run`
+fn interpret(
+    chip8: &mut Chip8,
+    budget: u32,
+    stop: &mut impl FnMut(u16) -> bool,
+) -> Result<(u32, bool), String> {
+    let mut executed = 0;
+    for _ in 0..budget {
+        if stop(chip8.regs.pc) {
+            return Ok((executed, true));
+        }
+        step_instruction(chip8)?;
+        executed += 1;
+        // with the vblank quirk drawing a sprite ends the frame
+        if chip8.vblank_wait {
+            break;
+        }
+    }
+    Ok((executed, false))
+}
+
+/// Runs the instruction at the program counter
+pub fn step_instruction(chip8: &mut Chip8) -> Result<(), String> {
+    let pc = chip8.regs.pc;
+    let instruction = match chip8.instruction_cache.as_mut() {
+        Some(cache) if (pc as usize) + 1 < chip8.memory.len() => cache
+            .get(&chip8.memory, pc)
+            .map_err(|err| format!("{} at {:03X}", err, pc))?,
+        // the cache doesn't cover the end of memory, decode_at fails there
+        _ => decode_at(&chip8.memory, pc)?,
+    };
+    if let Some(profile) = chip8.profile.as_mut() {
+        profile.record(pc, &instruction);
+    }
+    chip8.regs.pc = next_pc(pc, &instruction)?;
+    execute_instruction(&instruction, chip8).map_err(|err| {
+        chip8.regs.pc = pc;
+        format!("{} at {:03X}", err, pc)
+    })
+}
+
+/// Decodes the instruction at the address, the errors say where it is
+pub fn decode_at(memory: &[u8], address: u16) -> Result<Instruction, String> {
+    let index = address as usize;
+    if index + 1 >= memory.len() {
+        return Err(end_of_memory(index));
+    }
+    let raw_instruction = (memory[index] as u16) << 8 | memory[index + 1] as u16;
+    decode_instruction(raw_instruction).map_err(|err| format!("{} at {:03X}", err, address))
+}
+
+fn end_of_memory(address: usize) -> String {
+    format!(
+        "program counter ran past the end of memory ({:03X})",
+        address
+    )
+}
+
+/// The program counter while the instruction at `pc` runs, the address of
+/// the next one. There is none after the end of 64K of memory, only jumps,
+/// calls and returns can be there since they don't go on to it. It wraps for
+/// them, calls still find their own address 2 before it.
+pub fn next_pc(pc: u16, instruction: &Instruction) -> Result<u16, String> {
+    match pc.checked_add(2) {
+        Some(next) => Ok(next),
+        None if matches!(
+            instruction,
+            Instruction::Jmp { .. }
+                | Instruction::JmpV0 { .. }
+                | Instruction::Call { .. }
+                | Instruction::Ret
+        ) =>
+        {
+            Ok(pc.wrapping_add(2))
+        }
+        None => Err(format!("{} at {:03X}", end_of_memory(pc as usize + 2), pc)),
+    }
+}
+
+/// Skips the next instruction, which fails when there is none
+fn skip(chip8: &mut Chip8) -> Result<(), String> {
+    let pc = chip8.regs.pc;
+    chip8.regs.pc = pc
+        .checked_add(2)
+        .ok_or_else(|| end_of_memory(pc as usize + 2))?;
+    Ok(())
+}
+
+/// The value of I after FX55/FX65 read or wrote the registers up to
+/// `end_reg`, which depends on the quirks
+fn increment_i_after_mem(chip8: &Chip8, end_reg: u8) -> Result<u16, String> {
+    if chip8.quirks.memory_leave_i_unchanged {
+        return Ok(chip8.regs.i);
+    }
+    let increment = if chip8.quirks.memory_increment_by_x {
+        end_reg as u16
+    } else {
+        end_reg as u16 + 1
+    };
+    add_to_i(chip8.regs.i, increment)
+}
+
+fn add_to_i(i: u16, val: u16) -> Result<u16, String> {
+    i.checked_add(val).ok_or(format!(
+        "I overflowed, {:03X} + {:02X} is past FFFF",
+        i, val
+    ))
+}
+
+/// The addresses of the `len` bytes of memory from I on, which fail when
+/// they don't all fit into memory
+fn memory_at_i(chip8: &Chip8, len: usize) -> Result<Range<usize>, String> {
+    let start = chip8.regs.i as usize;
+    if start + len > chip8.memory.len() {
+        return Err(format!(
+            "Memory access out of bounds, {} bytes from I = {:03X} run past the end of memory",
+            len, start
+        ));
+    }
+    Ok(start..start + len)
+}
+
+/// The CHIP-8 key in the register, there are only 16
+fn key_in(chip8: &Chip8, reg: u8) -> Result<usize, String> {
+    let key = chip8.regs.general[reg as usize];
+    if key > 0xF {
+        return Err(format!(
+            "Invalid key {:02X} in V{:X}, keys go from 0 to F",
+            key, reg
+        ));
+    }
+    Ok(key as usize)
 }
 
 /// Gets the i-th nibble (half-byte) from x
 /// # Example
-/// ```
+/// ```text
 /// get_nibble_u16(0xABCD, 0) == 0x0D
 /// ```
 fn get_nibble_u16(x: u16, i: u8) -> u8 {
-    return ((x >> i * 4) & 0xF) as u8;
+    ((x >> (i * 4)) & 0xF) as u8
 }
 
 #[cfg(test)]
@@ -608,40 +968,70 @@ mod tests {
         ];
 
         for (input, expected) in cases.iter() {
-            assert_eq!(decode_instruction(*input), *expected);
+            assert_eq!(decode_instruction(*input).as_ref(), Ok(expected));
         }
     }
 
+    #[test]
+    fn decode_unknown_instruction_fails() {
+        for input in [0x0000, 0x0123, 0x5121, 0x8128, 0x912F, 0xE1FF, 0xF1FF] {
+            assert!(decode_instruction(input).is_err());
+        }
+    }
+
+    #[test]
+    fn load_rom_works() {
+        let mut chip8 = Chip8::new();
+        assert!(chip8.load_rom(&[0x60, 0x05], 0x200).is_ok());
+        assert_eq!(chip8.memory[0x200..0x202], [0x60, 0x05]);
+
+        assert!(chip8.load_rom(&[], 0x200).is_err());
+        assert!(chip8.load_rom(&[0; 3584], 0x200).is_ok());
+        assert!(chip8.load_rom(&[0; 3585], 0x200).is_err());
+        assert!(chip8.load_rom(&[0; 2], 0x1000).is_err());
+
+        let mut chip8 = Chip8::with_memory_size(0x10000);
+        assert!(chip8.load_rom(&[0; 3585], 0x200).is_ok());
+        assert!(chip8.load_rom(&[0; 65025], 0x200).is_err());
+    }
+
     #[test]
     fn execute_cls_works() {
         let mut chip8 = Chip8::new();
-        for byte in chip8.disp_buffer.iter_mut() {
-            *byte = 0xFF;
+        for y in 0..SCREEN_HEIGHT {
+            chip8.display.draw_row(0, y, 0xFF, false);
         }
 
-        execute_instruction(&Instruction::Cls, &mut chip8);
+        execute_instruction(&Instruction::Cls, &mut chip8).unwrap();
 
-        for byte in chip8.disp_buffer.iter() {
-            assert_eq!(*byte, 0u8);
-        }
+        assert_eq!(chip8.display, Display::new());
     }
 
     #[test]
     fn execute_ret_works() {
         let mut chip8 = Chip8::new();
-        chip8.regs.sp = 0;
-        chip8.stack[0] = 0x0ABC;
+        chip8.stack.push(StackFrame {
+            caller: 0x0ABA,
+            target: 0x300,
+        });
+
+        execute_instruction(&Instruction::Ret, &mut chip8).unwrap();
+        assert_eq!(chip8.regs.pc, 0xABC);
+        assert!(chip8.stack.is_empty());
 
-        execute_instruction(&Instruction::Ret, &mut chip8);
+        // nothing to return to
+        assert_eq!(
+            execute_instruction(&Instruction::Ret, &mut chip8),
+            Err("Stack underflow, returned without a call".to_string())
+        );
         assert_eq!(chip8.regs.pc, 0xABC);
-        assert_eq!(chip8.regs.sp, 0)
     }
 
     #[test]
     fn execute_jmp_works() {
         let mut chip8 = Chip8::new();
 
-        execute_instruction(&Instruction::Jmp { address: 0xABC }, &mut chip8);
+        execute_instruction(&Instruction::Jmp { address: 0xABC }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.pc, 0xABC);
     }
 
@@ -650,26 +1040,220 @@ mod tests {
         let mut chip8 = Chip8::new();
         chip8.regs.pc = 0x123;
 
-        execute_instruction(&Instruction::Call { address: 0xABC }, &mut chip8);
+        execute_instruction(&Instruction::Call { address: 0xABC }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.pc, 0xABC);
-        assert_eq!(chip8.regs.sp, 1);
-        assert_eq!(chip8.stack[1], 0x123);
+        assert_eq!(
+            chip8.stack,
+            [StackFrame {
+                caller: 0x121,
+                target: 0xABC
+            }]
+        );
+    }
+
+    #[test]
+    fn execute_call_overflow_fails() {
+        let mut chip8 = Chip8::new();
+        chip8.stack_depth = 12;
+        chip8.regs.pc = 0x200;
+        chip8.load_into_mem(&[0x22, 0x00], 0x200); // calls itself
+
+        for _ in 0..12 {
+            step_instruction(&mut chip8).unwrap();
+        }
+        assert_eq!(chip8.stack.len(), 12);
+        assert_eq!(
+            step_instruction(&mut chip8),
+            Err("Stack overflow, more than 12 nested calls at 200".to_string())
+        );
+        // left before the call, for the debugger to show
+        assert_eq!(chip8.regs.pc, 0x200);
+        assert_eq!(chip8.stack.len(), 12);
+    }
+
+    #[test]
+    fn running_past_the_end_of_64k_fails() {
+        let past_the_end = |address| {
+            format!(
+                "program counter ran past the end of memory (10000) at {:03X}",
+                address
+            )
+        };
+        // the start, the last 4 bytes of memory, how many instructions run,
+        // the error and where the program counter is left
+        let cases = [
+            // falls off the end
+            (
+                0xFFFE,
+                [0x00, 0x00, 0x60, 0x01],
+                1,
+                Some(past_the_end(0xFFFE)),
+                0xFFFE,
+            ),
+            // skips past the end
+            (
+                0xFFFC,
+                [0x30, 0x00, 0x60, 0x01],
+                1,
+                Some(past_the_end(0xFFFC)),
+                0xFFFC,
+            ),
+            // jumps back from the end
+            (0xFFFE, [0x00, 0x00, 0x13, 0x00], 1, None, 0x300),
+            // calls from the end and returns past it
+            (
+                0xFFFE,
+                [0x00, 0x00, 0x23, 0x00],
+                2,
+                Some(past_the_end(0x300)),
+                0x300,
+            ),
+        ];
+        for engine in ["cache", "no cache", "jit"] {
+            for (start, end, budget, error, pc) in &cases {
+                let mut chip8 = Chip8::with_memory_size(0x10000);
+                chip8.memory[0xFFFC..].copy_from_slice(end);
+                chip8.memory[0x300..0x302].copy_from_slice(&[0x00, 0xEE]);
+                chip8.regs.pc = *start;
+                match engine {
+                    "no cache" => chip8.instruction_cache = None,
+                    "jit" => chip8.jit = Some(Jit::new(0x10000)),
+                    _ => {}
+                }
+                let result = emulation_step_until(&mut chip8, *budget, |_| false);
+                assert_eq!(result.err(), *error, "{} from {:03X}", engine, start);
+                assert_eq!(chip8.regs.pc, *pc, "{} from {:03X}", engine, start);
+                assert_eq!(chip8.regs.general[0], 0);
+            }
+        }
+    }
+
+    #[test]
+    fn execute_memory_access_out_of_bounds_fails() {
+        let cases = [
+            (
+                Instruction::Drw {
+                    reg1: 0,
+                    reg2: 0,
+                    n_bytes: 5,
+                },
+                "5 bytes from I = FFE",
+            ),
+            (Instruction::LdB { reg: 0 }, "3 bytes from I = FFE"),
+            (
+                Instruction::LdRegsMem { end_reg: 4 },
+                "5 bytes from I = FFE",
+            ),
+            (
+                Instruction::LdMemRegs { end_reg: 0xF },
+                "16 bytes from I = FFE",
+            ),
+        ];
+        for (ins, bytes) in cases {
+            let mut chip8 = Chip8::new();
+            chip8.regs.i = 0xFFE;
+            chip8.regs.general[0] = 0xAB;
+            assert_eq!(
+                execute_instruction(&ins, &mut chip8),
+                Err(format!(
+                    "Memory access out of bounds, {} run past the end of memory",
+                    bytes
+                ))
+            );
+            // nothing was changed
+            assert_eq!(chip8.regs.i, 0xFFE);
+            assert_eq!(chip8.regs.general[0], 0xAB);
+            assert!(chip8.memory[0xFFE..].iter().all(|byte| *byte == 0));
+        }
+
+        // up to the last byte is fine
+        let mut chip8 = Chip8::new();
+        chip8.quirks.memory_leave_i_unchanged = false;
+        chip8.regs.i = 0xFFE;
+        execute_instruction(&Instruction::LdRegsMem { end_reg: 1 }, &mut chip8).unwrap();
+        assert_eq!(chip8.regs.i, 0x1000);
+        chip8.regs.i = 0xFFD;
+        execute_instruction(&Instruction::LdB { reg: 0 }, &mut chip8).unwrap();
+    }
+
+    #[test]
+    fn execute_drw_only_reads_drawn_rows() {
+        let drw = Instruction::Drw {
+            reg1: 0,
+            reg2: 1,
+            n_bytes: 5,
+        };
+        // two rows are left above the bottom edge, and two bytes of memory
+        let mut chip8 = Chip8::new();
+        chip8.quirks.wrap = false;
+        chip8.regs.i = 0xFFE;
+        chip8.regs.general[1] = 30;
+        chip8.memory[0xFFE] = 0x80;
+        chip8.memory[0xFFF] = 0x80;
+        execute_instruction(&drw, &mut chip8).unwrap();
+        assert_eq!(chip8.get_px(0, 30), 1);
+        assert_eq!(chip8.get_px(0, 31), 1);
+
+        // wrapping around draws all of them
+        chip8.quirks.wrap = true;
+        assert_eq!(
+            execute_instruction(&drw, &mut chip8),
+            Err(
+                "Memory access out of bounds, 5 bytes from I = FFE run past the end of memory"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn execute_i_overflow_fails() {
+        let mut chip8 = Chip8::new();
+        chip8.regs.i = 0xFFF0;
+        chip8.regs.general[0] = 0x20;
+        assert_eq!(
+            execute_instruction(&Instruction::AddI { reg: 0 }, &mut chip8),
+            Err("I overflowed, FFF0 + 20 is past FFFF".to_string())
+        );
+        assert_eq!(chip8.regs.i, 0xFFF0);
+
+        // the last bytes of 64K of memory can be written, I can't go past them
+        let mut chip8 = Chip8::with_memory_size(0x10000);
+        chip8.quirks.memory_leave_i_unchanged = false;
+        chip8.regs.i = 0xFFFE;
+        assert_eq!(
+            execute_instruction(&Instruction::LdRegsMem { end_reg: 1 }, &mut chip8),
+            Err("I overflowed, FFFE + 02 is past FFFF".to_string())
+        );
+        chip8.quirks.memory_leave_i_unchanged = true;
+        execute_instruction(&Instruction::LdRegsMem { end_reg: 1 }, &mut chip8).unwrap();
+    }
+
+    #[test]
+    fn execute_invalid_key_fails() {
+        let mut chip8 = Chip8::new();
+        chip8.regs.general[3] = 0x10;
+        for ins in [Instruction::Skp { reg: 3 }, Instruction::SkpNp { reg: 3 }] {
+            assert_eq!(
+                execute_instruction(&ins, &mut chip8),
+                Err("Invalid key 10 in V3, keys go from 0 to F".to_string())
+            );
+        }
     }
 
     #[test]
     fn execute_se_and_sne_works() {
         let mut chip8 = Chip8::new();
 
-        execute_instruction(&&Instruction::Se { reg: 0x0, val: 0x0 }, &mut chip8);
+        execute_instruction(&Instruction::Se { reg: 0x0, val: 0x0 }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.pc, 0x2);
 
-        execute_instruction(&&Instruction::Se { reg: 0x0, val: 0x1 }, &mut chip8);
+        execute_instruction(&Instruction::Se { reg: 0x0, val: 0x1 }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.pc, 0x2);
 
-        execute_instruction(&&Instruction::Sne { reg: 0x0, val: 0x1 }, &mut chip8);
+        execute_instruction(&Instruction::Sne { reg: 0x0, val: 0x1 }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.pc, 0x4);
 
-        execute_instruction(&&Instruction::Sne { reg: 0x0, val: 0x0 }, &mut chip8);
+        execute_instruction(&Instruction::Sne { reg: 0x0, val: 0x0 }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.pc, 0x4);
     }
 
@@ -681,39 +1265,43 @@ mod tests {
         chip8.regs.general[2] = 0xA;
 
         execute_instruction(
-            &&Instruction::SeReg {
+            &Instruction::SeReg {
                 reg1: 0x0,
                 reg2: 0x2,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.pc, 0x2);
 
         execute_instruction(
-            &&Instruction::SeReg {
+            &Instruction::SeReg {
                 reg1: 0x0,
                 reg2: 0x1,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.pc, 0x2);
 
         execute_instruction(
-            &&Instruction::SneReg {
+            &Instruction::SneReg {
                 reg1: 0x0,
                 reg2: 0x1,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.pc, 0x4);
 
         execute_instruction(
-            &&Instruction::SneReg {
+            &Instruction::SneReg {
                 reg1: 0x0,
                 reg2: 0x2,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.pc, 0x4);
     }
 
@@ -727,7 +1315,8 @@ mod tests {
                 val: 0x1F,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x1F);
     }
 
@@ -744,13 +1333,14 @@ mod tests {
                 val: 0x10,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x15);
 
         // overflow
         chip8.regs.general[0xA] = 0xFF;
 
-        execute_instruction(&Instruction::Add { reg: 0xA, val: 0x2 }, &mut chip8);
+        execute_instruction(&Instruction::Add { reg: 0xA, val: 0x2 }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x01);
     }
 
@@ -766,7 +1356,8 @@ mod tests {
                 reg2: 0xB,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x10);
     }
 
@@ -782,7 +1373,8 @@ mod tests {
                 reg2: 0xB,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0xCD);
     }
 
@@ -798,7 +1390,8 @@ mod tests {
                 reg2: 0xB,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x0A);
     }
 
@@ -814,7 +1407,8 @@ mod tests {
                 reg2: 0xB,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0xA5);
     }
 
@@ -832,7 +1426,8 @@ mod tests {
                 reg2: 0xB,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x16);
         assert_eq!(chip8.regs.general[0xF], 0x0);
 
@@ -846,7 +1441,8 @@ mod tests {
                 reg2: 0xB,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x01);
         assert_eq!(chip8.regs.general[0xF], 0x1);
     }
@@ -865,7 +1461,8 @@ mod tests {
                 reg2: 0xB,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x05);
         assert_eq!(chip8.regs.general[0xF], 0x1);
 
@@ -879,7 +1476,8 @@ mod tests {
                 reg2: 0xB,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0xFE);
         assert_eq!(chip8.regs.general[0xF], 0x0);
     }
@@ -898,7 +1496,8 @@ mod tests {
                 reg2: 0xB,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x05);
         assert_eq!(chip8.regs.general[0xF], 0x1);
 
@@ -912,11 +1511,141 @@ mod tests {
                 reg2: 0xB,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0xFE);
         assert_eq!(chip8.regs.general[0xF], 0x0);
     }
 
+    #[test]
+    fn execute_sub_equal_values_doesnt_borrow() {
+        let mut chip8 = Chip8::new();
+        for ins in [
+            Instruction::SubReg {
+                reg1: 0xA,
+                reg2: 0xB,
+            },
+            Instruction::SubRegN {
+                reg1: 0xA,
+                reg2: 0xB,
+            },
+        ] {
+            chip8.regs.general[0xA] = 0x03;
+            chip8.regs.general[0xB] = 0x03;
+            execute_instruction(&ins, &mut chip8).unwrap();
+            assert_eq!(chip8.regs.general[0xA], 0x00);
+            assert_eq!(chip8.regs.general[0xF], 0x1, "{:?}", ins);
+        }
+    }
+
+    #[test]
+    fn execute_flag_wins_over_result_in_vf() {
+        // VF as the target ends up holding the flag, not the result
+        let cases = [
+            (
+                Instruction::AddReg {
+                    reg1: 0xF,
+                    reg2: 0xB,
+                },
+                0x05,
+                0x03,
+                0x0,
+            ),
+            (
+                Instruction::AddReg {
+                    reg1: 0xF,
+                    reg2: 0xB,
+                },
+                0xFF,
+                0x03,
+                0x1,
+            ),
+            (
+                Instruction::SubReg {
+                    reg1: 0xF,
+                    reg2: 0xB,
+                },
+                0x05,
+                0x03,
+                0x1,
+            ),
+            (
+                Instruction::SubReg {
+                    reg1: 0xF,
+                    reg2: 0xB,
+                },
+                0x01,
+                0x03,
+                0x0,
+            ),
+            (
+                Instruction::SubRegN {
+                    reg1: 0xF,
+                    reg2: 0xB,
+                },
+                0x01,
+                0x03,
+                0x1,
+            ),
+            (
+                Instruction::SubRegN {
+                    reg1: 0xF,
+                    reg2: 0xB,
+                },
+                0x05,
+                0x03,
+                0x0,
+            ),
+            (
+                Instruction::Shr {
+                    reg1: 0xF,
+                    reg2: 0xF,
+                },
+                0x05,
+                0x00,
+                0x1,
+            ),
+            (
+                Instruction::Shr {
+                    reg1: 0xF,
+                    reg2: 0xF,
+                },
+                0x04,
+                0x00,
+                0x0,
+            ),
+            (
+                Instruction::Shl {
+                    reg1: 0xF,
+                    reg2: 0xF,
+                },
+                0x81,
+                0x00,
+                0x1,
+            ),
+            (
+                Instruction::Shl {
+                    reg1: 0xF,
+                    reg2: 0xF,
+                },
+                0x01,
+                0x00,
+                0x0,
+            ),
+        ];
+        for (ins, vf, vb, flag) in cases {
+            let mut chip8 = Chip8::new();
+            chip8.regs.general[0xF] = vf;
+            chip8.regs.general[0xB] = vb;
+            execute_instruction(&ins, &mut chip8).unwrap();
+            assert_eq!(
+                chip8.regs.general[0xF], flag,
+                "{:?} with VF {:02X}",
+                ins, vf
+            );
+        }
+    }
+
     #[test]
     fn execute_shr_works() {
         let mut chip8 = Chip8::new();
@@ -930,7 +1659,8 @@ mod tests {
                 reg2: 0x0,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x04);
         assert_eq!(chip8.regs.general[0xF], 0x0);
 
@@ -943,7 +1673,8 @@ mod tests {
                 reg2: 0x0,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x02);
         assert_eq!(chip8.regs.general[0xF], 0x1);
     }
@@ -961,7 +1692,8 @@ mod tests {
                 reg2: 0x0,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x08);
         assert_eq!(chip8.regs.general[0xF], 0x0);
 
@@ -974,16 +1706,69 @@ mod tests {
                 reg2: 0x0,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x2);
         assert_eq!(chip8.regs.general[0xF], 0x1);
     }
 
+    #[test]
+    fn execute_logic_quirk_works() {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.logic = true;
+        chip8.regs.general[0xA] = 0x0F;
+        chip8.regs.general[0xB] = 0xAA;
+        chip8.regs.general[0xF] = 0x1;
+
+        execute_instruction(
+            &Instruction::Or {
+                reg1: 0xA,
+                reg2: 0xB,
+            },
+            &mut chip8,
+        )
+        .unwrap();
+        assert_eq!(chip8.regs.general[0xA], 0xAF);
+        assert_eq!(chip8.regs.general[0xF], 0x0);
+    }
+
+    #[test]
+    fn execute_shift_quirk_works() {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.shift = false;
+
+        // VY is shifted into VX
+        chip8.regs.general[0xA] = 0x1;
+        chip8.regs.general[0xB] = 0x81;
+        execute_instruction(
+            &Instruction::Shl {
+                reg1: 0xA,
+                reg2: 0xB,
+            },
+            &mut chip8,
+        )
+        .unwrap();
+        assert_eq!(chip8.regs.general[0xA], 0x02);
+        assert_eq!(chip8.regs.general[0xB], 0x81);
+        assert_eq!(chip8.regs.general[0xF], 0x1);
+
+        execute_instruction(
+            &Instruction::Shr {
+                reg1: 0xA,
+                reg2: 0xB,
+            },
+            &mut chip8,
+        )
+        .unwrap();
+        assert_eq!(chip8.regs.general[0xA], 0x40);
+        assert_eq!(chip8.regs.general[0xF], 0x1);
+    }
+
     #[test]
     fn execute_ldi_works() {
         let mut chip8 = Chip8::new();
 
-        execute_instruction(&Instruction::Ldi { address: 0xABC }, &mut chip8);
+        execute_instruction(&Instruction::Ldi { address: 0xABC }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.i, 0xABC);
     }
 
@@ -992,15 +1777,26 @@ mod tests {
         let mut chip8 = Chip8::new();
         chip8.regs.general[0] = 0x5;
 
-        execute_instruction(&Instruction::JmpV0 { address: 0xABC }, &mut chip8);
+        execute_instruction(&Instruction::JmpV0 { address: 0xABC }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.pc, 0xAC1);
     }
 
+    #[test]
+    fn execute_jump_quirk_works() {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.jump = true;
+        chip8.regs.general[0] = 0x5;
+        chip8.regs.general[0xA] = 0x10;
+
+        execute_instruction(&Instruction::JmpV0 { address: 0xABC }, &mut chip8).unwrap();
+        assert_eq!(chip8.regs.pc, 0xACC);
+    }
+
     #[test]
     fn execute_rnd_works() {
         let mut chip8 = Chip8::new();
 
-        fastrand::seed(42);
+        chip8.rng = fastrand::Rng::with_seed(42);
         // get the first 2 random numbers
         // assert_eq!(fastrand::u8(..), 0x89);
         // assert_eq!(fastrand::u8(..), 0xC6);
@@ -1011,7 +1807,8 @@ mod tests {
                 mask: 0xFF,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xA], 0x89);
 
         execute_instruction(
@@ -1020,7 +1817,8 @@ mod tests {
                 mask: 0x0F,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         assert_eq!(chip8.regs.general[0xB], 0x06);
     }
 
@@ -1044,7 +1842,8 @@ mod tests {
                 n_bytes: 3,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
 
         chip8.dbg_print_display();
         for x in 10..18 {
@@ -1062,11 +1861,10 @@ mod tests {
                 n_bytes: 3,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         chip8.dbg_print_display();
-        for byte in chip8.disp_buffer.iter() {
-            assert_eq!(*byte, 0x0);
-        }
+        assert_eq!(chip8.display, Display::new());
         assert_eq!(chip8.regs.general[0xF], 0x1);
 
         // draw the sprite at x and y higher than screen coord, it should wrap
@@ -1079,7 +1877,8 @@ mod tests {
                 n_bytes: 3,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         chip8.dbg_print_display();
         for x in 10..18 {
             for y in 5..8 {
@@ -1098,7 +1897,8 @@ mod tests {
                 n_bytes: 3,
             },
             &mut chip8,
-        );
+        )
+        .unwrap();
         chip8.dbg_print_display();
         for x in 62..64 {
             for y in 30..32 {
@@ -1108,6 +1908,37 @@ mod tests {
         assert_eq!(chip8.regs.general[0xF], 0x0);
     }
 
+    #[test]
+    fn execute_drw_wrap_and_vblank_quirks_work() {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.wrap = true;
+        chip8.quirks.vblank = true;
+
+        chip8.regs.i = 0x200;
+        chip8.memory[0x200] = 0b11111111;
+        chip8.memory[0x201] = 0b11111111;
+        chip8.regs.general[0xA] = 60;
+        chip8.regs.general[0xB] = 31;
+
+        // draw the sprite in the corner, the rest of it wraps around
+        execute_instruction(
+            &Instruction::Drw {
+                reg1: 0xA,
+                reg2: 0xB,
+                n_bytes: 2,
+            },
+            &mut chip8,
+        )
+        .unwrap();
+        for x in [60, 61, 62, 63, 0, 1, 2, 3] {
+            for y in [31, 0] {
+                assert_eq!(chip8.get_px(x, y), 0x1);
+            }
+        }
+        assert_eq!(chip8.get_px(4, 0), 0x0);
+        assert!(chip8.vblank_wait);
+    }
+
     #[test]
     fn execute_skp_and_sknp_works() {
         let mut chip8 = Chip8::new();
@@ -1116,19 +1947,19 @@ mod tests {
         chip8.key_down[0x6] = true;
 
         // SKP: no skip, key is not down
-        execute_instruction(&Instruction::Skp { reg: 0xA }, &mut chip8);
+        execute_instruction(&Instruction::Skp { reg: 0xA }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.pc, 0x0);
 
         // SKP: skip, key is down
-        execute_instruction(&Instruction::Skp { reg: 0xB }, &mut chip8);
+        execute_instruction(&Instruction::Skp { reg: 0xB }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.pc, 0x2);
 
         // SKNP: no skip, key is down
-        execute_instruction(&Instruction::SkpNp { reg: 0xA }, &mut chip8);
+        execute_instruction(&Instruction::SkpNp { reg: 0xA }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.pc, 0x4);
 
         // SKNP: skip, key is down
-        execute_instruction(&Instruction::SkpNp { reg: 0xB }, &mut chip8);
+        execute_instruction(&Instruction::SkpNp { reg: 0xB }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.pc, 0x4);
     }
 
@@ -1138,31 +1969,65 @@ mod tests {
         chip8.regs.dt = 0x12;
 
         // Ld from DT
-        execute_instruction(&Instruction::LdFromDt { reg: 0xA }, &mut chip8);
+        execute_instruction(&Instruction::LdFromDt { reg: 0xA }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.dt, 0x12);
 
         // Ld into DT
-        execute_instruction(&Instruction::LdFromDt { reg: 0xA }, &mut chip8);
+        execute_instruction(&Instruction::LdFromDt { reg: 0xA }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.dt, 0x12);
     }
 
     #[test]
     fn execute_ld_key_works() {
         let mut chip8 = Chip8::new();
+        let ld_key = Instruction::LdKey { reg: 0xA };
 
-        // key 5 was down
+        // key 5 was already down when the wait started, it doesn't count
         chip8.key_down[5] = true;
-        execute_instruction(&Instruction::LdKey { reg: 0xA }, &mut chip8);
-        assert_eq!(chip8.regs.pc, 0x0); // no change to pc
-        assert_eq!(chip8.regs.general[0xA], 0x5);
+        chip8.regs.pc = 0x2; // simulate pc increment
+        execute_instruction(&ld_key, &mut chip8).unwrap();
+        assert_eq!(chip8.regs.pc, 0x0); // pc back 2 counts
+        assert!(chip8.key_wait.is_some());
 
-        // key 5 was down
+        // key 5 released, key 6 pressed
         chip8.key_down[5] = false;
-        chip8.regs.general[0xA] = 0;
-        chip8.regs.pc += 2; // simulate pc increment
-        execute_instruction(&Instruction::LdKey { reg: 0xA }, &mut chip8);
-        assert_eq!(chip8.regs.pc, 0x0); // pc back 2 counts
+        chip8.key_down[6] = true;
+        chip8.regs.pc += 2;
+        execute_instruction(&ld_key, &mut chip8).unwrap();
+        assert_eq!(chip8.regs.pc, 0x0); // still waiting for the release
         assert_eq!(chip8.regs.general[0xA], 0x0);
+
+        // key 6 released
+        chip8.key_down[6] = false;
+        chip8.regs.pc += 2;
+        execute_instruction(&ld_key, &mut chip8).unwrap();
+        assert_eq!(chip8.regs.pc, 0x2); // no change to pc
+        assert_eq!(chip8.regs.general[0xA], 0x6);
+        assert_eq!(chip8.key_wait, None);
+    }
+
+    #[test]
+    fn execute_ld_key_without_release_works() {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.key_wait_release = false;
+        let ld_key = Instruction::LdKey { reg: 0xA };
+
+        // no key down
+        chip8.regs.pc = 0x2;
+        execute_instruction(&ld_key, &mut chip8).unwrap();
+        assert_eq!(chip8.regs.pc, 0x0);
+
+        // finishes as soon as key 3 is pressed
+        chip8.key_down[3] = true;
+        chip8.regs.pc += 2;
+        execute_instruction(&ld_key, &mut chip8).unwrap();
+        assert_eq!(chip8.regs.pc, 0x2);
+        assert_eq!(chip8.regs.general[0xA], 0x3);
+        assert_eq!(chip8.key_wait, None);
+
+        // key 3 still held for the next wait, it doesn't count
+        execute_instruction(&ld_key, &mut chip8).unwrap();
+        assert_eq!(chip8.regs.pc, 0x0);
     }
 
     #[test]
@@ -1170,7 +2035,7 @@ mod tests {
         let mut chip8 = Chip8::new();
         chip8.regs.general[0xA] = 0x12;
 
-        execute_instruction(&Instruction::LdSt { reg: 0xA }, &mut chip8);
+        execute_instruction(&Instruction::LdSt { reg: 0xA }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.st, 0x12);
     }
 
@@ -1180,7 +2045,7 @@ mod tests {
         chip8.regs.i = 0x05;
         chip8.regs.general[0xA] = 0x12;
 
-        execute_instruction(&Instruction::AddI { reg: 0xA }, &mut chip8);
+        execute_instruction(&Instruction::AddI { reg: 0xA }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.i, 0x17);
     }
 
@@ -1189,7 +2054,7 @@ mod tests {
         let mut chip8 = Chip8::new();
         chip8.regs.general[0xA] = 0x12;
 
-        execute_instruction(&Instruction::LdF { reg: 0xA }, &mut chip8);
+        execute_instruction(&Instruction::LdF { reg: 0xA }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.i, 0x5A);
     }
 
@@ -1200,7 +2065,7 @@ mod tests {
         chip8.regs.i = 0x300;
 
         // breaks the decimal into digits and stores them at consecutive addresses
-        execute_instruction(&Instruction::LdB { reg: 0xA }, &mut chip8);
+        execute_instruction(&Instruction::LdB { reg: 0xA }, &mut chip8).unwrap();
         assert_eq!(chip8.memory[0x300], 2);
         assert_eq!(chip8.memory[0x301], 3);
         assert_eq!(chip8.memory[0x302], 4);
@@ -1215,11 +2080,26 @@ mod tests {
         chip8.regs.general[0x3] = 0xD;
         chip8.regs.i = 0x300;
 
-        execute_instruction(&Instruction::LdRegsMem { end_reg: 0x2 }, &mut chip8);
+        execute_instruction(&Instruction::LdRegsMem { end_reg: 0x2 }, &mut chip8).unwrap();
         assert_eq!(chip8.memory[0x300], 0xA);
         assert_eq!(chip8.memory[0x301], 0xB);
         assert_eq!(chip8.memory[0x302], 0xC);
         assert_eq!(chip8.memory[0x303], 0x0); // load only up to 0x2 reg
+        assert_eq!(chip8.regs.i, 0x300);
+    }
+
+    #[test]
+    fn execute_ld_regs_mem_increments_i() {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.memory_leave_i_unchanged = false;
+        chip8.regs.i = 0x300;
+
+        execute_instruction(&Instruction::LdRegsMem { end_reg: 0x2 }, &mut chip8).unwrap();
+        assert_eq!(chip8.regs.i, 0x303);
+
+        chip8.quirks.memory_increment_by_x = true;
+        execute_instruction(&Instruction::LdMemRegs { end_reg: 0x2 }, &mut chip8).unwrap();
+        assert_eq!(chip8.regs.i, 0x305);
     }
 
     #[test]
@@ -1231,7 +2111,7 @@ mod tests {
         chip8.memory[0x302] = 0xC;
         chip8.memory[0x303] = 0xD;
 
-        execute_instruction(&Instruction::LdMemRegs { end_reg: 0x2 }, &mut chip8);
+        execute_instruction(&Instruction::LdMemRegs { end_reg: 0x2 }, &mut chip8).unwrap();
         assert_eq!(chip8.regs.general[0x0], 0xA);
         assert_eq!(chip8.regs.general[0x1], 0xB);
         assert_eq!(chip8.regs.general[0x2], 0xC);
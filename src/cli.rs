@@ -0,0 +1,512 @@
+use std::slice::Iter;
+
+use crate::config::QuirkOverrides;
+
+pub const USAGE: &str = "Usage: chip8 [run] [options] [<path-to-rom>]
+       chip8 tui [options] <path-to-rom>
+       chip8 headless [options] [--frames <n>] <path-to-rom>
+       chip8 bench [options] [--frames <n> | --seconds <n>] <path-to-rom>
+       chip8 profile [options] [--frames <n>] [--output <path>] <path-to-rom>
+       chip8 disasm [--start-address <address>] [--symbols <path>] <path-to-rom>
+       chip8 asm [--start-address <address>] [--output <path>] <path-to-source>
+       chip8 info <path-to-rom>
+       chip8 config dump [options] [<path-to-rom>]
+       chip8 --help | --version";
+
+pub const HELP: &str = "Commands:
+  run            run a ROM in a window, the default, without a ROM the window waits
+                 for one to be dropped on it
+  tui            run a ROM in the terminal, for when there's no display, e.g. over SSH
+  headless       run a ROM without a window and print the display at the end
+  bench          run a ROM as fast as possible and print the speed
+  profile        run a ROM without a window and report which instructions ran and how
+                 often: a listing, a JSON report and a heat map of memory
+  disasm         list the instructions of a ROM
+  asm            assemble a program into a ROM, next to the source by default, and its
+                 labels into a symbol file next to the ROM
+  info           print the size and hash of a ROM, and what the ROM database knows about it
+  config dump    print the settings a ROM is run with, or the global ones
+
+Options:
+  --platform <id>            originalChip8, modernChip8, superchip or xochip
+  --ips <n>                  instructions executed per second
+  --stack-depth <n>          calls that can be nested, 12 on the COSMAC VIP, 16 by default
+  --quirks <list>            quirks to turn on or off, e.g. \"logic,wrap=false\"
+  --seed <n>                 seed for the random numbers, for reproducible runs
+  --start-address <address>  where the ROM is loaded and run from, 0x200 by default
+  --engine <name>            interpreter, or jit to compile the ROM's code as it runs
+  --palette <name>           palette to start with
+  --filter <name>            render filter to start with
+  --keymap <name>            keymap to use
+  --scale <n>                initial window size, as a multiple of 64x32
+  --frames <n>               frames to run for headless, bench and profile, 600 by default
+  --seconds <n>              how long bench runs for, instead of a number of frames
+  --output <path>            where asm writes the ROM, or where profile writes its
+                             reports, without the extensions
+  --symbols <path>           symbol file naming the ROM's addresses, <rom>.sym by default
+  -h, --help                 print this help
+  -V, --version              print the version
+
+Exit codes:
+  1  emulation error, e.g. an unknown instruction
+  2  invalid arguments or config
+  3  file not found or unreadable
+  4  invalid ROM, assembly source or symbol file";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// run a ROM, or wait for one to be dropped on the window
+    Run {
+        rom_path: Option<String>,
+        options: Options,
+    },
+    /// run a ROM in the terminal
+    Tui {
+        rom_path: String,
+        options: Options,
+    },
+    /// run a ROM without a window for some frames
+    Headless {
+        rom_path: String,
+        options: Options,
+    },
+    /// run a ROM as fast as possible for some frames
+    Bench {
+        rom_path: String,
+        options: Options,
+    },
+    /// run a ROM without a window and report which instructions ran
+    Profile {
+        rom_path: String,
+        options: Options,
+    },
+    /// list the instructions of a ROM
+    Disasm {
+        rom_path: String,
+        options: Options,
+    },
+    /// assemble a program
+    Asm {
+        source_path: String,
+        options: Options,
+    },
+    /// print what the ROM database knows about a ROM
+    Info {
+        rom_path: String,
+    },
+    /// print the settings a ROM would be run with, or the global ones
+    ConfigDump {
+        rom_path: Option<String>,
+        options: Options,
+    },
+    Help,
+    Version,
+}
+
+/// Settings given on the command line, they take precedence over the config file
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Options {
+    pub platform: Option<String>,
+    pub ips: Option<u32>,
+    pub stack_depth: Option<usize>,
+    pub quirks: QuirkOverrides,
+    pub seed: Option<u64>,
+    pub start_address: Option<u16>,
+    pub engine: Option<String>,
+    pub palette: Option<String>,
+    pub filter: Option<String>,
+    pub keymap: Option<String>,
+    pub scale: Option<u32>,
+    pub frames: Option<u32>,
+    pub seconds: Option<u32>,
+    pub output: Option<String>,
+    pub symbols: Option<String>,
+}
+
+/// Parses the command line arguments, without the program name
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        return Ok(Command::Help);
+    }
+    if args.iter().any(|arg| arg == "-V" || arg == "--version") {
+        return Ok(Command::Version);
+    }
+
+    let (command, args) = match args.first().map(|arg| arg.as_str()) {
+        Some("config") => match args.get(1).map(|arg| arg.as_str()) {
+            Some("dump") => ("config dump", &args[2..]),
+            Some(unexpected) => return Err(format!("Unknown config command {}", unexpected)),
+            None => return Err("Missing config command, available commands are: dump".to_string()),
+        },
+        Some(
+            command
+            @ ("run" | "tui" | "headless" | "bench" | "profile" | "disasm" | "asm" | "info"),
+        ) => (command, &args[1..]),
+        _ => ("run", args),
+    };
+    let (path, options) = parse_options(args)?;
+
+    // options only one command uses are rejected for the others, rather
+    // than silently ignored
+    if options.frames.is_some() && !matches!(command, "headless" | "bench" | "profile") {
+        return Err(format!("--frames can't be used with {}", command));
+    }
+    if options.seconds.is_some() && command != "bench" {
+        return Err(format!("--seconds can't be used with {}", command));
+    }
+    if options.frames.is_some() && options.seconds.is_some() {
+        return Err("--frames and --seconds can't be used together".to_string());
+    }
+    if options.output.is_some() && !matches!(command, "asm" | "profile") {
+        return Err(format!("--output can't be used with {}", command));
+    }
+    if options.symbols.is_some() && matches!(command, "asm" | "config dump") {
+        return Err(format!("--symbols can't be used with {}", command));
+    }
+    if command == "info" && options != Options::default() {
+        return Err("info doesn't take any options".to_string());
+    }
+
+    match command {
+        "config dump" => {
+            return Ok(Command::ConfigDump {
+                rom_path: path,
+                options,
+            })
+        }
+        "run" => {
+            return Ok(Command::Run {
+                rom_path: path,
+                options,
+            })
+        }
+        _ => {}
+    }
+    let path = match path {
+        Some(path) => path,
+        None if command == "asm" => return Err("No source path provided.".to_string()),
+        None => return Err("No ROM path provided.".to_string()),
+    };
+    Ok(match command {
+        "tui" => Command::Tui {
+            rom_path: path,
+            options,
+        },
+        "headless" => Command::Headless {
+            rom_path: path,
+            options,
+        },
+        "bench" => Command::Bench {
+            rom_path: path,
+            options,
+        },
+        "profile" => Command::Profile {
+            rom_path: path,
+            options,
+        },
+        "disasm" => Command::Disasm {
+            rom_path: path,
+            options,
+        },
+        "asm" => Command::Asm {
+            source_path: path,
+            options,
+        },
+        _ => Command::Info { rom_path: path },
+    })
+}
+
+/// Parses the options and the optional path of the ROM (or source)
+fn parse_options(args: &[String]) -> Result<(Option<String>, Options), String> {
+    let mut path = None;
+    let mut options = Options::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let flag = arg.as_str();
+        match flag {
+            "--platform" => options.platform = Some(next_value(&mut args, flag)?.clone()),
+            "--ips" => options.ips = Some(next_number(&mut args, flag, 1, u32::MAX as u64)? as u32),
+            "--stack-depth" => {
+                options.stack_depth = Some(next_number(&mut args, flag, 1, 256)? as usize)
+            }
+            "--quirks" => {
+                options.quirks = QuirkOverrides::parse_list(next_value(&mut args, flag)?)?
+            }
+            "--seed" => options.seed = Some(next_number(&mut args, flag, 0, u64::MAX)?),
+            "--start-address" => {
+                options.start_address = Some(next_number(&mut args, flag, 0, 0xFFF)? as u16)
+            }
+            "--engine" => options.engine = Some(next_value(&mut args, flag)?.clone()),
+            "--palette" => options.palette = Some(next_value(&mut args, flag)?.clone()),
+            "--filter" => options.filter = Some(next_value(&mut args, flag)?.clone()),
+            "--keymap" => options.keymap = Some(next_value(&mut args, flag)?.clone()),
+            "--scale" => options.scale = Some(next_number(&mut args, flag, 1, 64)? as u32),
+            "--frames" => {
+                options.frames = Some(next_number(&mut args, flag, 1, u32::MAX as u64)? as u32)
+            }
+            "--seconds" => options.seconds = Some(next_number(&mut args, flag, 1, 3600)? as u32),
+            "--output" => options.output = Some(next_value(&mut args, flag)?.clone()),
+            "--symbols" => options.symbols = Some(next_value(&mut args, flag)?.clone()),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            path_arg => {
+                if path.is_some() {
+                    return Err(format!("Unexpected argument {}", path_arg));
+                }
+                path = Some(path_arg.to_string());
+            }
+        }
+    }
+
+    Ok((path, options))
+}
+
+fn next_value<'a>(args: &mut Iter<'a, String>, flag: &str) -> Result<&'a String, String> {
+    args.next()
+        .ok_or_else(|| format!("Missing value after {}", flag))
+}
+
+/// Parses the next argument as a decimal or hexadecimal ("0x") number
+/// between `min` and `max`
+fn next_number(args: &mut Iter<String>, flag: &str, min: u64, max: u64) -> Result<u64, String> {
+    let value = next_value(args, flag)?;
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    match parsed {
+        Ok(number) if (min..=max).contains(&number) => Ok(number),
+        _ => Err(format!(
+            "Invalid value \"{}\" for {}, expected a number from {} to {}",
+            value, flag, min, max
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_args_works() {
+        assert_eq!(
+            parse_args(&args(&["pong.ch8"])),
+            Ok(Command::Run {
+                rom_path: Some("pong.ch8".to_string()),
+                options: Options::default(),
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&[
+                "--palette",
+                "amber",
+                "pong.ch8",
+                "--filter",
+                "blend",
+                "--keymap",
+                "keypad"
+            ])),
+            Ok(Command::Run {
+                rom_path: Some("pong.ch8".to_string()),
+                options: Options {
+                    palette: Some("amber".to_string()),
+                    filter: Some("blend".to_string()),
+                    keymap: Some("keypad".to_string()),
+                    ..Options::default()
+                }
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&[])),
+            Ok(Command::Run {
+                rom_path: None,
+                options: Options::default(),
+            })
+        );
+        assert!(parse_args(&args(&["pong.ch8", "--palette"])).is_err());
+        assert!(parse_args(&args(&["--zoom", "4", "pong.ch8"])).is_err());
+        assert!(parse_args(&args(&["pong.ch8", "tetris.ch8"])).is_err());
+    }
+
+    #[test]
+    fn parse_emulation_options_works() {
+        assert_eq!(
+            parse_args(&args(&[
+                "run",
+                "--platform",
+                "superchip",
+                "--ips",
+                "1200",
+                "--stack-depth",
+                "12",
+                "--quirks",
+                "logic,wrap=false",
+                "--seed",
+                "42",
+                "--start-address",
+                "0x600",
+                "--engine",
+                "jit",
+                "--scale",
+                "4",
+                "pong.ch8"
+            ])),
+            Ok(Command::Run {
+                rom_path: Some("pong.ch8".to_string()),
+                options: Options {
+                    platform: Some("superchip".to_string()),
+                    ips: Some(1200),
+                    stack_depth: Some(12),
+                    quirks: QuirkOverrides {
+                        logic: Some(true),
+                        wrap: Some(false),
+                        ..QuirkOverrides::default()
+                    },
+                    seed: Some(42),
+                    start_address: Some(0x600),
+                    engine: Some("jit".to_string()),
+                    scale: Some(4),
+                    ..Options::default()
+                }
+            })
+        );
+        assert!(parse_args(&args(&["--ips", "0", "pong.ch8"])).is_err());
+        assert!(parse_args(&args(&["--ips", "fast", "pong.ch8"])).is_err());
+        assert!(parse_args(&args(&["--stack-depth", "0", "pong.ch8"])).is_err());
+        assert!(parse_args(&args(&["--start-address", "0x1000", "pong.ch8"])).is_err());
+        assert!(parse_args(&args(&["--quirks", "clip", "pong.ch8"])).is_err());
+    }
+
+    #[test]
+    fn parse_subcommands_works() {
+        assert_eq!(
+            parse_args(&args(&["headless", "--frames", "60", "pong.ch8"])),
+            Ok(Command::Headless {
+                rom_path: "pong.ch8".to_string(),
+                options: Options {
+                    frames: Some(60),
+                    ..Options::default()
+                }
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["tui", "--keymap", "arrows", "pong.ch8"])),
+            Ok(Command::Tui {
+                rom_path: "pong.ch8".to_string(),
+                options: Options {
+                    keymap: Some("arrows".to_string()),
+                    ..Options::default()
+                }
+            })
+        );
+        assert!(parse_args(&args(&["tui"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["bench", "pong.ch8"])),
+            Ok(Command::Bench {
+                rom_path: "pong.ch8".to_string(),
+                options: Options::default(),
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["bench", "--seconds", "5", "pong.ch8"])),
+            Ok(Command::Bench {
+                rom_path: "pong.ch8".to_string(),
+                options: Options {
+                    seconds: Some(5),
+                    ..Options::default()
+                }
+            })
+        );
+        assert!(parse_args(&args(&["bench", "--seconds", "5", "--frames", "60", "a"])).is_err());
+        assert!(parse_args(&args(&["headless", "--seconds", "5", "pong.ch8"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["disasm", "pong.ch8"])),
+            Ok(Command::Disasm {
+                rom_path: "pong.ch8".to_string(),
+                options: Options::default(),
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["asm", "pong.8o", "--output", "pong.ch8"])),
+            Ok(Command::Asm {
+                source_path: "pong.8o".to_string(),
+                options: Options {
+                    output: Some("pong.ch8".to_string()),
+                    ..Options::default()
+                }
+            })
+        );
+        assert!(parse_args(&args(&["asm"])).is_err());
+        assert_eq!(
+            parse_args(&args(&[
+                "profile", "--frames", "60", "--output", "out", "pong.ch8"
+            ])),
+            Ok(Command::Profile {
+                rom_path: "pong.ch8".to_string(),
+                options: Options {
+                    frames: Some(60),
+                    output: Some("out".to_string()),
+                    ..Options::default()
+                }
+            })
+        );
+        assert!(parse_args(&args(&["profile", "--seconds", "5", "pong.ch8"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["disasm", "--symbols", "pong.sym", "pong.ch8"])),
+            Ok(Command::Disasm {
+                rom_path: "pong.ch8".to_string(),
+                options: Options {
+                    symbols: Some("pong.sym".to_string()),
+                    ..Options::default()
+                }
+            })
+        );
+        assert!(parse_args(&args(&["asm", "--symbols", "pong.sym", "pong.8o"])).is_err());
+        assert!(parse_args(&args(&["run", "--frames", "60", "pong.ch8"])).is_err());
+        assert!(parse_args(&args(&["headless", "--output", "a", "pong.ch8"])).is_err());
+
+        assert_eq!(parse_args(&args(&["--help"])), Ok(Command::Help));
+        assert_eq!(parse_args(&args(&["bench", "-h"])), Ok(Command::Help));
+        assert_eq!(parse_args(&args(&["-V"])), Ok(Command::Version));
+    }
+
+    #[test]
+    fn parse_info_args_works() {
+        assert_eq!(
+            parse_args(&args(&["info", "pong.ch8"])),
+            Ok(Command::Info {
+                rom_path: "pong.ch8".to_string()
+            })
+        );
+        assert!(parse_args(&args(&["info"])).is_err());
+        assert!(parse_args(&args(&["info", "pong.ch8", "tetris.ch8"])).is_err());
+        assert!(parse_args(&args(&["info", "--ips", "60", "pong.ch8"])).is_err());
+    }
+
+    #[test]
+    fn parse_config_dump_args_works() {
+        assert_eq!(
+            parse_args(&args(&["config", "dump"])),
+            Ok(Command::ConfigDump {
+                rom_path: None,
+                options: Options::default(),
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["config", "dump", "--palette", "lcd", "pong.ch8"])),
+            Ok(Command::ConfigDump {
+                rom_path: Some("pong.ch8".to_string()),
+                options: Options {
+                    palette: Some("lcd".to_string()),
+                    ..Options::default()
+                }
+            })
+        );
+        assert!(parse_args(&args(&["config"])).is_err());
+        assert!(parse_args(&args(&["config", "edit"])).is_err());
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::chip8::Quirks;
+
+/// User configuration, read from `$XDG_CONFIG_HOME/chip8/config.toml`
+/// (`~/.config/chip8/config.toml` if `XDG_CONFIG_HOME` isn't set).
+/// # Example
+/// ```toml
+/// palette = "amber"
+/// filter = "phosphor"
+/// scale_mode = "fit"
+/// platform = "modernChip8"
+/// ips = 700
+/// stack_depth = 16
+/// engine = "jit"
+/// rom_dir = "/home/me/roms"
+///
+/// [palettes]
+/// gameboy = ["#9BBC0F", "#0F380F", "#306230", "#8BAC0F"]
+///
+/// [filters]
+/// phosphor_decay = 0.5
+///
+/// [keymaps.wasd]
+/// W = 0x5
+/// A = 0x7
+/// S = 0x8
+/// D = 0x9
+///
+/// [pad_mappings.vertical]
+/// dpup = 0x1
+/// dpdown = 0x4
+///
+/// [quirks]
+/// key_wait_release = false
+///
+/// [roms."tetris.ch8"]
+/// keymap = "wasd"
+/// pad_mapping = "vertical"
+/// quirks = { key_wait_release = true }
+///
+/// [roms.a9993e364706816aba3e25717850c26c9cd0d89d]
+/// palette = "lcd"
+/// platform = "superchip"
+/// ```
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// name of the palette to start with
+    pub palette: Option<String>,
+    /// custom palettes, name -> list of "#RRGGBB" colors
+    pub palettes: BTreeMap<String, Vec<String>>,
+    /// name of the render filter to start with
+    pub filter: Option<String>,
+    pub filters: FilterSettings,
+    /// initial window size, as a multiple of the display size
+    pub window_scale: u32,
+    /// "integer" or "fit"
+    pub scale_mode: Option<String>,
+    pub fullscreen: bool,
+    /// name of the keymap to use
+    pub keymap: Option<String>,
+    /// custom keymaps, name -> (scancode name -> CHIP-8 key)
+    pub keymaps: BTreeMap<String, BTreeMap<String, u8>>,
+    /// name of the gamepad mapping to use
+    pub pad_mapping: Option<String>,
+    /// custom gamepad mappings, name -> (SDL button name -> CHIP-8 key)
+    pub pad_mappings: BTreeMap<String, BTreeMap<String, u8>>,
+    /// how far (0.0 - 1.0) the left stick needs to be pushed to count as a direction
+    pub stick_threshold: f32,
+    /// platform whose quirks and speed are used, e.g. "originalChip8"
+    pub platform: Option<String>,
+    /// instructions executed per second
+    pub ips: Option<u32>,
+    /// calls that can be nested before the stack overflows
+    pub stack_depth: Option<usize>,
+    pub quirks: QuirkOverrides,
+    /// where ROMs are loaded and run from, 0x200 by default
+    pub start_address: Option<u16>,
+    /// "interpreter" or "jit"
+    pub engine: Option<String>,
+    /// path of a ROM database used instead of the built-in one,
+    /// `programs.json` next to the config file if it's there
+    pub database: Option<PathBuf>,
+    /// directory listed by the ROM browser
+    pub rom_dir: Option<PathBuf>,
+    /// per-ROM overrides, keyed by the ROM file name or its SHA-1 hash
+    pub roms: BTreeMap<String, RomConfig>,
+}
+
+/// Settings overridden for a single ROM
+#[derive(Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomConfig {
+    pub palette: Option<String>,
+    /// colors of a palette just for the ROM, used instead of `palette`
+    pub colors: Option<Vec<String>>,
+    pub filter: Option<String>,
+    pub keymap: Option<String>,
+    pub pad_mapping: Option<String>,
+    pub platform: Option<String>,
+    pub ips: Option<u32>,
+    pub stack_depth: Option<usize>,
+    pub quirks: QuirkOverrides,
+    pub start_address: Option<u16>,
+}
+
+/// Quirks set in the config file, the ones left out keep their value
+#[derive(Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuirkOverrides {
+    pub shift: Option<bool>,
+    pub memory_increment_by_x: Option<bool>,
+    pub memory_leave_i_unchanged: Option<bool>,
+    pub wrap: Option<bool>,
+    pub jump: Option<bool>,
+    pub vblank: Option<bool>,
+    pub logic: Option<bool>,
+    pub key_wait_release: Option<bool>,
+}
+
+/// Tuning of the filters, the `[filters]` section of the config file
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterSettings {
+    /// how much of its brightness a pixel keeps each frame after turning off
+    pub phosphor_decay: f32,
+    /// how many frames are averaged by the blend filter
+    pub blend_frames: usize,
+}
+
+impl Default for FilterSettings {
+    fn default() -> FilterSettings {
+        FilterSettings {
+            phosphor_decay: 0.6,
+            blend_frames: 3,
+        }
+    }
+}
+
+impl RomConfig {
+    /// These settings, with the ones left out taken from `base`. The palette
+    /// and the colors are taken together, so a palette picked here isn't
+    /// replaced by colors from `base`.
+    pub fn or(&self, base: &RomConfig) -> RomConfig {
+        let (palette, colors) = if self.palette.is_some() || self.colors.is_some() {
+            (self.palette.clone(), self.colors.clone())
+        } else {
+            (base.palette.clone(), base.colors.clone())
+        };
+        RomConfig {
+            palette,
+            colors,
+            filter: self.filter.clone().or(base.filter.clone()),
+            keymap: self.keymap.clone().or(base.keymap.clone()),
+            pad_mapping: self.pad_mapping.clone().or(base.pad_mapping.clone()),
+            platform: self.platform.clone().or(base.platform.clone()),
+            ips: self.ips.or(base.ips),
+            stack_depth: self.stack_depth.or(base.stack_depth),
+            quirks: self.quirks.or(&base.quirks),
+            start_address: self.start_address.or(base.start_address),
+        }
+    }
+}
+
+impl QuirkOverrides {
+    /// Parses a comma separated list of quirks, like on the command line:
+    /// "logic,wrap=false" turns logic on and wrap off
+    pub fn parse_list(list: &str) -> Result<QuirkOverrides, String> {
+        let mut overrides = QuirkOverrides::default();
+        for item in list.split(',').map(|item| item.trim()) {
+            let (name, value) = match item.split_once('=') {
+                Some((name, "true")) => (name, true),
+                Some((name, "false")) => (name, false),
+                Some((_, value)) => {
+                    return Err(format!(
+                        "Invalid value \"{}\" for quirk {}, expected true or false",
+                        value, item
+                    ))
+                }
+                None => (item, true),
+            };
+            let quirk = match name {
+                "shift" => &mut overrides.shift,
+                "memory_increment_by_x" => &mut overrides.memory_increment_by_x,
+                "memory_leave_i_unchanged" => &mut overrides.memory_leave_i_unchanged,
+                "wrap" => &mut overrides.wrap,
+                "jump" => &mut overrides.jump,
+                "vblank" => &mut overrides.vblank,
+                "logic" => &mut overrides.logic,
+                "key_wait_release" => &mut overrides.key_wait_release,
+                _ => {
+                    return Err(format!(
+                        "Unknown quirk \"{}\", available quirks are: shift, memory_increment_by_x, memory_leave_i_unchanged, wrap, jump, vblank, logic, key_wait_release",
+                        name
+                    ))
+                }
+            };
+            *quirk = Some(value);
+        }
+        Ok(overrides)
+    }
+
+    pub fn or(&self, base: &QuirkOverrides) -> QuirkOverrides {
+        QuirkOverrides {
+            shift: self.shift.or(base.shift),
+            memory_increment_by_x: self.memory_increment_by_x.or(base.memory_increment_by_x),
+            memory_leave_i_unchanged: self
+                .memory_leave_i_unchanged
+                .or(base.memory_leave_i_unchanged),
+            wrap: self.wrap.or(base.wrap),
+            jump: self.jump.or(base.jump),
+            vblank: self.vblank.or(base.vblank),
+            logic: self.logic.or(base.logic),
+            key_wait_release: self.key_wait_release.or(base.key_wait_release),
+        }
+    }
+
+    pub fn apply(&self, quirks: &mut Quirks) {
+        let fields = [
+            (&mut quirks.shift, self.shift),
+            (
+                &mut quirks.memory_increment_by_x,
+                self.memory_increment_by_x,
+            ),
+            (
+                &mut quirks.memory_leave_i_unchanged,
+                self.memory_leave_i_unchanged,
+            ),
+            (&mut quirks.wrap, self.wrap),
+            (&mut quirks.jump, self.jump),
+            (&mut quirks.vblank, self.vblank),
+            (&mut quirks.logic, self.logic),
+            (&mut quirks.key_wait_release, self.key_wait_release),
+        ];
+        for (quirk, value) in fields {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            palette: None,
+            palettes: BTreeMap::new(),
+            filter: None,
+            filters: FilterSettings::default(),
+            window_scale: 8,
+            scale_mode: None,
+            fullscreen: false,
+            keymap: None,
+            keymaps: BTreeMap::new(),
+            pad_mapping: None,
+            pad_mappings: BTreeMap::new(),
+            stick_threshold: 0.5,
+            platform: None,
+            ips: None,
+            stack_depth: None,
+            quirks: QuirkOverrides::default(),
+            start_address: None,
+            engine: None,
+            database: None,
+            rom_dir: None,
+            roms: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        toml::from_str(text).map_err(|err| err.to_string())
+    }
+
+    /// Overrides for the ROM at the given path with the given SHA-1 hash, if
+    /// there are any. A section for the hash takes precedence over one for
+    /// the file name, since the file could have been renamed.
+    pub fn rom(&self, rom_path: &str, sha1: &str) -> Option<&RomConfig> {
+        let by_hash = self
+            .roms
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(sha1))
+            .map(|(_, rom_config)| rom_config);
+        if by_hash.is_some() {
+            return by_hash;
+        }
+        let file_name = Path::new(rom_path).file_name()?.to_str()?;
+        self.roms.get(file_name)
+    }
+
+    /// Path of the ROM database replacing the built-in one, if there is one
+    pub fn database_path(&self) -> Option<PathBuf> {
+        match &self.database {
+            Some(path) => Some(path.clone()),
+            None => Some(config_dir()?.join("programs.json")).filter(|path| path.exists()),
+        }
+    }
+}
+
+/// Directory of the config file and the ROM database
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("chip8"))
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// File with the recently played and favorite ROMs of the ROM browser
+pub fn history_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("history.toml"))
+}
+
+/// Loads the config file, a missing file results in the default config
+pub fn load() -> Result<Config, String> {
+    let path = match config_path() {
+        Some(path) if path.exists() => path,
+        _ => return Ok(Config::default()),
+    };
+    let text = std::fs::read_to_string(&path)
+        .map_err(|err| format!("Failed to read config {}: {}", path.display(), err))?;
+    Config::parse(&text).map_err(|err| format!("Invalid config {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_works() {
+        let config = Config::parse(
+            r##"
+            palette = "mine"
+            filter = "blend"
+
+            [palettes]
+            mine = ["#000000", "#FFFFFF"]
+
+            [filters]
+            blend_frames = 5
+
+            [keymaps.wasd]
+            W = 0x5
+            Space = 10
+
+            [pad_mappings.vertical]
+            dpup = 0x1
+
+            [quirks]
+            key_wait_release = false
+            logic = true
+
+            [roms."tetris.ch8"]
+            keymap = "wasd"
+            pad_mapping = "vertical"
+            platform = "superchip"
+            ips = 1200
+            stack_depth = 12
+            quirks = { key_wait_release = true }
+            "##,
+        )
+        .unwrap();
+        assert_eq!(config.palette, Some("mine".to_string()));
+        assert_eq!(config.palettes["mine"], vec!["#000000", "#FFFFFF"]);
+        assert_eq!(config.filter, Some("blend".to_string()));
+        assert_eq!(config.filters.blend_frames, 5);
+        assert_eq!(
+            config.filters.phosphor_decay,
+            FilterSettings::default().phosphor_decay
+        );
+
+        assert_eq!(config.keymaps["wasd"]["W"], 0x5);
+        assert_eq!(config.keymaps["wasd"]["Space"], 0xA);
+        let rom = config.rom("roms/tetris.ch8", "").unwrap();
+        assert_eq!(rom.keymap, Some("wasd".to_string()));
+        assert_eq!(rom.pad_mapping, Some("vertical".to_string()));
+        assert_eq!(config.pad_mappings["vertical"]["dpup"], 0x1);
+
+        let mut quirks = Quirks::default();
+        config.quirks.apply(&mut quirks);
+        assert!(!quirks.key_wait_release);
+        assert!(quirks.logic);
+        rom.quirks.apply(&mut quirks);
+        assert!(quirks.key_wait_release);
+        assert!(quirks.logic);
+        assert_eq!(rom.platform, Some("superchip".to_string()));
+        assert_eq!(rom.ips, Some(1200));
+        assert_eq!(rom.stack_depth, Some(12));
+        assert_eq!(config.rom("roms/pong.ch8", ""), None);
+
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert!(Config::parse("colour = 1").is_err());
+    }
+
+    #[test]
+    fn parse_quirk_list_works() {
+        assert_eq!(
+            QuirkOverrides::parse_list("logic, wrap=false,shift=true"),
+            Ok(QuirkOverrides {
+                logic: Some(true),
+                wrap: Some(false),
+                shift: Some(true),
+                ..QuirkOverrides::default()
+            })
+        );
+        assert!(QuirkOverrides::parse_list("logic=yes").is_err());
+        assert!(QuirkOverrides::parse_list("clip").is_err());
+    }
+
+    #[test]
+    fn rom_matches_hash_before_file_name() {
+        let config = Config::parse(
+            r#"
+            [roms."tetris.ch8"]
+            palette = "amber"
+
+            [roms.A9993E364706816ABA3E25717850C26C9CD0D89D]
+            palette = "lcd"
+            "#,
+        )
+        .unwrap();
+        let sha1 = "a9993e364706816aba3e25717850c26c9cd0d89d";
+        let rom = config.rom("tetris.ch8", sha1).unwrap();
+        assert_eq!(rom.palette, Some("lcd".to_string()));
+        let rom = config.rom("renamed.ch8", sha1).unwrap();
+        assert_eq!(rom.palette, Some("lcd".to_string()));
+        let rom = config.rom("tetris.ch8", "0000").unwrap();
+        assert_eq!(rom.palette, Some("amber".to_string()));
+        assert_eq!(config.rom("pong.ch8", "0000"), None);
+    }
+
+    #[test]
+    fn rom_config_or_works() {
+        let base = RomConfig {
+            colors: Some(vec!["#000000".to_string(), "#FFFFFF".to_string()]),
+            ips: Some(600),
+            quirks: QuirkOverrides {
+                wrap: Some(true),
+                logic: Some(true),
+                ..QuirkOverrides::default()
+            },
+            ..RomConfig::default()
+        };
+        let rom_config = RomConfig {
+            ips: Some(900),
+            quirks: QuirkOverrides {
+                logic: Some(false),
+                ..QuirkOverrides::default()
+            },
+            ..RomConfig::default()
+        };
+        let merged = rom_config.or(&base);
+        assert_eq!(merged.colors, base.colors);
+        assert_eq!(merged.ips, Some(900));
+        assert_eq!(merged.quirks.wrap, Some(true));
+        assert_eq!(merged.quirks.logic, Some(false));
+
+        // a palette replaces the colors
+        let rom_config = RomConfig {
+            palette: Some("lcd".to_string()),
+            ..RomConfig::default()
+        };
+        let merged = rom_config.or(&base);
+        assert_eq!(merged.palette, Some("lcd".to_string()));
+        assert_eq!(merged.colors, None);
+    }
+}
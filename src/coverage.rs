@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::chip8::decode_at;
+use crate::disasm::{format_with_symbols, lines};
+use crate::profile::Profile;
+use crate::symbols::Symbols;
+
+// memory bytes in a row of the heat map, and the size of each in pixels
+const HEAT_MAP_COLUMNS: usize = 64;
+const HEAT_MAP_CELL: usize = 4;
+// heat map colors: memory outside the ROM, ROM bytes that never ran, then
+// the rest of the palette from run once to run the most
+const OUTSIDE_ROM: u8 = 0;
+const NEVER_RUN: u8 = 1;
+const FIRST_HEAT: u8 = 2;
+
+#[derive(Serialize)]
+struct Report<'a> {
+    instructions: u64,
+    rom_start: usize,
+    rom_end: usize,
+    /// ROM bytes covered by instructions that ran
+    covered_bytes: usize,
+    kinds: &'a BTreeMap<&'static str, u64>,
+    addresses: Vec<AddressRuns>,
+    unexecuted: Vec<Region>,
+}
+
+#[derive(Serialize)]
+struct AddressRuns {
+    address: u16,
+    runs: u64,
+    instruction: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Region {
+    start: usize,
+    end: usize,
+}
+
+/// The ROM bytes covered by instructions that ran
+pub fn covered_bytes(profile: &Profile, rom_start: usize, rom_len: usize) -> usize {
+    (rom_start..rom_start + rom_len)
+        .filter(|address| profile.covered(*address))
+        .count()
+}
+
+/// The instruction at the address as the disassembler shows it, as it's in
+/// memory now
+pub fn instruction_at(memory: &[u8], address: u16, symbols: &Symbols) -> String {
+    match decode_at(memory, address) {
+        Ok(instruction) => format_with_symbols(&instruction, symbols),
+        Err(_) => "??".to_string(),
+    }
+}
+
+/// The disassembly of the ROM with the number of times each instruction
+/// ran in front, `-` for the ones that never ran
+pub fn listing(profile: &Profile, rom: &[u8], start_address: u16, symbols: &Symbols) -> String {
+    let mut listing = String::new();
+    for line in lines(rom, start_address, symbols) {
+        if let Some(label) = line.label {
+            listing.push_str(&format!("{:>10}  {}:\n", "", label));
+        }
+        let runs = match profile.counts().get(line.address) {
+            Some(runs) if *runs > 0 => runs.to_string(),
+            _ => "-".to_string(),
+        };
+        listing.push_str(&format!("{:>10}  {}\n", runs, line.text));
+    }
+    listing
+}
+
+/// Everything the profile knows as JSON: the runs by kind and by address,
+/// and the parts of the ROM that never ran. Addresses are in decimal, and
+/// regions end before their `end`.
+pub fn json_report(
+    profile: &Profile,
+    memory: &[u8],
+    rom_start: usize,
+    rom_len: usize,
+    symbols: &Symbols,
+) -> String {
+    let rom_end = rom_start + rom_len;
+    let addresses = (profile.counts().iter().enumerate())
+        .filter(|(_, runs)| **runs > 0)
+        .map(|(address, runs)| AddressRuns {
+            address: address as u16,
+            runs: *runs,
+            instruction: instruction_at(memory, address as u16, symbols),
+            label: symbols.name(address as u16).map(|name| name.to_string()),
+        })
+        .collect();
+    let unexecuted = (profile.unexecuted(rom_start..rom_end).into_iter())
+        .map(|region| Region {
+            start: region.start,
+            end: region.end,
+        })
+        .collect();
+    let report = Report {
+        instructions: profile.total(),
+        rom_start,
+        rom_end,
+        covered_bytes: covered_bytes(profile, rom_start, rom_len),
+        kinds: profile.kinds(),
+        addresses,
+        unexecuted,
+    };
+    serde_json::to_string_pretty(&report).unwrap()
+}
+
+/// A GIF of memory, 64 bytes a row, colored by how often the instruction
+/// covering each byte ran, from blue for rarely to yellow for the most. The
+/// counts are on a log scale, so loops stand out without hiding the rest.
+pub fn heat_map(profile: &Profile, rom_start: usize, rom_len: usize) -> Vec<u8> {
+    let counts = profile.counts();
+    let runs = |address: usize| match address {
+        0 => counts[0],
+        _ => counts[address].max(counts[address - 1]),
+    };
+    let most = (0..counts.len()).map(runs).max().unwrap_or(0);
+    let levels = (u8::MAX - FIRST_HEAT) as f64;
+    let cells: Vec<u8> = (0..counts.len())
+        .map(|address| match runs(address) {
+            0 if (rom_start..rom_start + rom_len).contains(&address) => NEVER_RUN,
+            0 => OUTSIDE_ROM,
+            // ln(most) is 0 when everything ran once
+            runs => {
+                let heat = (runs as f64).ln() / (most as f64).ln().max(f64::EPSILON);
+                FIRST_HEAT + (heat * levels).round() as u8
+            }
+        })
+        .collect();
+
+    let width = HEAT_MAP_COLUMNS * HEAT_MAP_CELL;
+    let height = cells.len().div_ceil(HEAT_MAP_COLUMNS) * HEAT_MAP_CELL;
+    let mut pixels = vec![OUTSIDE_ROM; width * height];
+    for (y, row) in pixels.chunks_mut(width).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let address = y / HEAT_MAP_CELL * HEAT_MAP_COLUMNS + x / HEAT_MAP_CELL;
+            *pixel = cells.get(address).copied().unwrap_or(OUTSIDE_ROM);
+        }
+    }
+
+    let mut gif = Vec::new();
+    let (width, height) = (width as u16, height as u16);
+    let palette = heat_palette();
+    let mut encoder = gif::Encoder::new(&mut gif, width, height, &palette).unwrap();
+    let frame = gif::Frame::from_indexed_pixels(width, height, pixels, None);
+    encoder.write_frame(&frame).unwrap();
+    drop(encoder);
+    gif
+}
+
+/// Black, dark gray, then blue through red to yellow, as RGB
+fn heat_palette() -> Vec<u8> {
+    let mut palette = vec![0, 0, 0, 48, 48, 48];
+    let levels = (u8::MAX - FIRST_HEAT) as f64;
+    for level in 0..=u8::MAX - FIRST_HEAT {
+        let heat = level as f64 / levels;
+        let channel = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        palette.extend([
+            channel(heat * 2.0),
+            channel(heat * 2.0 - 1.0),
+            channel(1.0 - heat * 2.0),
+        ]);
+    }
+    palette
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Instruction;
+
+    /// A profile of a loop at 0x200, the 4 bytes after it never ran
+    fn profile() -> (Profile, Vec<u8>) {
+        let rom = vec![0x70, 0x01, 0x12, 0x00, 0xF0, 0x90, 0x00, 0xE0];
+        let mut memory = vec![0; 0x300];
+        memory[0x200..0x208].copy_from_slice(&rom);
+        let mut profile = Profile::new(memory.len());
+        for _ in 0..3 {
+            profile.record(0x200, &Instruction::Add { reg: 0, val: 1 });
+            profile.record(0x202, &Instruction::Jmp { address: 0x200 });
+        }
+        (profile, memory)
+    }
+
+    #[test]
+    fn listing_works() {
+        let (profile, memory) = profile();
+        assert_eq!(
+            listing(&profile, &memory[0x200..0x208], 0x200, &Symbols::default()),
+            "         3  200: 7001  ADD V0, 0x01\n\
+             \x20        3  202: 1200  JP 0x200\n\
+             \x20        -  204: F090  DW 0xF090\n\
+             \x20        -  206: 00E0  CLS\n"
+        );
+
+        let symbols = Symbols::parse("0x200 main\ndata 0x204 2").unwrap();
+        assert_eq!(
+            listing(&profile, &memory[0x200..0x208], 0x200, &symbols),
+            "            main:\n\
+             \x20        3  200: 7001  ADD V0, 0x01\n\
+             \x20        3  202: 1200  JP main\n\
+             \x20        -  204: F090  DB 0xF0, 0x90\n\
+             \x20        -  206: 00E0  CLS\n"
+        );
+    }
+
+    #[test]
+    fn json_report_works() {
+        let (profile, memory) = profile();
+        let symbols = Symbols::parse("0x200 main").unwrap();
+        let report: serde_json::Value =
+            serde_json::from_str(&json_report(&profile, &memory, 0x200, 8, &symbols)).unwrap();
+        assert_eq!(report["instructions"], 6);
+        assert_eq!(report["covered_bytes"], 4);
+        assert_eq!(report["kinds"]["Add"], 3);
+        assert_eq!(report["addresses"][1]["address"], 0x202);
+        assert_eq!(report["addresses"][0]["label"], "main");
+        assert_eq!(report["addresses"][1]["instruction"], "JP main");
+        assert!(report["addresses"][1].get("label").is_none());
+        assert_eq!(report["unexecuted"][0]["start"], 0x204);
+        assert_eq!(report["unexecuted"][0]["end"], 0x208);
+    }
+
+    #[test]
+    fn heat_map_works() {
+        let (mut profile, _) = profile();
+        profile.record(0x2C0, &Instruction::Cls);
+        let gif = heat_map(&profile, 0x200, 8);
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(gif.as_slice()).unwrap();
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        // 0x300 bytes, 12 rows
+        assert_eq!((frame.width, frame.height), (256, 48));
+        let cell = |address: usize| {
+            let (x, y) = (address % 64 * 4, address / 64 * 4);
+            frame.buffer[y * 256 + x]
+        };
+        assert_eq!(cell(0x1FF), OUTSIDE_ROM);
+        assert_eq!(cell(0x200), u8::MAX);
+        assert_eq!(cell(0x203), u8::MAX);
+        assert_eq!(cell(0x204), NEVER_RUN);
+        // ran once, the coolest color
+        assert_eq!(cell(0x2C0), FIRST_HEAT);
+        assert_eq!(cell(0x2C1), FIRST_HEAT);
+    }
+}
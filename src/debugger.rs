@@ -0,0 +1,423 @@
+use std::collections::BTreeSet;
+
+use crate::chip8::{decode_instruction, Chip8, Instruction};
+use crate::disasm::format_with_symbols;
+use crate::overlay::{Ink, Overlay, CELL_HEIGHT, CELL_WIDTH, OVERLAY_HEIGHT, OVERLAY_WIDTH};
+use crate::symbols::Symbols;
+
+/// The debugger's view is twice as big as the overlay over the display, the
+/// display goes into its top left quarter
+pub const DEBUG_VIEW_WIDTH: usize = OVERLAY_WIDTH * 2;
+pub const DEBUG_VIEW_HEIGHT: usize = OVERLAY_HEIGHT * 2;
+
+// frames a written byte stays highlighted in the memory view
+const WRITE_HIGHLIGHT_FRAMES: u8 = 60;
+
+// where the panels go, in characters: registers, stack and sprite on the
+// right of the display, disassembly and memory below it
+const RIGHT_COLUMN: usize = OVERLAY_WIDTH / CELL_WIDTH + 2;
+const LOWER_ROW: usize = OVERLAY_HEIGHT / CELL_HEIGHT + 1;
+const PANEL_WIDTH: usize = OVERLAY_WIDTH / CELL_WIDTH - 2;
+const STACK_ROW: usize = 8;
+const SPRITE_ROW: usize = 14;
+const LIST_ROWS: usize = DEBUG_VIEW_HEIGHT / CELL_HEIGHT - LOWER_ROW - 1;
+
+// instructions shown before the current one in the disassembly
+const DISASM_CONTEXT: usize = 8;
+const BYTES_PER_ROW: usize = 16;
+// the address in front of the bytes of a row of the memory view
+const ADDRESS_WIDTH: usize = 5;
+// tallest sprite DXYN can draw
+const MAX_SPRITE_HEIGHT: usize = 15;
+// size of a sprite pixel in the view
+const SPRITE_SCALE: usize = 2;
+
+/// Breakpoints and the views of the debugger: registers, call stack,
+/// disassembly around the program counter, memory and the sprite at I
+pub struct Debugger {
+    pub breakpoints: BTreeSet<u16>,
+    pub paused: bool,
+    /// lets the instruction at the program counter run even though it has a
+    /// breakpoint, to go on after stopping at it
+    skip_breakpoint: bool,
+    previous_memory: Vec<u8>,
+    /// frames each byte of memory stays highlighted after being written
+    written: Vec<u8>,
+    /// address of the first row of the memory view
+    memory_top: usize,
+}
+
+impl Debugger {
+    pub fn new(chip8: &Chip8) -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            paused: false,
+            skip_breakpoint: false,
+            previous_memory: chip8.memory.clone(),
+            written: vec![0; chip8.memory.len()],
+            memory_top: chip8.regs.pc as usize & !(BYTES_PER_ROW - 1),
+        }
+    }
+
+    /// Starts over with a machine that was reset. The breakpoints are kept,
+    /// and a paused program stays paused, at its first instruction now.
+    pub fn restart(&mut self, chip8: &Chip8) {
+        *self = Debugger {
+            breakpoints: std::mem::take(&mut self.breakpoints),
+            paused: self.paused,
+            ..Debugger::new(chip8)
+        };
+    }
+
+    /// Whether to stop before running the instruction at `pc`
+    pub fn should_stop(&mut self, pc: u16) -> bool {
+        if self.skip_breakpoint {
+            self.skip_breakpoint = false;
+            return false;
+        }
+        self.breakpoints.contains(&pc)
+    }
+
+    /// Stops at the next instruction or goes on from a stop
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.skip_breakpoint = !self.paused;
+    }
+
+    /// Sets a breakpoint or removes it, gives whether it's set now
+    pub fn toggle_breakpoint(&mut self, address: u16) -> bool {
+        if !self.breakpoints.remove(&address) {
+            self.breakpoints.insert(address);
+            return true;
+        }
+        false
+    }
+
+    /// Notes the bytes that were written since the last call, called once a
+    /// frame
+    pub fn watch_memory(&mut self, memory: &[u8]) {
+        if memory.len() != self.previous_memory.len() {
+            self.previous_memory = memory.to_vec();
+            self.written = vec![0; memory.len()];
+            self.memory_top = self.memory_top.min(self.last_memory_top());
+            return;
+        }
+        for (i, byte) in memory.iter().enumerate() {
+            if *byte != self.previous_memory[i] {
+                self.written[i] = WRITE_HIGHLIGHT_FRAMES;
+            } else {
+                self.written[i] = self.written[i].saturating_sub(1);
+            }
+        }
+        self.previous_memory.copy_from_slice(memory);
+    }
+
+    /// Scrolls the memory view by a number of rows, up for negative ones
+    pub fn scroll_memory(&mut self, rows: i32) {
+        let top = self.memory_top as i64 + rows as i64 * BYTES_PER_ROW as i64;
+        self.memory_top = top.clamp(0, self.last_memory_top() as i64) as usize;
+    }
+
+    fn last_memory_top(&self) -> usize {
+        self.written.len().saturating_sub(LIST_ROWS * BYTES_PER_ROW)
+    }
+
+    /// Address shown at a character of the view, for setting breakpoints by
+    /// clicking on instructions or bytes
+    pub fn address_at(&self, chip8: &Chip8, column: usize, row: usize) -> Option<u16> {
+        if !(LOWER_ROW + 1..LOWER_ROW + 1 + LIST_ROWS).contains(&row) {
+            return None;
+        }
+        let line = row - LOWER_ROW - 1;
+        let address = if column < PANEL_WIDTH {
+            disasm_start(chip8.regs.pc) + line * 2
+        } else if column >= RIGHT_COLUMN {
+            let row_address = self.memory_top + line * BYTES_PER_ROW;
+            match (column - RIGHT_COLUMN).checked_sub(ADDRESS_WIDTH) {
+                Some(offset) if offset / 3 < BYTES_PER_ROW => row_address + offset / 3,
+                Some(_) => return None,
+                None => row_address,
+            }
+        } else {
+            return None;
+        };
+        Some(address as u16).filter(|address| (*address as usize) < chip8.memory.len())
+    }
+
+    pub fn draw(&self, chip8: &Chip8, symbols: &Symbols, overlay: &mut Overlay) {
+        overlay.fill_all(Ink::Background);
+        self.draw_registers(chip8, overlay);
+        self.draw_call_stack(chip8, symbols, overlay);
+        self.draw_sprite(chip8, overlay);
+        self.draw_disassembly(chip8, symbols, overlay);
+        self.draw_memory(chip8, overlay);
+    }
+
+    fn draw_registers(&self, chip8: &Chip8, overlay: &mut Overlay) {
+        let regs = &chip8.regs;
+        header(overlay, RIGHT_COLUMN, 0, "REGISTERS");
+        for (row, registers) in regs.general.chunks(4).enumerate() {
+            let text: Vec<String> = registers
+                .iter()
+                .enumerate()
+                .map(|(i, value)| format!("V{:X} {:02X}", row * 4 + i, value))
+                .collect();
+            overlay.text(RIGHT_COLUMN, 1 + row, &text.join("  "), Ink::Foreground);
+        }
+        let text = format!(
+            "I  {:04X}  PC {:04X}  SP {:X}",
+            regs.i,
+            regs.pc,
+            chip8.stack.len()
+        );
+        overlay.text(RIGHT_COLUMN, 5, &text, Ink::Foreground);
+        let text = format!("DT {:02X}    ST {:02X}", regs.dt, regs.st);
+        overlay.text(RIGHT_COLUMN, 6, &text, Ink::Foreground);
+    }
+
+    /// The calls that haven't returned, the latest first and highlighted,
+    /// with where they were called from
+    fn draw_call_stack(&self, chip8: &Chip8, symbols: &Symbols, overlay: &mut Overlay) {
+        let title = format!("CALL STACK {}/{}", chip8.stack.len(), chip8.stack_depth);
+        header(overlay, RIGHT_COLUMN, STACK_ROW, &title);
+        let rows = SPRITE_ROW - STACK_ROW - 1;
+        for (i, frame) in chip8.stack.iter().rev().enumerate() {
+            let row = STACK_ROW + 1 + i;
+            // the last row says how many more there are, if they don't fit
+            if i == rows - 1 && chip8.stack.len() > rows {
+                let text = format!("... {} MORE", chip8.stack.len() - i);
+                overlay.text(RIGHT_COLUMN, row, &text, Ink::Foreground);
+                break;
+            }
+            let text = format!(
+                "{} FROM {:03X}",
+                symbols.describe(frame.target),
+                frame.caller
+            );
+            let text: String = text.chars().take(PANEL_WIDTH).collect();
+            if i == 0 {
+                overlay.fill(RIGHT_COLUMN, row, text.len(), Ink::Foreground);
+                overlay.text(RIGHT_COLUMN, row, &text, Ink::Background);
+            } else {
+                overlay.text(RIGHT_COLUMN, row, &text, Ink::Foreground);
+            }
+        }
+    }
+
+    /// Memory at I drawn as a sprite, as tall as the sprite about to be
+    /// drawn if the next instruction is DXYN
+    fn draw_sprite(&self, chip8: &Chip8, overlay: &mut Overlay) {
+        let height = match fetch(chip8, chip8.regs.pc).map(decode_instruction) {
+            Some(Ok(Instruction::Drw { n_bytes, .. })) => n_bytes as usize,
+            _ => MAX_SPRITE_HEIGHT,
+        };
+        let title = format!("SPRITE AT I (8X{})", height);
+        header(overlay, RIGHT_COLUMN, SPRITE_ROW, &title);
+        let left = RIGHT_COLUMN * CELL_WIDTH;
+        let top = (SPRITE_ROW + 1) * CELL_HEIGHT;
+        for y in 0..height {
+            let byte = match chip8.memory.get(chip8.regs.i as usize + y) {
+                Some(byte) => *byte,
+                None => break,
+            };
+            for x in 0..8 {
+                if byte >> (7 - x) & 1 == 1 {
+                    for dy in 0..SPRITE_SCALE {
+                        for dx in 0..SPRITE_SCALE {
+                            overlay.set_pixel(
+                                left + x * SPRITE_SCALE + dx,
+                                top + y * SPRITE_SCALE + dy,
+                                Ink::Foreground,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw_disassembly(&self, chip8: &Chip8, symbols: &Symbols, overlay: &mut Overlay) {
+        header(overlay, 0, LOWER_ROW, "DISASSEMBLY");
+        let start = disasm_start(chip8.regs.pc);
+        for line in 0..LIST_ROWS {
+            let address = start + line * 2;
+            let raw = match fetch(chip8, address as u16) {
+                Some(raw) => raw,
+                None => break,
+            };
+            let text = match decode_instruction(raw) {
+                Ok(instruction) => format_with_symbols(&instruction, symbols),
+                Err(_) => format!("DW 0x{:04X}", raw),
+            };
+            let breakpoint = if self.breakpoints.contains(&(address as u16)) {
+                '*'
+            } else {
+                ' '
+            };
+            let text = format!("{}{:04X}  {:04X}  {}", breakpoint, address, raw, text);
+            let row = LOWER_ROW + 1 + line;
+            if address == chip8.regs.pc as usize {
+                overlay.fill(0, row, PANEL_WIDTH, Ink::Foreground);
+                overlay.text(0, row, &text, Ink::Background);
+            } else {
+                overlay.text(0, row, &text, Ink::Foreground);
+            }
+        }
+    }
+
+    fn draw_memory(&self, chip8: &Chip8, overlay: &mut Overlay) {
+        header(overlay, RIGHT_COLUMN, LOWER_ROW, "MEMORY");
+        for line in 0..LIST_ROWS {
+            let row_address = self.memory_top + line * BYTES_PER_ROW;
+            if row_address >= chip8.memory.len() {
+                break;
+            }
+            let row = LOWER_ROW + 1 + line;
+            overlay.text(
+                RIGHT_COLUMN,
+                row,
+                &format!("{:04X}", row_address),
+                Ink::Foreground,
+            );
+            let bytes = chip8.memory[row_address..].iter().take(BYTES_PER_ROW);
+            for (i, byte) in bytes.enumerate() {
+                let column = RIGHT_COLUMN + ADDRESS_WIDTH + i * 3;
+                let text = format!("{:02X}", byte);
+                if self
+                    .written
+                    .get(row_address + i)
+                    .is_some_and(|frames| *frames > 0)
+                {
+                    overlay.fill(column, row, 2, Ink::Foreground);
+                    overlay.text(column, row, &text, Ink::Background);
+                } else {
+                    overlay.text(column, row, &text, Ink::Foreground);
+                }
+            }
+        }
+    }
+}
+
+/// First address of the disassembly, a few instructions before the program
+/// counter
+fn disasm_start(pc: u16) -> usize {
+    (pc as usize).saturating_sub(DISASM_CONTEXT * 2)
+}
+
+fn fetch(chip8: &Chip8, address: u16) -> Option<u16> {
+    let address = address as usize;
+    match chip8.memory.get(address..address + 2) {
+        Some([high, low]) => Some((*high as u16) << 8 | *low as u16),
+        _ => None,
+    }
+}
+
+/// The title of a panel, highlighted across its width
+fn header(overlay: &mut Overlay, column: usize, row: usize, title: &str) {
+    overlay.fill(column, row, PANEL_WIDTH, Ink::Foreground);
+    overlay.text(column + 1, row, title, Ink::Background);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakpoints_work() {
+        let mut chip8 = Chip8::new();
+        chip8.regs.pc = 0x200;
+        let mut debugger = Debugger::new(&chip8);
+        assert!(debugger.toggle_breakpoint(0x204));
+        assert!(!debugger.should_stop(0x202));
+        assert!(debugger.should_stop(0x204));
+
+        // stopped at the breakpoint, going on runs its instruction
+        debugger.paused = true;
+        debugger.toggle_pause();
+        assert!(!debugger.paused);
+        assert!(!debugger.should_stop(0x204));
+        assert!(debugger.should_stop(0x204));
+
+        assert!(!debugger.toggle_breakpoint(0x204));
+        assert!(!debugger.should_stop(0x204));
+    }
+
+    #[test]
+    fn restart_works() {
+        let mut chip8 = Chip8::new();
+        chip8.regs.pc = 0x200;
+        let mut debugger = Debugger::new(&chip8);
+        debugger.toggle_breakpoint(0x200);
+        debugger.toggle_breakpoint(0x2A0);
+
+        // went on from a breakpoint, then paused again
+        chip8.regs.pc = 0x2A0;
+        chip8.memory[0x300] = 1;
+        debugger.watch_memory(&chip8.memory);
+        debugger.scroll_memory(10);
+        debugger.paused = true;
+        debugger.toggle_pause();
+        debugger.paused = true;
+
+        let chip8 = Chip8::new();
+        debugger.restart(&chip8);
+        assert!(debugger.paused);
+        assert_eq!(debugger.memory_top, 0);
+        assert!(debugger.written.iter().all(|frames| *frames == 0));
+        // the breakpoints are still there, the one at the start isn't skipped
+        assert!(debugger.should_stop(0x200));
+        assert!(debugger.should_stop(0x2A0));
+    }
+
+    #[test]
+    fn address_at_works() {
+        let mut chip8 = Chip8::new();
+        chip8.regs.pc = 0x210;
+        let mut debugger = Debugger::new(&chip8);
+
+        // the disassembly starts 8 instructions before the program counter
+        assert_eq!(debugger.address_at(&chip8, 3, LOWER_ROW + 1), Some(0x200));
+        assert_eq!(
+            debugger.address_at(&chip8, 3, LOWER_ROW + 1 + DISASM_CONTEXT),
+            Some(0x210)
+        );
+        // the headers aren't addresses
+        assert_eq!(debugger.address_at(&chip8, 3, LOWER_ROW), None);
+        assert_eq!(debugger.address_at(&chip8, RIGHT_COLUMN, 0), None);
+
+        // memory rows start at the program counter, 16 bytes each
+        assert_eq!(
+            debugger.address_at(&chip8, RIGHT_COLUMN, LOWER_ROW + 2),
+            Some(0x220)
+        );
+        let third_byte = RIGHT_COLUMN + ADDRESS_WIDTH + 2 * 3 + 1;
+        assert_eq!(
+            debugger.address_at(&chip8, third_byte, LOWER_ROW + 2),
+            Some(0x222)
+        );
+        debugger.scroll_memory(-1000);
+        assert_eq!(
+            debugger.address_at(&chip8, RIGHT_COLUMN, LOWER_ROW + 1),
+            Some(0)
+        );
+        debugger.scroll_memory(1000);
+        assert_eq!(
+            debugger.address_at(&chip8, RIGHT_COLUMN, LOWER_ROW + LIST_ROWS),
+            Some((chip8.memory.len() - BYTES_PER_ROW) as u16)
+        );
+    }
+
+    #[test]
+    fn watch_memory_highlights_writes() {
+        let mut chip8 = Chip8::new();
+        let mut debugger = Debugger::new(&chip8);
+        chip8.memory[0x300] = 0xAB;
+        debugger.watch_memory(&chip8.memory);
+        assert_eq!(debugger.written[0x300], WRITE_HIGHLIGHT_FRAMES);
+        assert_eq!(debugger.written[0x301], 0);
+        for _ in 0..WRITE_HIGHLIGHT_FRAMES {
+            debugger.watch_memory(&chip8.memory);
+        }
+        assert_eq!(debugger.written[0x300], 0);
+    }
+}
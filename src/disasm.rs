@@ -0,0 +1,199 @@
+use crate::chip8::{decode_instruction, Instruction};
+use crate::symbols::Symbols;
+
+/// Formats an instruction in the usual CHIP-8 assembly syntax
+/// (Cowgod's reference), which the assembler reads back
+pub fn format_instruction(ins: &Instruction) -> String {
+    match ins {
+        Instruction::Cls => "CLS".to_string(),
+        Instruction::Ret => "RET".to_string(),
+        Instruction::Jmp { address } => format!("JP 0x{:03X}", address),
+        Instruction::Call { address } => format!("CALL 0x{:03X}", address),
+        Instruction::Se { reg, val } => format!("SE V{:X}, 0x{:02X}", reg, val),
+        Instruction::Sne { reg, val } => format!("SNE V{:X}, 0x{:02X}", reg, val),
+        Instruction::SeReg { reg1, reg2 } => format!("SE V{:X}, V{:X}", reg1, reg2),
+        Instruction::Ld { reg, val } => format!("LD V{:X}, 0x{:02X}", reg, val),
+        Instruction::Add { reg, val } => format!("ADD V{:X}, 0x{:02X}", reg, val),
+        Instruction::LdReg { reg1, reg2 } => format!("LD V{:X}, V{:X}", reg1, reg2),
+        Instruction::Or { reg1, reg2 } => format!("OR V{:X}, V{:X}", reg1, reg2),
+        Instruction::And { reg1, reg2 } => format!("AND V{:X}, V{:X}", reg1, reg2),
+        Instruction::Xor { reg1, reg2 } => format!("XOR V{:X}, V{:X}", reg1, reg2),
+        Instruction::AddReg { reg1, reg2 } => format!("ADD V{:X}, V{:X}", reg1, reg2),
+        Instruction::SubReg { reg1, reg2 } => format!("SUB V{:X}, V{:X}", reg1, reg2),
+        Instruction::Shr { reg1, reg2 } => format!("SHR V{:X}, V{:X}", reg1, reg2),
+        Instruction::SubRegN { reg1, reg2 } => format!("SUBN V{:X}, V{:X}", reg1, reg2),
+        Instruction::Shl { reg1, reg2 } => format!("SHL V{:X}, V{:X}", reg1, reg2),
+        Instruction::SneReg { reg1, reg2 } => format!("SNE V{:X}, V{:X}", reg1, reg2),
+        Instruction::Ldi { address } => format!("LD I, 0x{:03X}", address),
+        Instruction::JmpV0 { address } => format!("JP V0, 0x{:03X}", address),
+        Instruction::Rnd { reg, mask } => format!("RND V{:X}, 0x{:02X}", reg, mask),
+        Instruction::Drw {
+            reg1,
+            reg2,
+            n_bytes,
+        } => format!("DRW V{:X}, V{:X}, {}", reg1, reg2, n_bytes),
+        Instruction::Skp { reg } => format!("SKP V{:X}", reg),
+        Instruction::SkpNp { reg } => format!("SKNP V{:X}", reg),
+        Instruction::LdFromDt { reg } => format!("LD V{:X}, DT", reg),
+        Instruction::LdKey { reg } => format!("LD V{:X}, K", reg),
+        Instruction::LdIntoDt { reg } => format!("LD DT, V{:X}", reg),
+        Instruction::LdSt { reg } => format!("LD ST, V{:X}", reg),
+        Instruction::AddI { reg } => format!("ADD I, V{:X}", reg),
+        Instruction::LdF { reg } => format!("LD F, V{:X}", reg),
+        Instruction::LdB { reg } => format!("LD B, V{:X}", reg),
+        Instruction::LdRegsMem { end_reg } => format!("LD [I], V{:X}", end_reg),
+        Instruction::LdMemRegs { end_reg } => format!("LD V{:X}, [I]", end_reg),
+    }
+}
+
+/// Formats an instruction like `format_instruction`, with the names of the
+/// addresses it uses when they have one: `CALL draw_player`. The assembler
+/// reads those back too, as labels.
+pub fn format_with_symbols(ins: &Instruction, symbols: &Symbols) -> String {
+    let named = |address: &u16| symbols.name(*address);
+    match ins {
+        Instruction::Jmp { address } => match named(address) {
+            Some(name) => format!("JP {}", name),
+            None => format_instruction(ins),
+        },
+        Instruction::Call { address } => match named(address) {
+            Some(name) => format!("CALL {}", name),
+            None => format_instruction(ins),
+        },
+        Instruction::Ldi { address } => match named(address) {
+            Some(name) => format!("LD I, {}", name),
+            None => format_instruction(ins),
+        },
+        Instruction::JmpV0 { address } => match named(address) {
+            Some(name) => format!("JP V0, {}", name),
+            None => format_instruction(ins),
+        },
+        _ => format_instruction(ins),
+    }
+}
+
+/// A line of a listing
+pub struct Line {
+    pub address: usize,
+    /// the name of the address, which goes on a line of its own before it
+    pub label: Option<String>,
+    /// the address, the bytes and the instruction or data
+    pub text: String,
+}
+
+/// The lines listing a ROM loaded at `start_address`. CHIP-8 mixes code and
+/// data, so every two bytes are shown as an instruction, the ones that don't
+/// decode (sprites, mostly) as data. The data regions of the symbols are
+/// shown as data whatever they hold.
+pub fn lines(rom: &[u8], start_address: u16, symbols: &Symbols) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = start_address as usize + offset;
+        let data = symbols.is_data(address);
+        // data ends where code starts, and the other way around
+        let len = match rom.get(offset + 1) {
+            Some(_) if symbols.is_data(address + 1) == data => 2,
+            _ => 1,
+        };
+        let text = match rom[offset..offset + len] {
+            [high, low] => {
+                let raw = (high as u16) << 8 | low as u16;
+                let text = match decode_instruction(raw) {
+                    Ok(ins) if !data => format_with_symbols(&ins, symbols),
+                    _ if data => format!("DB 0x{:02X}, 0x{:02X}", high, low),
+                    _ => format!("DW 0x{:04X}", raw),
+                };
+                format!("{:03X}: {:04X}  {}", address, raw, text)
+            }
+            [byte] => format!("{:03X}: {:02X}    DB 0x{:02X}", address, byte, byte),
+            _ => unreachable!(),
+        };
+        lines.push(Line {
+            address,
+            label: symbols.name(address as u16).map(|name| name.to_string()),
+            text,
+        });
+        offset += len;
+    }
+    lines
+}
+
+/// Lists the instructions of a ROM loaded at `start_address`, see `lines`
+pub fn disassemble(rom: &[u8], start_address: u16, symbols: &Symbols) -> String {
+    let mut listing = String::new();
+    for line in lines(rom, start_address, symbols) {
+        if let Some(label) = line.label {
+            listing.push_str(&format!("{}:\n", label));
+        }
+        listing.push_str(&line.text);
+        listing.push('\n');
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_instruction_works() {
+        assert_eq!(format_instruction(&Instruction::Cls), "CLS");
+        assert_eq!(
+            format_instruction(&Instruction::JmpV0 { address: 0x2A0 }),
+            "JP V0, 0x2A0"
+        );
+        assert_eq!(
+            format_instruction(&Instruction::Drw {
+                reg1: 0xA,
+                reg2: 0xB,
+                n_bytes: 15
+            }),
+            "DRW VA, VB, 15"
+        );
+        assert_eq!(
+            format_instruction(&Instruction::LdRegsMem { end_reg: 0x3 }),
+            "LD [I], V3"
+        );
+    }
+
+    #[test]
+    fn disassemble_works() {
+        let rom = [0x60, 0x05, 0xA2, 0x0A, 0xF0, 0x90, 0xFF];
+        assert_eq!(
+            disassemble(&rom, 0x200, &Symbols::default()),
+            "200: 6005  LD V0, 0x05\n\
+             202: A20A  LD I, 0x20A\n\
+             204: F090  DW 0xF090\n\
+             206: FF    DB 0xFF\n"
+        );
+    }
+
+    #[test]
+    fn disassemble_with_symbols_works() {
+        let rom = [0x22, 0x06, 0x12, 0x02, 0x00, 0xEE, 0x60, 0xFF, 0x00, 0xE0];
+        let symbols = Symbols::parse(
+            "0x200 main
+             0x204 draw
+             0x206 sprite
+             data 0x207 2",
+        )
+        .unwrap();
+        assert_eq!(
+            disassemble(&rom, 0x200, &symbols),
+            "main:\n\
+             200: 2206  CALL sprite\n\
+             202: 1202  JP 0x202\n\
+             draw:\n\
+             204: 00EE  RET\n\
+             sprite:\n\
+             206: 60    DB 0x60\n\
+             207: FF00  DB 0xFF, 0x00\n\
+             209: E0    DB 0xE0\n"
+        );
+        assert_eq!(
+            format_with_symbols(&Instruction::Ldi { address: 0x206 }, &symbols),
+            "LD I, sprite"
+        );
+    }
+}
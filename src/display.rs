@@ -0,0 +1,205 @@
+use crate::chip8::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const WIDTH: usize = SCREEN_WIDTH as usize;
+const HEIGHT: usize = SCREEN_HEIGHT as usize;
+
+/// Pixels of the display, a bit each. Every row is a `u64`, the leftmost
+/// pixel in the highest bit, so a sprite row is drawn with a shift and an
+/// XOR instead of pixel by pixel.
+///
+/// It also keeps track of what changed since frontends last took the
+/// changes, so they don't need to draw frames that look the same.
+#[derive(Clone, Debug)]
+pub struct Display {
+    rows: [u64; HEIGHT],
+    /// set when pixels were cleared or drawn
+    dirty: bool,
+    /// the rows as the changes were last taken, none for a new display
+    taken: Option<[u64; HEIGHT]>,
+}
+
+/// A rectangle of the display, in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8,
+}
+
+impl Rect {
+    const FULL: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: SCREEN_WIDTH,
+        height: SCREEN_HEIGHT,
+    };
+}
+
+// displays showing the same pixels are the same, whatever changed on the way
+impl PartialEq for Display {
+    fn eq(&self, other: &Display) -> bool {
+        self.rows == other.rows
+    }
+}
+
+impl Default for Display {
+    fn default() -> Display {
+        Display::new()
+    }
+}
+
+impl Display {
+    pub fn new() -> Display {
+        Display {
+            rows: [0; HEIGHT],
+            dirty: true,
+            taken: None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.rows = [0; HEIGHT];
+        self.dirty = true;
+    }
+
+    /// 1 if the pixel is lit, 0 if it isn't
+    pub fn pixel(&self, x: u8, y: u8) -> u8 {
+        (self.rows[y as usize] >> (WIDTH - 1 - x as usize)) as u8 & 1
+    }
+
+    /// XORs a sprite row, 8 pixels wide, onto the row `y` starting at `x`.
+    /// The part past the right edge wraps around to the left edge with
+    /// `wrap`, and is cut off without it. Gives whether a lit pixel was
+    /// turned off.
+    pub fn draw_row(&mut self, x: u8, y: u8, sprite_row: u8, wrap: bool) -> bool {
+        let sprite = (sprite_row as u64) << (WIDTH - 8);
+        let sprite = if wrap {
+            sprite.rotate_right(x as u32)
+        } else {
+            sprite >> x
+        };
+        let row = &mut self.rows[y as usize];
+        let collision = *row & sprite != 0;
+        *row ^= sprite;
+        self.dirty |= sprite != 0;
+        collision
+    }
+
+    /// The part of the display that changed since the last call, none if
+    /// nothing did. Pixels drawn and erased again in between don't count, so
+    /// a game redrawing the same picture every frame doesn't change it. All
+    /// of a new display counts as changed.
+    pub fn take_dirty(&mut self) -> Option<Rect> {
+        if !std::mem::take(&mut self.dirty) {
+            return None;
+        }
+        let taken = self.taken.replace(self.rows);
+        let taken = match taken {
+            Some(taken) => taken,
+            None => return Some(Rect::FULL),
+        };
+        let changed = self.rows.iter().zip(taken).map(|(row, taken)| row ^ taken);
+        // bounds of the changed pixels, as (left, right, top, bottom)
+        let mut bounds: Option<(u32, u32, usize, usize)> = None;
+        for (y, changed) in changed.enumerate().filter(|(_, changed)| *changed != 0) {
+            let (left, right) = (
+                changed.leading_zeros(),
+                WIDTH as u32 - 1 - changed.trailing_zeros(),
+            );
+            bounds = Some(match bounds {
+                Some((l, r, top, _)) => (l.min(left), r.max(right), top, y),
+                None => (left, right, y, y),
+            });
+        }
+        bounds.map(|(left, right, top, bottom)| Rect {
+            x: left as u8,
+            y: top as u8,
+            width: (right - left + 1) as u8,
+            height: (bottom - top + 1) as u8,
+        })
+    }
+
+    /// A byte for each pixel (1 if it's lit), row by row, which frontends
+    /// use as palette indices
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(WIDTH * HEIGHT);
+        for row in self.rows {
+            bytes.extend((0..WIDTH).rev().map(|bit| (row >> bit) as u8 & 1));
+        }
+        bytes
+    }
+
+    /// The pixels in RGBA, row by row, in the colors for unlit and lit pixels
+    pub fn to_rgba(&self, colors: [[u8; 4]; 2]) -> Vec<u8> {
+        let bytes = self.to_bytes();
+        bytes.iter().flat_map(|px| colors[*px as usize]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_row_works() {
+        let mut display = Display::new();
+        assert!(!display.draw_row(2, 1, 0b1000_0001, false));
+        assert_eq!(display.pixel(2, 1), 1);
+        assert_eq!(display.pixel(3, 1), 0);
+        assert_eq!(display.pixel(9, 1), 1);
+        assert_eq!(display.pixel(2, 0), 0);
+        // drawing it again turns it off
+        assert!(display.draw_row(2, 1, 0b1000_0000, false));
+        assert_eq!(display.pixel(2, 1), 0);
+        assert_eq!(display.pixel(9, 1), 1);
+
+        // at the right edge, the rest of the row is cut off or wraps around
+        display.clear();
+        display.draw_row(60, 0, 0xFF, false);
+        display.draw_row(60, 1, 0xFF, true);
+        let bytes = display.to_bytes();
+        assert_eq!(&bytes[..WIDTH], [&[0; 60][..], &[1; 4]].concat());
+        assert_eq!(
+            &bytes[WIDTH..WIDTH * 2],
+            [&[1; 4][..], &[0; 56], &[1; 4]].concat()
+        );
+    }
+
+    #[test]
+    fn take_dirty_works() {
+        let mut display = Display::new();
+        assert_eq!(display.take_dirty(), Some(Rect::FULL));
+        assert_eq!(display.take_dirty(), None);
+
+        display.draw_row(10, 3, 0b1100_0000, false);
+        // wraps around to x = 1
+        display.draw_row(60, 5, 0b0000_0100, true);
+        assert_eq!(
+            display.take_dirty(),
+            Some(Rect {
+                x: 1,
+                y: 3,
+                width: 11,
+                height: 3,
+            })
+        );
+
+        // erased and drawn again, it looks the same
+        display.clear();
+        display.draw_row(10, 3, 0b1100_0000, false);
+        display.draw_row(60, 5, 0b0000_0100, true);
+        assert_eq!(display.take_dirty(), None);
+        display.draw_row(10, 3, 0, false);
+        assert_eq!(display.take_dirty(), None);
+    }
+
+    #[test]
+    fn to_rgba_works() {
+        let mut display = Display::new();
+        display.draw_row(0, 0, 0b0100_0000, false);
+        let rgba = display.to_rgba([[0, 0, 0, 255], [255, 255, 255, 255]]);
+        assert_eq!(rgba.len(), WIDTH * HEIGHT * 4);
+        assert_eq!(&rgba[..8], [0, 0, 0, 255, 255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::VecDeque;
+
+use crate::config::FilterSettings;
+
+/// Post-processing applied to the display buffer before it's drawn, to
+/// hide the flicker caused by games erasing and redrawing sprites.
+/// Filters only see the frames the frontend presents, the emulator state
+/// is never touched.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterKind {
+    /// draw the display buffer as-is
+    None,
+    /// lit pixels fade out over several frames instead of turning off
+    Phosphor,
+    /// average of the last N frames
+    Blend,
+    /// a pixel is lit if it was lit in either of the last two frames
+    Deflicker,
+}
+
+const FILTER_KINDS: [FilterKind; 4] = [
+    FilterKind::None,
+    FilterKind::Phosphor,
+    FilterKind::Blend,
+    FilterKind::Deflicker,
+];
+
+impl FilterKind {
+    pub fn from_name(name: &str) -> Result<FilterKind, String> {
+        match FILTER_KINDS.iter().find(|kind| kind.name() == name) {
+            Some(kind) => Ok(*kind),
+            None => Err(format!(
+                "Unknown filter \"{}\", available filters are: {}",
+                name,
+                FILTER_KINDS.map(|kind| kind.name()).join(", ")
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterKind::None => "none",
+            FilterKind::Phosphor => "phosphor",
+            FilterKind::Blend => "blend",
+            FilterKind::Deflicker => "deflicker",
+        }
+    }
+
+    fn next(&self) -> FilterKind {
+        let i = FILTER_KINDS.iter().position(|kind| kind == self).unwrap();
+        FILTER_KINDS[(i + 1) % FILTER_KINDS.len()]
+    }
+}
+
+// a pixel fainter than this is drawn in the background color anyway
+const MIN_INTENSITY: f32 = 1.0 / 256.0;
+
+/// A pixel as it should be drawn: the value picks the palette color and
+/// the intensity (0.0 - 1.0) how far it is blended into the background
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FilteredPx {
+    pub value: u8,
+    pub intensity: f32,
+}
+
+pub struct FrameFilter {
+    kind: FilterKind,
+    settings: FilterSettings,
+    // most recent frame first
+    history: VecDeque<Vec<u8>>,
+    pixels: Vec<FilteredPx>,
+    /// whether applying the last frame again would give the same pixels
+    settled: bool,
+}
+
+impl FrameFilter {
+    pub fn new(kind: FilterKind, settings: FilterSettings) -> FrameFilter {
+        FrameFilter {
+            kind,
+            settings,
+            history: VecDeque::new(),
+            pixels: Vec::new(),
+            settled: false,
+        }
+    }
+
+    pub fn cycle(&mut self) -> FilterKind {
+        self.kind = self.kind.next();
+        self.history.clear();
+        self.pixels.clear();
+        self.settled = false;
+        self.kind
+    }
+
+    /// Whether the pixels stay the same as long as the frames do, then an
+    /// unchanged frame doesn't need to be filtered and drawn again
+    pub fn is_settled(&self) -> bool {
+        self.settled
+    }
+
+    /// Feeds the next presented frame through the filter
+    pub fn apply(&mut self, frame: &[u8]) -> &[FilteredPx] {
+        if self.pixels.len() != frame.len() {
+            self.history.clear();
+            self.pixels = vec![FilteredPx::default(); frame.len()];
+        }
+
+        let history_len = match self.kind {
+            FilterKind::None | FilterKind::Phosphor => 0,
+            FilterKind::Blend => self.settings.blend_frames.max(1) - 1,
+            FilterKind::Deflicker => 1,
+        };
+
+        let mut changed = false;
+        for (i, px) in self.pixels.iter_mut().enumerate() {
+            let value = frame[i];
+            let filtered = match self.kind {
+                FilterKind::None => lit_px(value),
+                FilterKind::Phosphor if value > 0 => lit_px(value),
+                FilterKind::Phosphor => {
+                    let intensity = px.intensity * self.settings.phosphor_decay;
+                    FilteredPx {
+                        value: px.value,
+                        // too dim to tell from the background, so the fade ends
+                        intensity: if intensity < MIN_INTENSITY {
+                            0.0
+                        } else {
+                            intensity
+                        },
+                    }
+                }
+                FilterKind::Blend => {
+                    let past = self.history.iter().take(history_len).map(|f| f[i]);
+                    let lit = past.clone().filter(|v| *v > 0).count() + (value > 0) as usize;
+                    FilteredPx {
+                        value: latest_lit(value, past),
+                        intensity: lit as f32 / (history_len + 1) as f32,
+                    }
+                }
+                FilterKind::Deflicker => {
+                    let past = self.history.front().map(|f| f[i]).unwrap_or(0);
+                    lit_px(latest_lit(value, [past].into_iter()))
+                }
+            };
+            changed |= filtered != *px;
+            *px = filtered;
+        }
+
+        // the history only stays the same when it's all this frame
+        let history_settled = self.history.len() == history_len
+            && self.history.iter().all(|past| past.as_slice() == frame);
+        self.settled = !changed && history_settled;
+        if history_len > 0 {
+            self.history.truncate(history_len - 1);
+            self.history.push_front(frame.to_vec());
+        }
+        &self.pixels
+    }
+}
+
+fn lit_px(value: u8) -> FilteredPx {
+    FilteredPx {
+        value,
+        intensity: if value > 0 { 1.0 } else { 0.0 },
+    }
+}
+
+// value of the most recent frame in which the pixel was lit
+fn latest_lit(value: u8, mut past: impl Iterator<Item = u8>) -> u8 {
+    if value > 0 {
+        return value;
+    }
+    past.find(|v| *v > 0).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_kind_names_work() {
+        assert_eq!(FilterKind::from_name("blend"), Ok(FilterKind::Blend));
+        assert!(FilterKind::from_name("crt").is_err());
+        assert_eq!(FilterKind::Deflicker.next(), FilterKind::None);
+    }
+
+    #[test]
+    fn phosphor_filter_fades_out() {
+        let settings = FilterSettings {
+            phosphor_decay: 0.5,
+            ..FilterSettings::default()
+        };
+        let mut filter = FrameFilter::new(FilterKind::Phosphor, settings);
+
+        assert_eq!(filter.apply(&[2, 0])[0], lit_px(2));
+        let faded = filter.apply(&[0, 0])[0];
+        assert_eq!(faded.value, 2);
+        assert_eq!(faded.intensity, 0.5);
+        assert_eq!(filter.apply(&[0, 0])[0].intensity, 0.25);
+        assert_eq!(filter.apply(&[1, 0])[0], lit_px(1));
+        assert_eq!(filter.apply(&[0, 0])[1].intensity, 0.0);
+        // it fades out completely, then the frames stop changing
+        for _ in 0..7 {
+            filter.apply(&[0, 0]);
+        }
+        assert!(!filter.is_settled());
+        assert_eq!(filter.apply(&[0, 0])[0].intensity, 0.0);
+        assert!(!filter.is_settled());
+        filter.apply(&[0, 0]);
+        assert!(filter.is_settled());
+    }
+
+    #[test]
+    fn blend_filter_averages_frames() {
+        let settings = FilterSettings {
+            blend_frames: 4,
+            ..FilterSettings::default()
+        };
+        let mut filter = FrameFilter::new(FilterKind::Blend, settings);
+
+        assert_eq!(filter.apply(&[1])[0].intensity, 0.25);
+        assert_eq!(filter.apply(&[0])[0].intensity, 0.25);
+        assert_eq!(filter.apply(&[1])[0].intensity, 0.5);
+        assert_eq!(filter.apply(&[1])[0].intensity, 0.75);
+        // the first frame drops out of the window
+        let px = filter.apply(&[0])[0];
+        assert_eq!(px.intensity, 0.5);
+        assert_eq!(px.value, 1);
+    }
+
+    #[test]
+    fn deflicker_filter_keeps_last_two_frames() {
+        let mut filter = FrameFilter::new(FilterKind::Deflicker, FilterSettings::default());
+
+        assert_eq!(filter.apply(&[1, 0]), &[lit_px(1), lit_px(0)]);
+        assert_eq!(filter.apply(&[0, 0]), &[lit_px(1), lit_px(0)]);
+        assert_eq!(filter.apply(&[0, 3]), &[lit_px(0), lit_px(3)]);
+
+        assert!(!filter.is_settled());
+        filter.apply(&[0, 3]);
+        assert!(filter.is_settled());
+
+        assert_eq!(filter.cycle(), FilterKind::None);
+        assert!(!filter.is_settled());
+        assert_eq!(filter.apply(&[0, 0]), &[lit_px(0), lit_px(0)]);
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, HashMap};
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+
+use crate::settings::DEFAULT_PAD_MAPPING;
+
+// directions of the left stick are treated like the D-pad
+const STICK_DIRECTIONS: [(Axis, bool, Button); 4] = [
+    (Axis::LeftY, false, Button::DPadUp),
+    (Axis::LeftY, true, Button::DPadDown),
+    (Axis::LeftX, false, Button::DPadLeft),
+    (Axis::LeftX, true, Button::DPadRight),
+];
+
+/// Maps gamepad buttons onto the 16 keys of the CHIP-8 keypad
+#[derive(Debug, PartialEq)]
+pub struct PadMapping {
+    buttons: HashMap<Button, usize>,
+}
+
+impl PadMapping {
+    /// Builds a mapping from the built-in presets:
+    /// - `default`: D-pad as 5/7/8/9, which most games use for movement
+    /// - `numeric`: D-pad as 2/4/6/8, for games following the numeric keypad layout
+    ///
+    /// Both map the face buttons to 6/4/1/C, the shoulder buttons
+    /// to A/B, start to F and back to 0.
+    pub fn preset(name: &str) -> Result<PadMapping, String> {
+        let mut buttons = vec![
+            (Button::A, 0x6),
+            (Button::B, 0x4),
+            (Button::X, 0x1),
+            (Button::Y, 0xC),
+            (Button::LeftShoulder, 0xA),
+            (Button::RightShoulder, 0xB),
+            (Button::Start, 0xF),
+            (Button::Back, 0x0),
+        ];
+        match name {
+            DEFAULT_PAD_MAPPING => buttons.extend([
+                (Button::DPadUp, 0x5),
+                (Button::DPadLeft, 0x7),
+                (Button::DPadDown, 0x8),
+                (Button::DPadRight, 0x9),
+            ]),
+            "numeric" => buttons.extend([
+                (Button::DPadUp, 0x2),
+                (Button::DPadLeft, 0x4),
+                (Button::DPadRight, 0x6),
+                (Button::DPadDown, 0x8),
+            ]),
+            _ => {
+                return Err(format!(
+                    "Unknown pad mapping \"{}\", available mappings are: default, numeric",
+                    name
+                ))
+            }
+        }
+        Ok(PadMapping {
+            buttons: buttons.into_iter().collect(),
+        })
+    }
+
+    /// Builds a mapping from a config file section, which maps SDL button
+    /// names (e.g. "a", "dpup" or "leftshoulder") onto CHIP-8 keys
+    pub fn from_bindings(bindings: &BTreeMap<String, u8>) -> Result<PadMapping, String> {
+        let mut buttons = HashMap::new();
+        for (button_name, key) in bindings.iter() {
+            let button = match Button::from_string(button_name) {
+                Some(button) => button,
+                None => return Err(format!("Unknown button \"{}\" in pad mapping", button_name)),
+            };
+            if *key > 0xF {
+                return Err(format!(
+                    "Button \"{}\" is bound to {:#X}, CHIP-8 keys go from 0x0 to 0xF",
+                    button_name, key
+                ));
+            }
+            buttons.insert(button, *key as usize);
+        }
+        Ok(PadMapping { buttons })
+    }
+
+    /// Looks up a mapping by name, custom mappings from the config file
+    /// take precedence over the presets
+    pub fn named(
+        name: &str,
+        custom: &BTreeMap<String, BTreeMap<String, u8>>,
+    ) -> Result<PadMapping, String> {
+        match custom.get(name) {
+            Some(bindings) => PadMapping::from_bindings(bindings),
+            None => PadMapping::preset(name),
+        }
+    }
+
+    /// Binds the D-pad, A and B to the CHIP-8 keys a ROM uses for the roles
+    /// "up", "down", "left", "right", "a" and "b"
+    pub fn bind_roles(&mut self, roles: &BTreeMap<String, u8>) {
+        let role_buttons = [
+            ("up", Button::DPadUp),
+            ("down", Button::DPadDown),
+            ("left", Button::DPadLeft),
+            ("right", Button::DPadRight),
+            ("a", Button::A),
+            ("b", Button::B),
+        ];
+        for (role, button) in role_buttons {
+            if let Some(key) = roles.get(role).filter(|key| **key <= 0xF) {
+                self.buttons.insert(button, *key as usize);
+            }
+        }
+    }
+
+    /// Works out which CHIP-8 keys are held, given the state of a pad's
+    /// buttons and axes. A stick direction counts as held once the axis
+    /// is deflected further than `threshold` (0.0 - 1.0).
+    pub fn key_down(
+        &self,
+        button: impl Fn(Button) -> bool,
+        axis: impl Fn(Axis) -> i16,
+        threshold: f32,
+    ) -> [bool; 16] {
+        let mut key_down = [false; 16];
+        for (pad_button, key) in self.buttons.iter() {
+            if button(*pad_button) {
+                key_down[*key] = true;
+            }
+        }
+        let threshold = (threshold.clamp(0.0, 1.0) * i16::MAX as f32) as i32;
+        for (stick_axis, positive, dpad_button) in STICK_DIRECTIONS.iter() {
+            let value = axis(*stick_axis) as i32;
+            let deflected = if *positive {
+                value > threshold
+            } else {
+                value < -threshold
+            };
+            if let (true, Some(key)) = (deflected, self.buttons.get(dpad_button)) {
+                key_down[*key] = true;
+            }
+        }
+        key_down
+    }
+}
+
+/// The connected game controllers. Controllers are opened and closed as
+/// they're plugged in and out, including the ones connected at startup
+/// since SDL reports those as added too.
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    pads: HashMap<u32, GameController>,
+    mapping: PadMapping,
+    stick_threshold: f32,
+}
+
+impl Gamepads {
+    pub fn new(
+        subsystem: GameControllerSubsystem,
+        mapping: PadMapping,
+        stick_threshold: f32,
+    ) -> Gamepads {
+        Gamepads {
+            subsystem,
+            pads: HashMap::new(),
+            mapping,
+            stick_threshold,
+        }
+    }
+
+    pub fn set_mapping(&mut self, mapping: PadMapping) {
+        self.mapping = mapping;
+    }
+
+    /// Handles controllers being plugged in and out, other events are ignored
+    pub fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => match self.subsystem.open(*which) {
+                Ok(pad) => {
+                    println!("Connected controller {}", pad.name());
+                    self.pads.insert(pad.instance_id(), pad);
+                }
+                Err(err) => println!("Failed to open controller, error is \"{}\"", err),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(pad) = self.pads.remove(which) {
+                    println!("Disconnected controller {}", pad.name());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// CHIP-8 keys held on any of the connected controllers
+    pub fn key_down(&self) -> [bool; 16] {
+        let mut key_down = [false; 16];
+        for pad in self.pads.values() {
+            let pad_keys = self.mapping.key_down(
+                |button| pad.button(button),
+                |axis| pad.axis(axis),
+                self.stick_threshold,
+            );
+            for (key, is_down) in pad_keys.iter().enumerate() {
+                key_down[key] |= *is_down;
+            }
+        }
+        key_down
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held_keys(key_down: [bool; 16]) -> Vec<usize> {
+        (0..16).filter(|key| key_down[*key]).collect()
+    }
+
+    #[test]
+    fn presets_work() {
+        let mapping = PadMapping::preset(DEFAULT_PAD_MAPPING).unwrap();
+        let key_down = mapping.key_down(
+            |button| button == Button::DPadLeft || button == Button::A,
+            |_| 0,
+            0.5,
+        );
+        assert_eq!(held_keys(key_down), vec![0x6, 0x7]);
+
+        let mapping = PadMapping::preset("numeric").unwrap();
+        let key_down = mapping.key_down(|button| button == Button::DPadUp, |_| 0, 0.5);
+        assert_eq!(held_keys(key_down), vec![0x2]);
+
+        assert!(PadMapping::preset("snes").is_err());
+    }
+
+    #[test]
+    fn stick_directions_use_threshold() {
+        let mapping = PadMapping::preset(DEFAULT_PAD_MAPPING).unwrap();
+
+        // pushed halfway right and all the way up
+        let axis = |axis| match axis {
+            Axis::LeftX => i16::MAX / 2,
+            Axis::LeftY => i16::MIN,
+            _ => 0,
+        };
+        assert_eq!(
+            held_keys(mapping.key_down(|_| false, axis, 0.4)),
+            vec![0x5, 0x9]
+        );
+        assert_eq!(held_keys(mapping.key_down(|_| false, axis, 0.6)), vec![0x5]);
+        // the right stick isn't mapped
+        let axis = |axis| if axis == Axis::RightX { i16::MAX } else { 0 };
+        assert_eq!(
+            held_keys(mapping.key_down(|_| false, axis, 0.5)),
+            Vec::<usize>::new()
+        );
+    }
+
+    #[test]
+    fn bind_roles_works() {
+        let mut roles = BTreeMap::new();
+        roles.insert("left".to_string(), 0x4);
+        roles.insert("b".to_string(), 0xE);
+        let mut mapping = PadMapping::preset(DEFAULT_PAD_MAPPING).unwrap();
+        mapping.bind_roles(&roles);
+        let key_down = mapping.key_down(
+            |button| button == Button::DPadLeft || button == Button::B,
+            |_| 0,
+            0.5,
+        );
+        assert_eq!(held_keys(key_down), vec![0x4, 0xE]);
+    }
+
+    #[test]
+    fn from_bindings_works() {
+        let mut bindings = BTreeMap::new();
+        bindings.insert("dpup".to_string(), 0x2);
+        bindings.insert("rightshoulder".to_string(), 0xE);
+        let mapping = PadMapping::from_bindings(&bindings).unwrap();
+        let key_down = mapping.key_down(|_| true, |_| 0, 0.5);
+        assert_eq!(held_keys(key_down), vec![0x2, 0xE]);
+
+        let mut custom = BTreeMap::new();
+        custom.insert("mine".to_string(), bindings.clone());
+        assert_eq!(PadMapping::named("mine", &custom), Ok(mapping));
+
+        bindings.insert("turbo".to_string(), 0x1);
+        assert!(PadMapping::from_bindings(&bindings).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use std::rc::Rc;
+
+use crate::chip8::{decode_at, execute_instruction, next_pc, Chip8, Instruction};
+
+// most blocks are a lot shorter, this keeps invalidation cheap
+const MAX_BLOCK_LENGTH: usize = 32;
+
+/// An instruction compiled into a closure, that knows its operands. Fails
+/// like `execute_instruction` does.
+type Op = Box<dyn Fn(&mut Chip8) -> Result<(), String>>;
+
+/// Instructions run one after the other from the start, only the last one
+/// can jump, skip, wait for a key, draw or write memory
+struct Block {
+    ops: Vec<Op>,
+    /// the instructions the ops were compiled from, for profiles
+    instructions: Vec<Instruction>,
+}
+
+impl Block {
+    fn bytes(&self) -> usize {
+        self.ops.len() * 2
+    }
+}
+
+/// Runs programs compiled into closures, a block of instructions at a time.
+/// Blocks are compiled the first time they're run and dropped when the
+/// memory they were compiled from is written, so self-modifying code works.
+/// Timers only change between frames, so they don't need blocks to end.
+pub struct Jit {
+    /// compiled blocks, by the address they start at
+    blocks: Vec<Option<Rc<Block>>>,
+}
+
+impl Jit {
+    pub fn new(memory_size: usize) -> Jit {
+        Jit {
+            blocks: vec![None; memory_size],
+        }
+    }
+
+    /// Forgets the blocks that overlap the bytes written
+    pub fn invalidate(&mut self, start: usize, len: usize) {
+        let end = (start + len).min(self.blocks.len());
+        let first = start.saturating_sub(MAX_BLOCK_LENGTH * 2 - 1).min(end);
+        for (address, entry) in self.blocks[first..end].iter_mut().enumerate() {
+            let overlaps = match entry {
+                Some(block) => first + address + block.bytes() > start,
+                None => false,
+            };
+            if overlaps {
+                *entry = None;
+            }
+        }
+    }
+
+    /// The block starting at the address, compiled unless it was before
+    fn block(&mut self, memory: &[u8], address: u16) -> Result<Rc<Block>, String> {
+        // there are no blocks at the end of memory, it fails like it does
+        // in the interpreter
+        if address as usize + 1 >= memory.len() {
+            return Err(decode_at(memory, address).unwrap_err());
+        }
+        if let Some(block) = &self.blocks[address as usize] {
+            return Ok(block.clone());
+        }
+        let block = Rc::new(compile(memory, address)?);
+        self.blocks[address as usize] = Some(block.clone());
+        Ok(block)
+    }
+}
+
+/// Runs up to `budget` instructions, the same way the interpreter would.
+/// Stops before an instruction when `stop` says so for its address, and after
+/// a sprite was drawn with the vblank quirk. Gives the number of instructions
+/// run and whether it stopped.
+pub fn run(
+    chip8: &mut Chip8,
+    budget: u32,
+    stop: &mut impl FnMut(u16) -> bool,
+) -> Result<(u32, bool), String> {
+    let mut executed = 0;
+    while executed < budget {
+        let start = chip8.regs.pc;
+        // checked before compiling, a breakpoint can be on an instruction
+        // that doesn't decode
+        if stop(start) {
+            return Ok((executed, true));
+        }
+        let jit = chip8.jit.as_mut().ok_or("The JIT isn't set up")?;
+        let block = jit.block(&chip8.memory, start)?;
+        for (i, (op, instruction)) in block.ops.iter().zip(&block.instructions).enumerate() {
+            // blocks end before the end of memory, so this fits
+            let address = (start as usize + i * 2) as u16;
+            if address != start {
+                if executed == budget {
+                    break;
+                }
+                if stop(address) {
+                    return Ok((executed, true));
+                }
+            }
+            // set before the instruction like the interpreter does, so calls,
+            // skips and key waits see it and it's right when the block is left
+            chip8.regs.pc = next_pc(address, instruction)?;
+            if let Some(profile) = chip8.profile.as_mut() {
+                profile.record(address, instruction);
+            }
+            if let Err(err) = op(chip8) {
+                chip8.regs.pc = address;
+                return Err(format!("{} at {:03X}", err, address));
+            }
+            executed += 1;
+        }
+        if chip8.vblank_wait {
+            break;
+        }
+    }
+    Ok((executed, false))
+}
+
+/// Compiles the instructions from the address up to the first one that ends
+/// a block. Fails when the first instruction can't be decoded, when a later
+/// one can't the block ends before it and the error comes when it's reached.
+fn compile(memory: &[u8], start: u16) -> Result<Block, String> {
+    let mut ops = Vec::new();
+    let mut instructions = Vec::new();
+    let mut address = start;
+    while ops.len() < MAX_BLOCK_LENGTH {
+        let instruction = match decode_at(memory, address) {
+            Ok(instruction) => instruction,
+            Err(_) if !ops.is_empty() => break,
+            Err(err) => return Err(err),
+        };
+        ops.push(compile_op(instruction));
+        instructions.push(instruction);
+        if ends_block(&instruction) {
+            break;
+        }
+        // at the end of 64K of memory the block ends, and running on from
+        // its last instruction fails
+        address = match address.checked_add(2) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    Ok(Block { ops, instructions })
+}
+
+/// The most common instructions get closures of their own, the rest are run
+/// by the interpreter's code
+fn compile_op(instruction: Instruction) -> Op {
+    match instruction {
+        Instruction::Jmp { address } => Box::new(move |chip8| {
+            chip8.regs.pc = address;
+            Ok(())
+        }),
+        Instruction::Ld { reg, val } => Box::new(move |chip8| {
+            chip8.regs.general[reg as usize] = val;
+            Ok(())
+        }),
+        Instruction::Add { reg, val } => Box::new(move |chip8| {
+            let reg = &mut chip8.regs.general[reg as usize];
+            *reg = reg.wrapping_add(val);
+            Ok(())
+        }),
+        Instruction::LdReg { reg1, reg2 } => Box::new(move |chip8| {
+            chip8.regs.general[reg1 as usize] = chip8.regs.general[reg2 as usize];
+            Ok(())
+        }),
+        Instruction::Ldi { address } => Box::new(move |chip8| {
+            chip8.regs.i = address;
+            Ok(())
+        }),
+        _ => Box::new(move |chip8| execute_instruction(&instruction, chip8)),
+    }
+}
+
+/// Whether the instruction is the last of its block: where the next one is
+/// depends on the machine, it can end the frame, or it writes memory which
+/// may hold compiled code
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jmp { .. }
+            | Instruction::Call { .. }
+            | Instruction::Ret
+            | Instruction::JmpV0 { .. }
+            | Instruction::Se { .. }
+            | Instruction::Sne { .. }
+            | Instruction::SeReg { .. }
+            | Instruction::SneReg { .. }
+            | Instruction::Skp { .. }
+            | Instruction::SkpNp { .. }
+            | Instruction::Drw { .. }
+            | Instruction::LdKey { .. }
+            | Instruction::LdB { .. }
+            | Instruction::LdRegsMem { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::emulation_step_until;
+
+    #[test]
+    fn blocks_end_at_control_flow() {
+        let mut memory = vec![0; 16];
+        memory[2..12].copy_from_slice(&[
+            0x60, 0x05, // V0 = 5
+            0x70, 0x01, // V0 += 1
+            0x30, 0x06, // skip if V0 == 6
+            0x61, 0x01, // V1 = 1
+            0x01, 0x23, // unknown
+        ]);
+        let mut jit = Jit::new(memory.len());
+        assert_eq!(jit.block(&memory, 2).unwrap().ops.len(), 3);
+        // the unknown instruction is left out, and fails once it's reached
+        assert_eq!(jit.block(&memory, 8).unwrap().ops.len(), 1);
+        assert_eq!(
+            jit.block(&memory, 10).err(),
+            Some("Unknown instruction 0123 at 00A".to_string())
+        );
+        assert_eq!(
+            jit.block(&memory, 15).err(),
+            Some("program counter ran past the end of memory (00F)".to_string())
+        );
+    }
+
+    #[test]
+    fn running_past_the_end_of_memory_fails() {
+        // jumps to the last byte, falls off the end, jumps beyond a small
+        // memory, falls off the end of 64K
+        let programs: [(usize, &[(usize, u8)]); 4] = [
+            (0x1000, &[(0x200, 0x1F), (0x201, 0xFF)]),
+            (0x1000, &[(0x200, 0x1F), (0x201, 0xFE), (0xFFE, 0x60)]),
+            (0x800, &[(0x200, 0x1F), (0x201, 0xFF)]),
+            (0x10000, &[(0xFFFA, 0x60), (0xFFFC, 0x60), (0xFFFE, 0x60)]),
+        ];
+        // where they start and how they fail
+        let runs = [
+            (0x200, "program counter ran past the end of memory (FFF)"),
+            (0x200, "program counter ran past the end of memory (1000)"),
+            (0x200, "program counter ran past the end of memory (FFF)"),
+            (
+                0xFFFA,
+                "program counter ran past the end of memory (10000) at FFFE",
+            ),
+        ];
+        for ((memory_size, bytes), (start, error)) in programs.into_iter().zip(runs) {
+            let results: Vec<Result<(u32, bool), String>> = ["cache", "no cache", "jit"]
+                .into_iter()
+                .map(|engine| {
+                    let mut chip8 = Chip8::with_memory_size(memory_size);
+                    for (address, byte) in bytes {
+                        chip8.memory[*address] = *byte;
+                    }
+                    chip8.regs.pc = start;
+                    match engine {
+                        "no cache" => chip8.instruction_cache = None,
+                        "jit" => chip8.jit = Some(Jit::new(memory_size)),
+                        _ => {}
+                    }
+                    emulation_step_until(&mut chip8, 10, |_| false)
+                })
+                .collect();
+            for result in results {
+                assert_eq!(result, Err(error.to_string()));
+            }
+        }
+    }
+
+    #[test]
+    fn invalidate_drops_overlapping_blocks() {
+        let mut memory = vec![0; 16];
+        memory[2..10].copy_from_slice(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02, 0x00, 0xE0]);
+        let mut jit = Jit::new(memory.len());
+        for address in [2, 4, 8] {
+            jit.block(&memory, address).unwrap();
+        }
+        // the last byte of the blocks at 2 and 4
+        jit.invalidate(7, 1);
+        assert!(jit.blocks[2].is_none());
+        assert!(jit.blocks[4].is_none());
+        assert!(jit.blocks[8].is_some());
+
+        // writes running past the end of memory are cut off
+        jit.invalidate(15, 4);
+        jit.invalidate(20, 4);
+        assert!(jit.blocks[8].is_some());
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, HashMap};
+
+pub const DEFAULT_KEYMAP: &str = "default";
+
+// the names of the keys that can be mapped, the names SDL gives their
+// scancodes. The longer ones are kept apart so the list stays compact.
+const KEY_NAMES: [&str; 93] = [
+    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S",
+    "T", "U", "V", "W", "X", "Y", "Z", "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "Return",
+    "Escape", "Tab", "Space", "-", "=", "[", "]", "\\", ";", "'", "`", ",", ".", "/", "CapsLock",
+    "F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10", "F11", "F12", "Pause", "Insert",
+    "Home", "PageUp", "Delete", "End", "PageDown", "Right", "Left", "Down", "Up", "Numlock",
+    "Keypad /", "Keypad *", "Keypad -", "Keypad +", "Keypad 1", "Keypad 2", "Keypad 3", "Keypad 4",
+    "Keypad 5", "Keypad 6", "Keypad 7", "Keypad 8", "Keypad 9", "Keypad 0", "Keypad .", "Left Alt",
+    "Left GUI",
+];
+const LONG_KEY_NAMES: [&str; 10] = [
+    "Backspace",
+    "PrintScreen",
+    "ScrollLock",
+    "Keypad Enter",
+    "Left Ctrl",
+    "Left Shift",
+    "Right Ctrl",
+    "Right Shift",
+    "Right Alt",
+    "Right GUI",
+];
+
+/// A key of the keyboard, by where it is on a US keyboard. Keys are named
+/// like SDL names scancodes, the window and the terminal both turn what they
+/// get into keys, so keymaps work the same in both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Key(&'static str);
+
+impl Key {
+    pub const UP: Key = Key("Up");
+    pub const DOWN: Key = Key("Down");
+    pub const LEFT: Key = Key("Left");
+    pub const RIGHT: Key = Key("Right");
+    pub const SPACE: Key = Key("Space");
+    pub const RETURN: Key = Key("Return");
+
+    /// The key with the name, which doesn't depend on case
+    pub fn from_name(name: &str) -> Option<Key> {
+        KEY_NAMES
+            .iter()
+            .chain(&LONG_KEY_NAMES)
+            .find(|known| known.eq_ignore_ascii_case(name))
+            .map(|known| Key(known))
+    }
+}
+
+/// Maps keyboard keys onto the 16 keys of the CHIP-8 keypad.
+/// Keys are matched by position, so a keymap refers to physical keys and
+/// stays the same on AZERTY, Dvorak etc. keyboards.
+#[derive(Debug, PartialEq)]
+pub struct Keymap {
+    buttons: HashMap<Key, usize>,
+}
+
+impl Keymap {
+    /// Builds a keymap from the built-in presets:
+    /// - `default`: 1234/QWER/ASDF/ZXCV, laid out like the COSMAC VIP keypad
+    /// - `keypad`: numeric keypad, digits map onto the same CHIP-8 keys
+    /// - `arrows`: the default layout with arrow keys as 2/4/6/8 and space as 5
+    pub fn preset(name: &str) -> Result<Keymap, String> {
+        let default = [
+            (Key("1"), 0x1),
+            (Key("2"), 0x2),
+            (Key("3"), 0x3),
+            (Key("4"), 0xC),
+            (Key("Q"), 0x4),
+            (Key("W"), 0x5),
+            (Key("E"), 0x6),
+            (Key("R"), 0xD),
+            (Key("A"), 0x7),
+            (Key("S"), 0x8),
+            (Key("D"), 0x9),
+            (Key("F"), 0xE),
+            (Key("Z"), 0xA),
+            (Key("X"), 0x0),
+            (Key("C"), 0xB),
+            (Key("V"), 0xF),
+        ];
+        let buttons: Vec<(Key, usize)> = match name {
+            DEFAULT_KEYMAP => default.to_vec(),
+            "keypad" => vec![
+                (Key("Keypad 0"), 0x0),
+                (Key("Keypad 1"), 0x1),
+                (Key("Keypad 2"), 0x2),
+                (Key("Keypad 3"), 0x3),
+                (Key("Keypad 4"), 0x4),
+                (Key("Keypad 5"), 0x5),
+                (Key("Keypad 6"), 0x6),
+                (Key("Keypad 7"), 0x7),
+                (Key("Keypad 8"), 0x8),
+                (Key("Keypad 9"), 0x9),
+                (Key("Keypad /"), 0xA),
+                (Key("Keypad *"), 0xB),
+                (Key("Keypad -"), 0xC),
+                (Key("Keypad +"), 0xD),
+                (Key("Keypad Enter"), 0xE),
+                (Key("Keypad ."), 0xF),
+            ],
+            "arrows" => {
+                let mut buttons = default.to_vec();
+                buttons.extend([
+                    (Key::UP, 0x2),
+                    (Key::LEFT, 0x4),
+                    (Key::RIGHT, 0x6),
+                    (Key::DOWN, 0x8),
+                    (Key::SPACE, 0x5),
+                ]);
+                buttons
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown keymap \"{}\", available keymaps are: default, keypad, arrows",
+                    name
+                ))
+            }
+        };
+        Ok(Keymap {
+            buttons: buttons.into_iter().collect(),
+        })
+    }
+
+    /// Builds a keymap from a config file section, which maps key names
+    /// (US layout, e.g. "Q", "Up" or "Keypad 8") onto CHIP-8 keys
+    pub fn from_bindings(bindings: &BTreeMap<String, u8>) -> Result<Keymap, String> {
+        let mut buttons = HashMap::new();
+        for (key_name, button) in bindings.iter() {
+            let key = match Key::from_name(key_name) {
+                Some(key) => key,
+                None => return Err(format!("Unknown key \"{}\" in keymap", key_name)),
+            };
+            if *button > 0xF {
+                return Err(format!(
+                    "Key \"{}\" is bound to {:#X}, CHIP-8 keys go from 0x0 to 0xF",
+                    key_name, button
+                ));
+            }
+            buttons.insert(key, *button as usize);
+        }
+        Ok(Keymap { buttons })
+    }
+
+    /// Looks up a keymap by name, custom keymaps from the config file take
+    /// precedence over the presets
+    pub fn named(
+        name: &str,
+        custom: &BTreeMap<String, BTreeMap<String, u8>>,
+    ) -> Result<Keymap, String> {
+        match custom.get(name) {
+            Some(bindings) => Keymap::from_bindings(bindings),
+            None => Keymap::preset(name),
+        }
+    }
+
+    /// Binds the arrow keys, space and left shift to the CHIP-8 keys a ROM
+    /// uses for the roles "up", "down", "left", "right", "a" and "b"
+    pub fn bind_roles(&mut self, roles: &BTreeMap<String, u8>) {
+        let role_keys = [
+            ("up", Key::UP),
+            ("down", Key::DOWN),
+            ("left", Key::LEFT),
+            ("right", Key::RIGHT),
+            ("a", Key::SPACE),
+            ("b", Key("Left Shift")),
+        ];
+        for (role, key) in role_keys {
+            if let Some(button) = roles.get(role).filter(|button| **button <= 0xF) {
+                self.buttons.insert(key, *button as usize);
+            }
+        }
+    }
+
+    pub fn button(&self, key: Key) -> Option<usize> {
+        self.buttons.get(&key).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_from_name_works() {
+        assert_eq!(Key::from_name("Up"), Some(Key::UP));
+        assert_eq!(Key::from_name("keypad 8"), Some(Key("Keypad 8")));
+        assert_eq!(Key::from_name("\\"), Some(Key("\\")));
+        assert_eq!(Key::from_name("Hyper"), None);
+        // the presets only use keys that have names
+        for name in ["default", "keypad", "arrows"] {
+            for key in Keymap::preset(name).unwrap().buttons.keys() {
+                assert_eq!(Key::from_name(key.0), Some(*key));
+            }
+        }
+    }
+
+    #[test]
+    fn presets_work() {
+        let keymap = Keymap::preset(DEFAULT_KEYMAP).unwrap();
+        assert_eq!(keymap.button(Key("4")), Some(0xC));
+        assert_eq!(keymap.button(Key("X")), Some(0x0));
+        assert_eq!(keymap.button(Key::UP), None);
+
+        let keymap = Keymap::preset("arrows").unwrap();
+        assert_eq!(keymap.button(Key::UP), Some(0x2));
+        assert_eq!(keymap.button(Key("V")), Some(0xF));
+
+        assert!(Keymap::preset("azerty").is_err());
+    }
+
+    #[test]
+    fn from_bindings_works() {
+        let mut bindings = BTreeMap::new();
+        bindings.insert("Keypad 8".to_string(), 0x2);
+        bindings.insert("Space".to_string(), 0xA);
+        let keymap = Keymap::from_bindings(&bindings).unwrap();
+        assert_eq!(keymap.button(Key("Keypad 8")), Some(0x2));
+        assert_eq!(keymap.button(Key::SPACE), Some(0xA));
+        assert_eq!(keymap.button(Key("Q")), None);
+
+        bindings.insert("Hyper".to_string(), 0x1);
+        assert!(Keymap::from_bindings(&bindings).is_err());
+        bindings.remove("Hyper");
+        bindings.insert("Q".to_string(), 0x10);
+        assert!(Keymap::from_bindings(&bindings).is_err());
+    }
+
+    #[test]
+    fn bind_roles_works() {
+        let mut roles = BTreeMap::new();
+        roles.insert("up".to_string(), 0x5);
+        roles.insert("a".to_string(), 0x6);
+        roles.insert("player2Up".to_string(), 0x1);
+        let mut keymap = Keymap::preset(DEFAULT_KEYMAP).unwrap();
+        keymap.bind_roles(&roles);
+        assert_eq!(keymap.button(Key::UP), Some(0x5));
+        assert_eq!(keymap.button(Key::SPACE), Some(0x6));
+        assert_eq!(keymap.button(Key::DOWN), None);
+        assert_eq!(keymap.button(Key("W")), Some(0x5));
+    }
+
+    #[test]
+    fn named_prefers_custom_keymaps() {
+        let mut custom = BTreeMap::new();
+        let mut bindings = BTreeMap::new();
+        bindings.insert("Return".to_string(), 0x5);
+        custom.insert("arrows".to_string(), bindings);
+
+        let keymap = Keymap::named("arrows", &custom).unwrap();
+        assert_eq!(keymap.button(Key::RETURN), Some(0x5));
+        assert_eq!(keymap.button(Key::UP), None);
+        assert_eq!(Keymap::named("keypad", &custom), Keymap::preset("keypad"));
+    }
+}
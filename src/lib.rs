@@ -0,0 +1,8 @@
+//! The CHIP-8 machine and the engines running it. They're a library of their
+//! own, used by the binary, so they can be benchmarked (see `benches/core.rs`).
+
+pub mod cache;
+pub mod chip8;
+pub mod display;
+pub mod jit;
+pub mod profile;
@@ -1,168 +1,846 @@
-mod chip8;
+// without SDL the parts only the window uses, like the palette blending or
+// the waiting screen, are left unused
+#![cfg_attr(not(feature = "sdl"), allow(dead_code))]
 
+mod asm;
+#[cfg(feature = "sdl")]
+mod browser;
+mod cartridge;
+mod cli;
+mod config;
+mod coverage;
+#[cfg(feature = "sdl")]
+mod debugger;
+mod disasm;
+#[cfg(feature = "sdl")]
+mod filter;
+#[cfg(feature = "sdl")]
+mod gamepad;
+mod keymap;
+mod octo;
+#[cfg(feature = "sdl")]
+mod osd;
+#[cfg(feature = "sdl")]
+mod overlay;
+mod palette;
+mod platform;
+mod rom;
+mod romdb;
+mod runner;
+#[cfg(feature = "sdl")]
+mod screen;
+mod settings;
+mod symbols;
+mod tui;
+mod waiting;
+#[cfg(feature = "sdl")]
+mod window;
+
+#[cfg(feature = "sdl")]
 extern crate sdl2;
 
 use std::env;
 use std::io::Error;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-use chip8::{
-    decode_instruction, execute_instruction, fetch_instruction, Chip8, SCREEN_HEIGHT, SCREEN_WIDTH,
-};
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use std::time::Duration;
-
-const COLOR_OFF: Color = Color::RGB(0x10, 0x1D, 0x42);
-const COLOR_ON: Color = Color::RGB(0xF7, 0x87, 0x64);
-const DISP_SCALE: u32 = 8;
-
-fn main() {
-    println!("");
-    println!("CHIP-8");
-    println!("");
-    println!("welcome to CHIP-8 ツ");
-    println!("");
+use cartridge::Cartridge;
+use chip8::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip_8::{chip8, display, jit, profile};
+use cli::{Command, Options};
+use config::{Config, RomConfig};
+#[cfg(feature = "sdl")]
+use filter::{FilterKind, FrameFilter};
+#[cfg(feature = "sdl")]
+use gamepad::PadMapping;
+use keymap::Keymap;
+use palette::Palettes;
+use profile::Profile;
+use romdb::RomDb;
+use runner::{make_chip8, window_title, Engine, NullFrontend, Program, Runner};
+#[cfg(feature = "sdl")]
+use screen::{ScaleMode, Screen};
+use settings::{Settings, DEFAULT_START_ADDRESS, ROM_PALETTE};
+use symbols::Symbols;
+use tui::TuiFrontend;
+#[cfg(feature = "sdl")]
+use window::SdlFrontend;
+
+// exit codes, listed in the help
+const EXIT_EMULATION_ERROR: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_FILE_ERROR: u8 = 3;
+const EXIT_INVALID_ROM: u8 = 4;
+
+// frames run by headless, bench and profile, 10 seconds worth
+const DEFAULT_FRAMES: u32 = 600;
+// addresses profile prints, the rest are in the reports
+const HOT_SPOTS: usize = 10;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match cli::parse_args(&args) {
+        Ok(command) => command,
+        Err(err) => {
+            println!("{}\n{}", err, cli::USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let config = match config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            println!("{}", err);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("No ROM path provided.\nUsage: chip8 <path-to-rom>");
-        return;
+    match command {
+        Command::Run { rom_path, options } => run(rom_path.as_deref(), &options, &config),
+        Command::Tui { rom_path, options } => tui(&rom_path, &options, &config),
+        Command::Headless { rom_path, options } => headless(&rom_path, &options, &config),
+        Command::Bench { rom_path, options } => bench(&rom_path, &options, &config),
+        Command::Profile { rom_path, options } => profile(&rom_path, &options, &config),
+        Command::Disasm { rom_path, options } => disasm(&rom_path, &options),
+        Command::Asm {
+            source_path,
+            options,
+        } => asm(&source_path, &options),
+        Command::Info { rom_path } => info(&rom_path, &config),
+        Command::ConfigDump { rom_path, options } => config_dump(rom_path, &options, &config),
+        Command::Help => {
+            println!("{}\n\n{}", cli::USAGE, cli::HELP);
+            ExitCode::SUCCESS
+        }
+        Command::Version => {
+            println!("chip8 {}", env!("CARGO_PKG_VERSION"));
+            ExitCode::SUCCESS
+        }
     }
-    let file_path = &args[1];
+}
+
+#[cfg(feature = "sdl")]
+fn run(rom_path: Option<&str>, options: &Options, config: &Config) -> ExitCode {
+    println!();
+    println!("CHIP-8");
+    println!();
+    println!("welcome to CHIP-8 ツ");
+    println!();
 
     let path = env::current_dir().unwrap();
     println!("The current directory is {}", path.display());
 
-    println!("Loading ROM at path {}", file_path);
-    let rom = match load_file(&file_path) {
-        Ok(data) => data,
+    let loaded = match rom_path {
+        Some(rom_path) => {
+            println!("Loading ROM at path {}", rom_path);
+            load_program(rom_path, options, config)
+        }
+        None => {
+            println!("No ROM given, drop one on the window to play it");
+            waiting_program(options, config)
+        }
+    };
+    let program = match loaded {
+        Ok(program) => program,
+        Err(code) => return code,
+    };
+    let rom_frontend = match RomFrontend::new(&program.settings, config) {
+        Ok(frontend) => frontend,
         Err(err) => {
-            println!(
-                "Failed to open ROM at path {}, error is \"{}\"",
-                file_path, err
-            );
-            return;
+            println!("{}", err);
+            return ExitCode::from(EXIT_USAGE);
         }
     };
 
-    let mut chip8 = make_chip8();
-    chip8.load_into_mem(&rom, 0x200);
+    let scale_mode = match ScaleMode::from_name(&program.settings.scale_mode) {
+        Ok(mode) => mode,
+        Err(err) => {
+            println!("{}", err);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    let settings = &program.settings;
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let window = video_subsystem
         .window(
-            "chip-8",
-            SCREEN_WIDTH as u32 * DISP_SCALE,
-            SCREEN_HEIGHT as u32 * DISP_SCALE,
+            &window_title(rom_path),
+            SCREEN_WIDTH as u32 * settings.window_scale,
+            SCREEN_HEIGHT as u32 * settings.window_scale,
         )
         .position_centered()
-        .opengl()
+        .resizable()
         .build()
         .unwrap();
 
-    let mut canvas = window.into_canvas().build().unwrap();
-
-    canvas.set_draw_color(COLOR_OFF);
-    canvas.clear();
-    canvas.present();
-
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    'running: loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => break 'running,
-                Event::KeyDown {
-                    keycode: Some(keycode),
-                    ..
-                } => {
-                    if let Some(key) = keycode_to_button(keycode) {
-                        chip8.key_down[key] = true;
-                    }
-                }
-                Event::KeyUp {
-                    keycode: Some(keycode),
-                    ..
-                } => {
-                    if let Some(key) = keycode_to_button(keycode) {
-                        chip8.key_down[key] = false;
-                    }
-                }
-                _ => {}
+    let canvas = window.into_canvas().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+    let mut screen = Screen::new(canvas, &texture_creator, scale_mode).unwrap();
+    if settings.fullscreen {
+        screen.set_fullscreen(true).unwrap();
+    }
+
+    let mut frontend = SdlFrontend::new(
+        &sdl_context,
+        screen,
+        rom_frontend,
+        settings,
+        options,
+        config,
+    )
+    .unwrap();
+    match rom_path {
+        Some(rom_path) => frontend.record_played(rom_path),
+        // without a ROM to play, there's one to pick from the ROM directory
+        None if config.rom_dir.is_some() => frontend.open_browser(),
+        None => {}
+    }
+    let mut runner = Runner::new(frontend, program);
+    let result = runner.run();
+    finish(result, &runner.program)
+}
+
+/// Without SDL there's no window to play in, only the terminal
+#[cfg(not(feature = "sdl"))]
+fn run(_rom_path: Option<&str>, _options: &Options, _config: &Config) -> ExitCode {
+    println!("chip8 was built without SDL, play in the terminal with \"chip8 tui\"");
+    ExitCode::from(EXIT_USAGE)
+}
+
+/// Runs a ROM in the terminal, drawing the display with half blocks and
+/// ringing the bell for the sound
+fn tui(rom_path: &str, options: &Options, config: &Config) -> ExitCode {
+    let program = match load_program(rom_path, options, config) {
+        Ok(program) => program,
+        Err(code) => return code,
+    };
+    let rom_frontend = match RomFrontend::new(&program.settings, config) {
+        Ok(frontend) => frontend,
+        Err(err) => {
+            println!("{}", err);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    let status = format!("{} - Esc to quit", window_title(Some(rom_path)));
+    let frontend = match TuiFrontend::new(rom_frontend.palettes, rom_frontend.keymap, status) {
+        Ok(frontend) => frontend,
+        Err(err) => {
+            println!("{}", err);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    let mut runner = Runner::new(frontend, program);
+    let result = runner.run();
+    // the terminal is restored when the frontend is dropped, before an error
+    // is printed
+    let program = runner.program;
+    drop(runner.frontend);
+    finish(result, &program)
+}
+
+/// Runs a ROM for some frames without a window or input, then prints the
+/// display. Handy for checking test ROMs from scripts.
+fn headless(rom_path: &str, options: &Options, config: &Config) -> ExitCode {
+    let program = match load_program(rom_path, options, config) {
+        Ok(program) => program,
+        Err(code) => return code,
+    };
+    let frames = options.frames.unwrap_or(DEFAULT_FRAMES);
+    let mut runner = Runner::new(NullFrontend::new(frames), program);
+    if let Err(err) = runner.run() {
+        return finish(Err(err), &runner.program);
+    }
+    let chip8 = &runner.program.chip8;
+    for y in 0..SCREEN_HEIGHT {
+        let row: String = (0..SCREEN_WIDTH)
+            .map(|x| if chip8.get_px(x, y) > 0 { '#' } else { '.' })
+            .collect();
+        println!("{}", row);
+    }
+    ExitCode::SUCCESS
+}
+
+/// Runs a ROM as fast as possible, without a window or input, and prints
+/// how fast that was. It's run a second time decoding every instruction, to
+/// show what the instruction cache is worth.
+fn bench(rom_path: &str, options: &Options, config: &Config) -> ExitCode {
+    let program = match load_program(rom_path, options, config) {
+        Ok(program) => program,
+        Err(code) => return code,
+    };
+    let frontend = || match options.seconds {
+        Some(seconds) => NullFrontend::timed(Duration::from_secs(seconds as u64)),
+        None => NullFrontend::new(options.frames.unwrap_or(DEFAULT_FRAMES)),
+    };
+    let mut runner = Runner::new(frontend(), program);
+    let seconds = match timed_run(&mut runner) {
+        Ok(seconds) => seconds,
+        Err(err) => return finish(Err(err), &runner.program),
+    };
+    let (frames, instructions) = (runner.frontend.frames, runner.frontend.instructions);
+    let ips = instructions as f64 / seconds;
+    println!(
+        "Ran {} frames, {} instructions in {:.3} s",
+        frames, instructions, seconds
+    );
+    println!(
+        "{:.0} instructions per second, {:.1}x real time",
+        ips,
+        frames as f64 / 60.0 / seconds
+    );
+
+    let mut program = runner.program;
+    if let Err(err) = program.reset(options.seed) {
+        println!("Invalid ROM {}: {}", rom_path, err);
+        return ExitCode::from(EXIT_INVALID_ROM);
+    }
+    program.chip8.instruction_cache = None;
+    program.chip8.jit = None;
+    let mut runner = Runner::new(frontend(), program);
+    let seconds = match timed_run(&mut runner) {
+        Ok(seconds) => seconds,
+        Err(err) => return finish(Err(err), &runner.program),
+    };
+    let uncached_ips = runner.frontend.instructions as f64 / seconds;
+    let engine = match runner.program.settings.engine {
+        Engine::Interpreter => "the cache",
+        Engine::Jit => "the JIT",
+    };
+    let ratio = ips / uncached_ips;
+    let comparison = if ratio >= 1.0 {
+        format!("{:.2}x faster", ratio)
+    } else {
+        format!("{:.2}x slower", 1.0 / ratio)
+    };
+    println!(
+        "{:.0} instructions per second decoding every instruction, {} is {}",
+        uncached_ips, engine, comparison
+    );
+    ExitCode::SUCCESS
+}
+
+/// Runs a ROM for some frames without a window or input, counting the
+/// instructions that run, then writes an annotated listing, a JSON report
+/// and a heat map of memory, and prints the hot spots. The reports are
+/// written when emulation fails too, they show how it got there.
+fn profile(rom_path: &str, options: &Options, config: &Config) -> ExitCode {
+    let mut program = match load_program(rom_path, options, config) {
+        Ok(program) => program,
+        Err(code) => return code,
+    };
+    program.chip8.profile = Some(Profile::new(program.chip8.memory.len()));
+    let frames = options.frames.unwrap_or(DEFAULT_FRAMES);
+    let mut runner = Runner::new(NullFrontend::new(frames), program);
+    let result = runner.run();
+
+    let program = &runner.program;
+    let profile = program.chip8.profile.as_ref().unwrap();
+    let (rom_start, rom_len) = (program.settings.start_address, program.rom.len());
+    let base = match &options.output {
+        Some(path) => path.clone(),
+        None => Path::new(rom_path)
+            .with_extension("profile")
+            .display()
+            .to_string(),
+    };
+    let reports = [
+        (
+            "lst",
+            coverage::listing(profile, &program.rom, rom_start, &program.symbols).into_bytes(),
+        ),
+        (
+            "json",
+            coverage::json_report(
+                profile,
+                &program.chip8.memory,
+                rom_start as usize,
+                rom_len,
+                &program.symbols,
+            )
+            .into_bytes(),
+        ),
+        (
+            "gif",
+            coverage::heat_map(profile, rom_start as usize, rom_len),
+        ),
+    ];
+    for (extension, report) in reports {
+        let path = format!("{}.{}", base, extension);
+        if let Err(err) = std::fs::write(&path, report) {
+            println!(
+                "Failed to write report to path {}, error is \"{}\"",
+                path, err
+            );
+            return ExitCode::from(EXIT_FILE_ERROR);
+        }
+    }
+
+    let covered = coverage::covered_bytes(profile, rom_start as usize, rom_len);
+    println!(
+        "Ran {} instructions, covering {} of {} ROM bytes ({:.1}%)",
+        profile.total(),
+        covered,
+        rom_len,
+        covered as f64 * 100.0 / rom_len.max(1) as f64
+    );
+    println!("Hot spots:");
+    for (address, runs) in profile.hot_spots(HOT_SPOTS) {
+        let instruction =
+            coverage::instruction_at(&program.chip8.memory, address, &program.symbols);
+        println!(
+            "{:>10}  {}: {}",
+            runs,
+            program.symbols.describe(address),
+            instruction
+        );
+    }
+    println!("Wrote {}.lst, {}.json and {}.gif", base, base, base);
+    finish(result, program)
+}
+
+/// Runs until the frontend quits, gives how many seconds that took
+fn timed_run(runner: &mut Runner<NullFrontend>) -> Result<f64, String> {
+    let start = Instant::now();
+    runner.run()?;
+    Ok(start.elapsed().as_secs_f64().max(f64::EPSILON))
+}
+
+/// The exit code for how a run ended, errors are reported with the calls
+/// the program was in
+fn finish(result: Result<(), String>, program: &Program) -> ExitCode {
+    let err = match result {
+        Ok(()) => return ExitCode::SUCCESS,
+        Err(err) => err,
+    };
+    println!("Emulation error: {}", err);
+    if !program.chip8.stack.is_empty() {
+        println!("Call stack, latest call first:");
+        for frame in program.chip8.stack.iter().rev() {
+            println!(
+                "  {} from {}",
+                program.symbols.describe(frame.target),
+                program.symbols.describe(frame.caller)
+            );
+        }
+    }
+    ExitCode::from(EXIT_EMULATION_ERROR)
+}
+
+/// Prints the instructions of a ROM
+fn disasm(rom_path: &str, options: &Options) -> ExitCode {
+    let (rom, symbols) = match read_rom(rom_path) {
+        Ok((rom, _, symbols)) => (rom, symbols),
+        Err(code) => return code,
+    };
+    let symbols = match load_symbols(rom_path, options, symbols) {
+        Ok(symbols) => symbols,
+        Err(code) => return code,
+    };
+    let start_address = options.start_address.unwrap_or(DEFAULT_START_ADDRESS);
+    print!("{}", disasm::disassemble(&rom, start_address, &symbols));
+    ExitCode::SUCCESS
+}
+
+/// Assembles a program into a ROM
+fn asm(source_path: &str, options: &Options) -> ExitCode {
+    let source = match std::fs::read_to_string(source_path) {
+        Ok(source) => source,
+        Err(err) => {
+            println!(
+                "Failed to open source at path {}, error is \"{}\"",
+                source_path, err
+            );
+            return ExitCode::from(EXIT_FILE_ERROR);
+        }
+    };
+    let start_address = options.start_address.unwrap_or(DEFAULT_START_ADDRESS);
+    let (rom, symbols) = match asm::assemble(&source, start_address) {
+        Ok(assembled) => assembled,
+        Err(err) => {
+            println!("{}: {}", source_path, err);
+            return ExitCode::from(EXIT_INVALID_ROM);
+        }
+    };
+    let output_path = match &options.output {
+        Some(path) => Path::new(path).to_path_buf(),
+        None => Path::new(source_path).with_extension("ch8"),
+    };
+    if output_path == Path::new(source_path) {
+        println!("Not overwriting the source, pick another path with --output");
+        return ExitCode::from(EXIT_USAGE);
+    }
+    if let Err(err) = std::fs::write(&output_path, &rom) {
+        println!(
+            "Failed to write ROM to path {}, error is \"{}\"",
+            output_path.display(),
+            err
+        );
+        return ExitCode::from(EXIT_FILE_ERROR);
+    }
+    println!("Wrote {} bytes to {}", rom.len(), output_path.display());
+    if !symbols.is_empty() {
+        let symbols_path = output_path.with_extension("sym");
+        if let Err(err) = std::fs::write(&symbols_path, symbols.to_text()) {
+            println!(
+                "Failed to write symbols to path {}, error is \"{}\"",
+                symbols_path.display(),
+                err
+            );
+            return ExitCode::from(EXIT_FILE_ERROR);
+        }
+        println!("Wrote symbols to {}", symbols_path.display());
+    }
+    ExitCode::SUCCESS
+}
+
+/// Reads a ROM, works out its settings and loads it into a fresh machine
+fn load_program(rom_path: &str, options: &Options, config: &Config) -> Result<Program, ExitCode> {
+    let (rom, cartridge_config, symbols) = read_rom(rom_path)?;
+    let symbols = load_symbols(rom_path, options, symbols)?;
+    let rom_db = load_database(config);
+    let settings = match rom_settings(
+        rom_path,
+        &rom,
+        cartridge_config.as_ref(),
+        &rom_db,
+        options,
+        config,
+    ) {
+        Ok((settings, title)) => {
+            if let Some(title) = title {
+                println!("Found \"{}\" in the ROM database", title);
             }
+            settings
+        }
+        Err(err) => {
+            println!("{}", err);
+            return Err(ExitCode::from(EXIT_USAGE));
+        }
+    };
+    if let Some(platform) = settings.platform {
+        println!("Emulating {}", platform.name());
+    }
+
+    let chip8 = match make_chip8(&rom, &settings, options.seed) {
+        Ok(chip8) => chip8,
+        Err(err) => {
+            println!("Invalid ROM {}: {}", rom_path, err);
+            return Err(ExitCode::from(EXIT_INVALID_ROM));
         }
+    };
+    for warning in rom::check(&rom) {
+        println!("Warning: {} {}", rom_path, warning);
+    }
+    Ok(Program {
+        rom,
+        settings,
+        chip8,
+        symbols,
+    })
+}
+
+/// The program shown until a ROM is dropped on the window, run with the
+/// global settings
+fn waiting_program(options: &Options, config: &Config) -> Result<Program, ExitCode> {
+    let mut settings = match Settings::resolve(config, None, None, 0, options) {
+        Ok(settings) => settings,
+        Err(err) => {
+            println!("{}", err);
+            return Err(ExitCode::from(EXIT_USAGE));
+        }
+    };
+    // it's compiled to run from the usual address
+    settings.start_address = DEFAULT_START_ADDRESS;
+    let rom = waiting::rom().and_then(|rom| {
+        let chip8 = make_chip8(&rom, &settings, options.seed)?;
+        Ok((rom, chip8))
+    });
+    match rom {
+        Ok((rom, chip8)) => Ok(Program {
+            rom,
+            settings,
+            chip8,
+            symbols: Symbols::default(),
+        }),
+        Err(err) => {
+            println!("Failed to load the waiting screen: {}", err);
+            Err(ExitCode::from(EXIT_INVALID_ROM))
+        }
+    }
+}
 
-        // clear screen
-        canvas.set_draw_color(COLOR_OFF);
-        canvas.clear();
+/// The parts of the frontend that depend on the settings of the ROM
+struct RomFrontend {
+    palettes: Palettes,
+    #[cfg(feature = "sdl")]
+    filter: FrameFilter,
+    keymap: Keymap,
+    #[cfg(feature = "sdl")]
+    pad_mapping: PadMapping,
+}
+
+impl RomFrontend {
+    fn new(settings: &Settings, config: &Config) -> Result<RomFrontend, String> {
+        let mut custom_palettes = config.palettes.clone();
+        if let Some(colors) = &settings.colors {
+            custom_palettes.insert(ROM_PALETTE.to_string(), colors.clone());
+        }
+        let mut palettes = Palettes::new(&custom_palettes)?;
+        palettes.select(&settings.palette)?;
+
+        #[cfg(feature = "sdl")]
+        let filter_kind = FilterKind::from_name(&settings.filter)?;
+
+        let mut keymap = Keymap::named(&settings.keymap, &config.keymaps)?;
+        keymap.bind_roles(&settings.key_roles);
+
+        #[cfg(feature = "sdl")]
+        let mut pad_mapping = PadMapping::named(&settings.pad_mapping, &config.pad_mappings)?;
+        #[cfg(feature = "sdl")]
+        pad_mapping.bind_roles(&settings.pad_roles);
 
-        emulation_step(&mut chip8);
+        Ok(RomFrontend {
+            palettes,
+            #[cfg(feature = "sdl")]
+            filter: FrameFilter::new(filter_kind, config.filters),
+            keymap,
+            #[cfg(feature = "sdl")]
+            pad_mapping,
+        })
+    }
+}
+
+/// Prints what the ROM database knows about a ROM
+fn info(rom_path: &str, config: &Config) -> ExitCode {
+    let rom = match read_rom(rom_path) {
+        Ok((rom, _, _)) => rom,
+        Err(code) => return code,
+    };
+    println!("ROM: {}", rom_path);
+    println!("Size: {} bytes", rom.len());
+    println!("SHA-1: {}", romdb::sha1_hex(&rom));
+    for warning in rom::check(&rom) {
+        println!("Warning: {} {}", rom_path, warning);
+    }
 
-        // draw new screen state
-        canvas.set_draw_color(COLOR_ON);
-        for i in 0..SCREEN_WIDTH {
-            for j in 0..SCREEN_HEIGHT {
-                let x = i as u32 * DISP_SCALE;
-                let y = j as u32 * DISP_SCALE;
-                if chip8.get_px(i as u8, j as u8) > 0 {
-                    canvas
-                        .fill_rect(Rect::new(x as i32, y as i32, DISP_SCALE, DISP_SCALE))
-                        .unwrap();
+    let rom_db = load_database(config);
+    let found = match rom_db.lookup(&rom) {
+        Some(found) => found,
+        None => {
+            println!("Not found in the ROM database");
+            return ExitCode::SUCCESS;
+        }
+    };
+
+    println!("Title: {}", found.program.title);
+    if !found.program.authors.is_empty() {
+        println!("Authors: {}", found.program.authors.join(", "));
+    }
+    if let Some(release) = &found.program.release {
+        println!("Release: {}", release);
+    }
+    if let Some(description) = &found.program.description {
+        println!("Description: {}", description);
+    }
+    if let Some(file) = &found.rom.file {
+        println!("File: {}", file);
+    }
+    if !found.rom.platforms.is_empty() {
+        println!("Platforms: {}", found.rom.platforms.join(", "));
+    }
+    match found.rom.platform() {
+        Some(platform) => {
+            println!("Emulated as: {} ({})", platform.name(), platform.id());
+            let mut quirks = platform.quirks();
+            found.rom.apply_quirks(platform, &mut quirks);
+            let enabled = [
+                ("shift", quirks.shift),
+                ("memory_increment_by_x", quirks.memory_increment_by_x),
+                ("memory_leave_i_unchanged", quirks.memory_leave_i_unchanged),
+                ("wrap", quirks.wrap),
+                ("jump", quirks.jump),
+                ("vblank", quirks.vblank),
+                ("logic", quirks.logic),
+            ]
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect::<Vec<&str>>();
+            println!("Quirks: {}", enabled.join(", "));
+        }
+        None => println!("Emulated as: none of its platforms are supported"),
+    }
+    if let Some(ips) = found.rom.ips() {
+        println!("Speed: {} instructions per second", ips);
+    }
+    if !found.rom.keys.is_empty() {
+        let keys: Vec<String> = found
+            .rom
+            .keys
+            .iter()
+            .map(|(role, key)| format!("{}={:X}", role, key))
+            .collect();
+        println!("Keys: {}", keys.join(", "));
+    }
+    ExitCode::SUCCESS
+}
+
+/// Prints the settings a ROM would be run with, or the global settings
+/// without a ROM
+fn config_dump(rom_path: Option<String>, options: &Options, config: &Config) -> ExitCode {
+    match config::config_path() {
+        Some(path) if path.exists() => println!("# config file: {}", path.display()),
+        Some(path) => println!("# config file: {} (not found)", path.display()),
+        None => println!("# config file: none, HOME isn't set"),
+    }
+    let settings = match rom_path {
+        Some(rom_path) => {
+            println!("# ROM: {}", rom_path);
+            let (rom, cartridge_config, _) = match read_rom(&rom_path) {
+                Ok(loaded) => loaded,
+                Err(code) => return code,
+            };
+            let rom_db = load_database(config);
+            rom_settings(
+                &rom_path,
+                &rom,
+                cartridge_config.as_ref(),
+                &rom_db,
+                options,
+                config,
+            )
+            .map(|(settings, title)| {
+                if let Some(title) = title {
+                    println!("# ROM database: {}", title);
                 }
-            }
+                settings
+            })
         }
+        None => Settings::resolve(config, None, None, 0, options),
+    };
+    match settings.and_then(|settings| settings.to_toml()) {
+        Ok(toml) => {
+            print!("{}", toml);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            println!("{}", err);
+            ExitCode::from(EXIT_USAGE)
+        }
+    }
+}
 
-        canvas.present();
-        // keep emulation at ~60FPS (execution time of the loop not counted)
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+/// Works out the settings for a ROM from the command line, the config file,
+/// the settings of its cartridge and the ROM database. Also gives the title
+/// of the ROM if it's in the database.
+fn rom_settings(
+    rom_path: &str,
+    rom: &[u8],
+    cartridge_config: Option<&RomConfig>,
+    rom_db: &RomDb,
+    options: &Options,
+    config: &Config,
+) -> Result<(Settings, Option<String>), String> {
+    let sha1 = romdb::sha1_hex(rom);
+    let db_match = rom_db.lookup(rom);
+    // the ROM's section of the config file goes over the cartridge
+    let rom_config = match (config.rom(rom_path, &sha1), cartridge_config) {
+        (Some(rom_config), Some(cartridge_config)) => Some(rom_config.or(cartridge_config)),
+        (rom_config, cartridge_config) => rom_config.or(cartridge_config).cloned(),
+    };
+    let settings = Settings::resolve(
+        config,
+        rom_config.as_ref(),
+        db_match.as_ref().map(|found| found.rom),
+        rom.len(),
+        options,
+    )?;
+    Ok((settings, db_match.map(|found| found.program.title.clone())))
+}
+
+/// Loads the ROM database picked in the config, or else the built-in one.
+/// Problems with the picked one are only reported, the built-in one is
+/// used instead.
+fn load_database(config: &Config) -> RomDb {
+    let path = match config.database_path() {
+        Some(path) => path,
+        None => return RomDb::built_in(),
+    };
+    match RomDb::load(&path) {
+        Ok(rom_db) => rom_db,
+        Err(err) => {
+            println!("{}, using the built-in one", err);
+            RomDb::built_in()
+        }
     }
 }
 
-fn make_chip8() -> Chip8 {
-    let mut chip8 = Chip8::new();
-    chip8.load_font();
-    chip8.regs.pc = 0x200;
-    chip8
+/// Reads a ROM, or compiles the program of an Octo cartridge, which comes
+/// with settings and the names of its labels
+fn read_rom(rom_path: &str) -> Result<(Vec<u8>, Option<RomConfig>, Symbols), ExitCode> {
+    let data = read_file(rom_path)?;
+    rom_from_file(data).map_err(|err| {
+        println!("Invalid cartridge {}: {}", rom_path, err);
+        ExitCode::from(EXIT_INVALID_ROM)
+    })
 }
 
-fn emulation_step(chip8: &mut Chip8) {
-    // roughly 11 instructions per frame, going by folklore
-    for _ in 0..11 {
-        let raw_instruction = fetch_instruction(&mut chip8.regs, &chip8.memory);
-        let instruction = decode_instruction(raw_instruction);
-        execute_instruction(&instruction, chip8);
+/// The ROM in a file, which is the file itself unless it's a cartridge
+fn rom_from_file(data: Vec<u8>) -> Result<(Vec<u8>, Option<RomConfig>, Symbols), String> {
+    if !cartridge::is_cartridge(&data) {
+        return Ok((data, None, Symbols::default()));
     }
-    chip8.decrement_timers();
+    let cartridge = Cartridge::decode(&data)?;
+    let (rom, symbols) = cartridge.rom()?;
+    Ok((rom, Some(cartridge.rom_config()), symbols))
 }
 
-fn load_file(name: &str) -> Result<Vec<u8>, Error> {
-    return std::fs::read(name);
-}
-
-fn keycode_to_button(key: Keycode) -> Option<usize> {
-    match key {
-        Keycode::Num1 => Some(0x1),
-        Keycode::Num2 => Some(0x2),
-        Keycode::Num3 => Some(0x3),
-        Keycode::Num4 => Some(0xC),
-        Keycode::Q => Some(0x4),
-        Keycode::W => Some(0x5),
-        Keycode::E => Some(0x6),
-        Keycode::R => Some(0xD),
-        Keycode::A => Some(0x7),
-        Keycode::S => Some(0x8),
-        Keycode::D => Some(0x9),
-        Keycode::F => Some(0xE),
-        Keycode::Z => Some(0xA),
-        Keycode::X => Some(0x0),
-        Keycode::C => Some(0xB),
-        Keycode::V => Some(0xF),
-        _ => None,
+/// The symbols of a ROM: those of the file given with `--symbols`, or else
+/// of the `.sym` file next to the ROM if there's one, over those that came
+/// with the ROM
+fn load_symbols(rom_path: &str, options: &Options, symbols: Symbols) -> Result<Symbols, ExitCode> {
+    let path = match &options.symbols {
+        Some(path) => Path::new(path).to_path_buf(),
+        None => Path::new(rom_path).with_extension("sym"),
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(_) if options.symbols.is_none() => return Ok(symbols),
+        Err(err) => {
+            println!(
+                "Failed to open symbol file at path {}, error is \"{}\"",
+                path.display(),
+                err
+            );
+            return Err(ExitCode::from(EXIT_FILE_ERROR));
+        }
+    };
+    match Symbols::parse(&text) {
+        Ok(file_symbols) => Ok(file_symbols.merge(symbols)),
+        Err(err) if options.symbols.is_none() => {
+            println!("Warning: ignoring symbol file {}: {}", path.display(), err);
+            Ok(symbols)
+        }
+        Err(err) => {
+            println!("Invalid symbol file {}: {}", path.display(), err);
+            Err(ExitCode::from(EXIT_INVALID_ROM))
+        }
     }
 }
+
+fn load_file(name: &str) -> Result<Vec<u8>, Error> {
+    std::fs::read(name)
+}
+
+/// Reads a ROM, reporting failures
+fn read_file(rom_path: &str) -> Result<Vec<u8>, ExitCode> {
+    load_file(rom_path).map_err(|err| {
+        println!(
+            "Failed to open ROM at path {}, error is \"{}\"",
+            rom_path, err
+        );
+        ExitCode::from(EXIT_FILE_ERROR)
+    })
+}
@@ -0,0 +1,1002 @@
+use std::collections::HashMap;
+
+use crate::asm::encode_instruction;
+use crate::chip8::Instruction;
+
+// Octo always loads programs here
+const START_ADDRESS: usize = 0x200;
+const MEMORY_END: usize = 0x10000;
+
+// statements of SUPER-CHIP and XO-CHIP, which the interpreter doesn't have
+const UNSUPPORTED: [&str; 15] = [
+    "hires",
+    "lores",
+    "scroll-down",
+    "scroll-up",
+    "scroll-left",
+    "scroll-right",
+    "exit",
+    "bighex",
+    "long",
+    "plane",
+    "audio",
+    "pitch",
+    "saveflags",
+    "loadflags",
+    "native",
+];
+
+/// Compiles a program written in Octo (https://github.com/JohnEarnest/Octo),
+/// the language of Octo cartridges. The CHIP-8 part of the language is
+/// supported: labels, `:const`, `:alias`, `:unpack`, `:next`, `:org`,
+/// `:byte`, `:call`, `:macro`, `:calc`, `if ... then`,
+/// `if ... begin ... else ... end` and `loop ... while ... again`.
+///
+/// Like in Octo the program starts at its `main` label.
+/// # Example
+/// ```text
+/// : main
+///     i := smile
+///     loop
+///         sprite v0 v1 3
+///         v0 += 8
+///         if v0 == 64 then v0 := 0
+///     again
+/// : smile
+///     0b01010000 0b00000000 0b01110000
+/// ```
+pub fn compile(source: &str) -> Result<Vec<u8>, String> {
+    compile_with_labels(source).map(|(rom, _)| rom)
+}
+
+/// Compiles like `compile`, also gives the addresses of the labels
+pub fn compile_with_labels(source: &str) -> Result<(Vec<u8>, HashMap<String, u16>), String> {
+    Compiler::new(source).run()
+}
+
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+enum FixupKind {
+    // the low 12 bits of an instruction
+    Address,
+    // the low nibble of a byte, the top 4 bits of an address
+    High,
+    // a byte, the low 8 bits of an address
+    Low,
+}
+
+/// A label used before it's defined, filled in at the end
+struct Fixup {
+    address: usize,
+    name: String,
+    kind: FixupKind,
+    line: usize,
+}
+
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+/// The instructions testing a condition: the ones computing it (only
+/// needed for comparisons), then one that skips the next instruction if the
+/// condition holds and one that skips it if it doesn't
+struct Condition {
+    setup: Vec<Instruction>,
+    skip_if_true: Instruction,
+    skip_if_false: Instruction,
+}
+
+struct Compiler {
+    // the tokens left, last one first so they can be popped
+    tokens: Vec<Token>,
+    // line of the token being compiled, for errors
+    line: usize,
+    memory: Vec<u8>,
+    here: usize,
+    end: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    // jumps to patch at the `else` or `end` of each open `if ... begin`
+    branches: Vec<usize>,
+    // start of each open loop and the jumps out of it by `while`
+    loops: Vec<(usize, Vec<usize>)>,
+}
+
+impl Compiler {
+    fn new(source: &str) -> Compiler {
+        let mut tokens = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let code = line.split('#').next().unwrap_or("");
+            tokens.extend(code.split_whitespace().map(|text| Token {
+                text: text.to_string(),
+                line: i + 1,
+            }));
+        }
+        tokens.reverse();
+        Compiler {
+            tokens,
+            line: 0,
+            memory: vec![0; MEMORY_END],
+            here: START_ADDRESS,
+            end: START_ADDRESS,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    fn run(mut self) -> Result<(Vec<u8>, HashMap<String, u16>), String> {
+        // room for a jump to main, which doesn't have to come first
+        self.emit(&Instruction::Jmp { address: 0 })
+            .map_err(|err| format!("line 1: {}", err))?;
+        while !self.tokens.is_empty() {
+            self.statement()
+                .map_err(|err| format!("line {}: {}", self.line, err))?;
+        }
+        if !self.branches.is_empty() {
+            return Err("an if ... begin is missing its end".to_string());
+        }
+        if !self.loops.is_empty() {
+            return Err("a loop is missing its again".to_string());
+        }
+        let main = match self.labels.get("main") {
+            Some(main) => *main,
+            None => return Err("the program has no main label".to_string()),
+        };
+        self.patch_address(START_ADDRESS, main)
+            .map_err(|err| format!("main: {}", err))?;
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = match self.labels.get(&fixup.name) {
+                Some(address) => *address,
+                None => return Err(format!("line {}: unknown name {}", fixup.line, fixup.name)),
+            };
+            match fixup.kind {
+                FixupKind::Address => self
+                    .patch_address(fixup.address, address)
+                    .map_err(|err| format!("line {}: {}", fixup.line, err))?,
+                FixupKind::High => self.memory[fixup.address] |= (address >> 8) as u8 & 0xF,
+                FixupKind::Low => self.memory[fixup.address] = address as u8,
+            }
+        }
+        let rom = self.memory[START_ADDRESS..self.end].to_vec();
+        // a label can be at the very end of memory, past the last address
+        let labels = (self.labels.into_iter())
+            .filter(|(_, address)| *address < MEMORY_END)
+            .map(|(name, address)| (name, address as u16))
+            .collect();
+        Ok((rom, labels))
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+        match token.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.define_label(name, self.here)
+            }
+            ":next" => {
+                // names the second byte of the next instruction, for code
+                // that changes its own operands
+                let name = self.name()?;
+                self.define_label(name, self.here + 1)
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.number()?;
+                self.define_constant(name, value as f64)
+            }
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.expect("}")?;
+                self.define_constant(name, value)
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let reg = self.register()?;
+                self.aliases.insert(name, reg);
+                Ok(())
+            }
+            ":macro" => self.define_macro(),
+            ":unpack" => {
+                // v0 := nibble and the top 4 bits of the address, v1 := the
+                // low 8 bits
+                let nibble = self.number()?;
+                if !(0..=0xF).contains(&nibble) {
+                    return Err(format!("{} doesn't fit into 4 bits", nibble));
+                }
+                let (high, low) = match self.label_or_number()? {
+                    Ok(address) => (address >> 8 & 0xF, address & 0xFF),
+                    Err(name) => {
+                        self.add_fixup(self.here + 1, name.clone(), FixupKind::High);
+                        self.add_fixup(self.here + 3, name, FixupKind::Low);
+                        (0, 0)
+                    }
+                };
+                let val = (nibble << 4 | high) as u8;
+                self.emit(&Instruction::Ld { reg: 0x0, val })?;
+                self.emit(&Instruction::Ld {
+                    reg: 0x1,
+                    val: low as u8,
+                })
+            }
+            ":org" => {
+                let address = self.number()?;
+                if !(0..MEMORY_END as i64).contains(&address) {
+                    return Err(format!("{:#X} is outside of memory", address));
+                }
+                self.here = address as usize;
+                Ok(())
+            }
+            ":byte" => {
+                let val = self.byte()?;
+                self.emit_byte(val)
+            }
+            ":call" => {
+                let address = self.address()?;
+                self.emit(&Instruction::Call { address })
+            }
+            ":breakpoint" => self.next().map(|_| ()),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(|_| ())
+            }
+            ";" | "return" => self.emit(&Instruction::Ret),
+            "clear" => self.emit(&Instruction::Cls),
+            "bcd" => {
+                let reg = self.register()?;
+                self.emit(&Instruction::LdB { reg })
+            }
+            "save" | "load" => {
+                let end_reg = self.register()?;
+                if self.peek() == Some("-") {
+                    return Err(format!("{} with a range of registers needs XO-CHIP", token));
+                }
+                if token == "save" {
+                    self.emit(&Instruction::LdRegsMem { end_reg })
+                } else {
+                    self.emit(&Instruction::LdMemRegs { end_reg })
+                }
+            }
+            "sprite" => {
+                let reg1 = self.register()?;
+                let reg2 = self.register()?;
+                let n_bytes = self.number()?;
+                if !(0..=0xF).contains(&n_bytes) {
+                    return Err(format!("sprites have up to 15 rows, not {}", n_bytes));
+                }
+                self.emit(&Instruction::Drw {
+                    reg1,
+                    reg2,
+                    n_bytes: n_bytes as u8,
+                })
+            }
+            "jump" => {
+                let address = self.address()?;
+                self.emit(&Instruction::Jmp { address })
+            }
+            "jump0" => {
+                let address = self.address()?;
+                self.emit(&Instruction::JmpV0 { address })
+            }
+            "loop" => {
+                self.loops.push((self.here, Vec::new()));
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition()?;
+                if self.loops.is_empty() {
+                    return Err("while outside of a loop".to_string());
+                }
+                let exit = self.emit_branch(condition)?;
+                if let Some((_, exits)) = self.loops.last_mut() {
+                    exits.push(exit);
+                }
+                Ok(())
+            }
+            "again" => {
+                let (start, exits) = match self.loops.pop() {
+                    Some(open) => open,
+                    None => return Err("again without a loop".to_string()),
+                };
+                self.emit(&Instruction::Jmp {
+                    address: start as u16,
+                })?;
+                for exit in exits {
+                    self.patch_address(exit, self.here)?;
+                }
+                Ok(())
+            }
+            "if" => {
+                let condition = self.condition()?;
+                match self.next()?.as_str() {
+                    "then" => {
+                        for ins in &condition.setup {
+                            self.emit(ins)?;
+                        }
+                        self.emit(&condition.skip_if_false)
+                    }
+                    "begin" => {
+                        let branch = self.emit_branch(condition)?;
+                        self.branches.push(branch);
+                        Ok(())
+                    }
+                    other => Err(format!("expected then or begin, got {}", other)),
+                }
+            }
+            "else" => {
+                let branch = match self.branches.pop() {
+                    Some(branch) => branch,
+                    None => return Err("else without an if ... begin".to_string()),
+                };
+                let skip_else = self.here;
+                self.emit(&Instruction::Jmp { address: 0 })?;
+                self.patch_address(branch, self.here)?;
+                self.branches.push(skip_else);
+                Ok(())
+            }
+            "end" => match self.branches.pop() {
+                Some(branch) => self.patch_address(branch, self.here),
+                None => Err("end without an if ... begin".to_string()),
+            },
+            "i" => match self.next()?.as_str() {
+                ":=" if self.peek() == Some("hex") => {
+                    self.next()?;
+                    let reg = self.register()?;
+                    self.emit(&Instruction::LdF { reg })
+                }
+                ":=" => {
+                    if let Some(word @ ("bighex" | "long")) = self.peek() {
+                        return Err(format!("i := {} needs SUPER-CHIP or XO-CHIP", word));
+                    }
+                    let address = self.address()?;
+                    self.emit(&Instruction::Ldi { address })
+                }
+                "+=" => {
+                    let reg = self.register()?;
+                    self.emit(&Instruction::AddI { reg })
+                }
+                other => Err(format!("unknown operator {} for i", other)),
+            },
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let reg = self.register()?;
+                if token == "delay" {
+                    self.emit(&Instruction::LdIntoDt { reg })
+                } else {
+                    self.emit(&Instruction::LdSt { reg })
+                }
+            }
+            word if UNSUPPORTED.contains(&word) || word == ":stringmode" => Err(format!(
+                "{} isn't supported, it needs SUPER-CHIP or XO-CHIP",
+                word
+            )),
+            _ => {
+                if let Some(reg) = self.register_name(&token) {
+                    self.assignment(reg)
+                } else if self.macros.contains_key(&token) {
+                    self.expand_macro(&token)
+                } else if let Some(value) = self.constant_or_number(&token) {
+                    // numbers on their own are data
+                    self.emit_byte(to_byte(value)?)
+                } else if let Some(address) = self.labels.get(&token) {
+                    // and names are calls
+                    let address = check_address(*address as i64)?;
+                    self.emit(&Instruction::Call { address })
+                } else if is_name(&token) {
+                    let address = self.forward_address(token);
+                    self.emit(&Instruction::Call { address })
+                } else {
+                    Err(format!("unknown statement {}", token))
+                }
+            }
+        }
+    }
+
+    fn assignment(&mut self, reg: u8) -> Result<(), String> {
+        let op = self.next()?;
+        let ins = match (op.as_str(), self.peek()) {
+            (":=", Some("random")) => {
+                self.next()?;
+                Instruction::Rnd {
+                    reg,
+                    mask: self.byte()?,
+                }
+            }
+            (":=", Some("key")) => {
+                self.next()?;
+                Instruction::LdKey { reg }
+            }
+            (":=", Some("delay")) => {
+                self.next()?;
+                Instruction::LdFromDt { reg }
+            }
+            (":=", _) => match self.operand()? {
+                Operand::Register(reg2) => Instruction::LdReg { reg1: reg, reg2 },
+                Operand::Byte(val) => Instruction::Ld { reg, val },
+            },
+            ("+=", _) => match self.operand()? {
+                Operand::Register(reg2) => Instruction::AddReg { reg1: reg, reg2 },
+                Operand::Byte(val) => Instruction::Add { reg, val },
+            },
+            ("-=", _) => match self.operand()? {
+                Operand::Register(reg2) => Instruction::SubReg { reg1: reg, reg2 },
+                Operand::Byte(val) => Instruction::Add {
+                    reg,
+                    val: val.wrapping_neg(),
+                },
+            },
+            (op, _) => {
+                let reg2 = self.register()?;
+                match op {
+                    "=-" => Instruction::SubRegN { reg1: reg, reg2 },
+                    "|=" => Instruction::Or { reg1: reg, reg2 },
+                    "&=" => Instruction::And { reg1: reg, reg2 },
+                    "^=" => Instruction::Xor { reg1: reg, reg2 },
+                    ">>=" => Instruction::Shr { reg1: reg, reg2 },
+                    "<<=" => Instruction::Shl { reg1: reg, reg2 },
+                    _ => return Err(format!("unknown operator {}", op)),
+                }
+            }
+        };
+        self.emit(&ins)
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let reg = self.register()?;
+        let op = self.next()?;
+        let (skip_if_true, skip_if_false) = match op.as_str() {
+            "key" => (Instruction::Skp { reg }, Instruction::SkpNp { reg }),
+            "-key" => (Instruction::SkpNp { reg }, Instruction::Skp { reg }),
+            "==" | "!=" => {
+                let (equal, not_equal) = match self.operand()? {
+                    Operand::Register(reg2) => (
+                        Instruction::SeReg { reg1: reg, reg2 },
+                        Instruction::SneReg { reg1: reg, reg2 },
+                    ),
+                    Operand::Byte(val) => {
+                        (Instruction::Se { reg, val }, Instruction::Sne { reg, val })
+                    }
+                };
+                if op == "==" {
+                    (equal, not_equal)
+                } else {
+                    (not_equal, equal)
+                }
+            }
+            "<" | ">" | "<=" | ">=" => return self.comparison(reg, &op),
+            _ => return Err(format!("unknown comparison {}", op)),
+        };
+        Ok(Condition {
+            setup: Vec::new(),
+            skip_if_true,
+            skip_if_false,
+        })
+    }
+
+    /// Compares with a subtraction into VF, whose flag tells whether the
+    /// first operand is at least the second one
+    fn comparison(&mut self, reg: u8, op: &str) -> Result<Condition, String> {
+        let other = self.operand()?;
+        let (first, second, flag) = match op {
+            ">=" => (Operand::Register(reg), other, 1),
+            "<" => (Operand::Register(reg), other, 0),
+            ">" => (other, Operand::Register(reg), 0),
+            _ => (other, Operand::Register(reg), 1),
+        };
+        let setup = match (first, second) {
+            (Operand::Register(reg1), Operand::Register(reg2)) => vec![
+                Instruction::LdReg {
+                    reg1: 0xF,
+                    reg2: reg1,
+                },
+                Instruction::SubReg { reg1: 0xF, reg2 },
+            ],
+            (Operand::Register(reg2), Operand::Byte(val)) => vec![
+                Instruction::Ld { reg: 0xF, val },
+                Instruction::SubRegN { reg1: 0xF, reg2 },
+            ],
+            (Operand::Byte(val), Operand::Register(reg2)) => vec![
+                Instruction::Ld { reg: 0xF, val },
+                Instruction::SubReg { reg1: 0xF, reg2 },
+            ],
+            (Operand::Byte(_), Operand::Byte(_)) => unreachable!(),
+        };
+        Ok(Condition {
+            setup,
+            skip_if_true: Instruction::Se {
+                reg: 0xF,
+                val: flag,
+            },
+            skip_if_false: Instruction::Sne {
+                reg: 0xF,
+                val: flag,
+            },
+        })
+    }
+
+    /// Emits a jump taken when the condition doesn't hold, gives its address
+    /// so it can be patched once its target is known
+    fn emit_branch(&mut self, condition: Condition) -> Result<usize, String> {
+        for ins in &condition.setup {
+            self.emit(ins)?;
+        }
+        self.emit(&condition.skip_if_true)?;
+        let branch = self.here;
+        self.emit(&Instruction::Jmp { address: 0 })?;
+        Ok(branch)
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.name()?;
+        let mut args = Vec::new();
+        loop {
+            match self.next()?.as_str() {
+                "{" => break,
+                arg => args.push(arg.to_string()),
+            }
+        }
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.pop()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), String> {
+        let arg_count = self.macros[name].args.len();
+        let mut values = Vec::new();
+        for _ in 0..arg_count {
+            values.push(self.next()?);
+        }
+        let line = self.line;
+        let found = &self.macros[name];
+        let expanded: Vec<Token> = found
+            .body
+            .iter()
+            .rev()
+            .map(|token| {
+                let text = match found.args.iter().position(|arg| *arg == token.text) {
+                    Some(i) => values[i].clone(),
+                    None => token.text.clone(),
+                };
+                Token { text, line }
+            })
+            .collect();
+        self.tokens.extend(expanded);
+        Ok(())
+    }
+
+    /// Evaluates the expression of a `:calc`. Like in Octo operators have no
+    /// precedence and are applied from right to left, so `2 * 3 + 1` is 8.
+    fn calc(&mut self) -> Result<f64, String> {
+        let left = self.calc_term()?;
+        let op = match self.peek() {
+            Some(
+                op @ ("+" | "-" | "*" | "/" | "%" | "&" | "|" | "^" | "<<" | ">>" | "pow" | "min"
+                | "max" | "<" | ">" | "<=" | ">=" | "==" | "!="),
+            ) => op.to_string(),
+            _ => return Ok(left),
+        };
+        self.next()?;
+        let right = self.calc()?;
+        let bool_value = |b: bool| if b { 1.0 } else { 0.0 };
+        let int = |x: f64| x as i64;
+        Ok(match op.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (int(left) & int(right)) as f64,
+            "|" => (int(left) | int(right)) as f64,
+            "^" => (int(left) ^ int(right)) as f64,
+            "<<" => (int(left) << int(right)) as f64,
+            ">>" => (int(left) >> int(right)) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => bool_value(left < right),
+            ">" => bool_value(left > right),
+            "<=" => bool_value(left <= right),
+            ">=" => bool_value(left >= right),
+            "==" => bool_value(left == right),
+            _ => bool_value(left != right),
+        })
+    }
+
+    fn calc_term(&mut self) -> Result<f64, String> {
+        let token = self.next()?;
+        let unary: Option<fn(f64) -> f64> = match token.as_str() {
+            "-" => Some(|x| -x),
+            "~" => Some(|x| !(x as i64) as f64),
+            "!" => Some(|x| if x == 0.0 { 1.0 } else { 0.0 }),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(unary) = unary {
+            return Ok(unary(self.calc_term()?));
+        }
+        match token.as_str() {
+            "(" => {
+                let value = self.calc()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            "@" => {
+                let address = self.calc_term()? as usize;
+                match self.memory.get(address) {
+                    Some(byte) => Ok(*byte as f64),
+                    None => Err(format!("{:#X} is outside of memory", address)),
+                }
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            name => match self.constant_or_number(name) {
+                Some(value) => Ok(value),
+                None => match self.labels.get(name) {
+                    Some(address) => Ok(*address as f64),
+                    None => Err(format!("unknown name {}", name)),
+                },
+            },
+        }
+    }
+
+    /// Reads a number, a constant, a label or a `{ calc expression }`
+    fn number(&mut self) -> Result<i64, String> {
+        match self.label_or_number()? {
+            Ok(value) => Ok(value),
+            Err(name) => Err(format!("unknown name {}", name)),
+        }
+    }
+
+    /// Like `number`, but a name that isn't known yet is given back as the
+    /// error, as it can be a label defined further on
+    fn label_or_number(&mut self) -> Result<Result<i64, String>, String> {
+        let token = self.next()?;
+        if token == "{" {
+            let value = self.calc()?;
+            self.expect("}")?;
+            return Ok(Ok(value.floor() as i64));
+        }
+        if let Some(value) = self.constant_or_number(&token) {
+            return Ok(Ok(value.floor() as i64));
+        }
+        if let Some(address) = self.labels.get(&token) {
+            return Ok(Ok(*address as i64));
+        }
+        if self.register_name(&token).is_some() || !is_name(&token) {
+            return Err(format!("expected a number, got {}", token));
+        }
+        Ok(Err(token))
+    }
+
+    /// Reads the address of an instruction, which can be a label defined
+    /// further on
+    fn address(&mut self) -> Result<u16, String> {
+        match self.label_or_number()? {
+            Ok(address) => check_address(address),
+            Err(name) => Ok(self.forward_address(name)),
+        }
+    }
+
+    /// Leaves the address of a label to be filled in once it's defined
+    fn forward_address(&mut self, name: String) -> u16 {
+        self.add_fixup(self.here, name, FixupKind::Address);
+        0
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        to_byte(self.number()? as f64)
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.peek().and_then(|token| self.register_name(token)) {
+            Some(reg) => {
+                self.next()?;
+                Ok(Operand::Register(reg))
+            }
+            None => Ok(Operand::Byte(self.byte()?)),
+        }
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.register_name(&token)
+            .ok_or_else(|| format!("expected a register, got {}", token))
+    }
+
+    fn register_name(&self, token: &str) -> Option<u8> {
+        if let Some(reg) = self.aliases.get(token) {
+            return Some(*reg);
+        }
+        let digit = token.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn constant_or_number(&self, token: &str) -> Option<f64> {
+        match self.constants.get(token) {
+            Some(value) => Some(*value),
+            None => parse_number(token).map(|value| value as f64),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let token = self.next()?;
+        if !is_name(&token) || self.register_name(&token).is_some() {
+            return Err(format!("{} can't be used as a name", token));
+        }
+        Ok(token)
+    }
+
+    fn define_label(&mut self, name: String, address: usize) -> Result<(), String> {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return Err(format!("{} is defined twice", name));
+        }
+        self.labels.insert(name, address);
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: String, value: f64) -> Result<(), String> {
+        if self.labels.contains_key(&name) {
+            return Err(format!("{} is already a label", name));
+        }
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    fn add_fixup(&mut self, address: usize, name: String, kind: FixupKind) {
+        self.fixups.push(Fixup {
+            address,
+            name,
+            kind,
+            line: self.line,
+        });
+    }
+
+    fn patch_address(&mut self, at: usize, address: usize) -> Result<(), String> {
+        let address = check_address(address as i64)?;
+        self.memory[at] = self.memory[at] & 0xF0 | (address >> 8) as u8;
+        self.memory[at + 1] = address as u8;
+        Ok(())
+    }
+
+    fn emit(&mut self, ins: &Instruction) -> Result<(), String> {
+        let opcode = encode_instruction(ins);
+        self.emit_byte((opcode >> 8) as u8)?;
+        self.emit_byte(opcode as u8)
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), String> {
+        if self.here >= MEMORY_END {
+            return Err("the program doesn't fit into memory".to_string());
+        }
+        self.memory[self.here] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(format!("expected {}, got {}", expected, token));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        self.pop().map(|token| token.text)
+    }
+
+    fn pop(&mut self) -> Result<Token, String> {
+        match self.tokens.pop() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token)
+            }
+            None => Err("unexpected end of the program".to_string()),
+        }
+    }
+}
+
+fn parse_number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn is_name(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn to_byte(value: f64) -> Result<u8, String> {
+    let value = value.floor() as i64;
+    if !(-128..=255).contains(&value) {
+        return Err(format!("{} doesn't fit into a byte", value));
+    }
+    Ok(value as u8)
+}
+
+fn check_address(address: i64) -> Result<u16, String> {
+    if !(0..=0xFFF).contains(&address) {
+        return Err(format!("address {:#X} doesn't fit into 12 bits", address));
+    }
+    Ok(address as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compile_works() {
+        let source = "
+            : main
+                i := smile      # a comment
+                loop
+                    sprite v0 v1 3
+                    v0 += 8
+                    if v0 == 64 then v0 := 0
+                again
+            : smile
+                0b01010000 0x00 112
+        ";
+        assert_eq!(
+            compile(source).unwrap(),
+            [
+                0x12, 0x02, // jump main
+                0xA2, 0x0E, // i := smile
+                0xD0, 0x13, // sprite v0 v1 3
+                0x70, 0x08, // v0 += 8
+                0x40, 0x40, // if v0 == 64 then
+                0x60, 0x00, // v0 := 0
+                0x12, 0x04, // again
+                0x50, 0x00, 0x70,
+            ]
+        );
+        let (_, labels) = compile_with_labels(source).unwrap();
+        assert_eq!(labels["main"], 0x202);
+        assert_eq!(labels["smile"], 0x20E);
+    }
+
+    #[test]
+    fn control_flow_works() {
+        let source = "
+            : main
+                if v1 != v2 begin
+                    clear
+                else
+                    draw
+                end
+                loop
+                    v0 -= 1
+                    while v0 > 3
+                again
+            : draw ;
+        ";
+        assert_eq!(
+            compile(source).unwrap(),
+            [
+                0x12, 0x02, // jump main
+                0x91, 0x20, // if v1 != v2 begin
+                0x12, 0x0A, // jump to else
+                0x00, 0xE0, // clear
+                0x12, 0x0C, // else
+                0x22, 0x18, // draw
+                0x70, 0xFF, // v0 -= 1
+                0x6F, 0x03, // while v0 > 3: vf := 3
+                0x8F, 0x05, // vf -= v0
+                0x3F, 0x00, // skip the exit while v0 > 3
+                0x12, 0x18, // jump out of the loop
+                0x12, 0x0C, // again
+                0x00, 0xEE, // ;
+            ]
+        );
+    }
+
+    #[test]
+    fn directives_work() {
+        let source = "
+            :const speed 3
+            :alias x v4
+            :calc twice { speed * 2 + 1 }
+            :macro add-to reg amount { reg += amount }
+            : main
+                x := speed
+                add-to x twice
+                :unpack 0xA data
+                jump0 data
+            :org 0x220
+            : data
+                :byte { twice + 1 }
+        ";
+        assert_eq!(
+            compile(source).unwrap()[..12],
+            [0x12, 0x02, 0x64, 0x03, 0x74, 0x09, 0x60, 0xA2, 0x61, 0x20, 0xB2, 0x20]
+        );
+        assert_eq!(compile(source).unwrap()[0x20], 10);
+    }
+
+    #[test]
+    fn compile_reports_errors() {
+        assert_eq!(
+            compile(": start clear").unwrap_err(),
+            "the program has no main label"
+        );
+        assert_eq!(
+            compile(": main\n jump nowhere").unwrap_err(),
+            "line 2: unknown name nowhere"
+        );
+        assert_eq!(
+            compile(": main\n v0 := 256").unwrap_err(),
+            "line 2: 256 doesn't fit into a byte"
+        );
+        assert_eq!(
+            compile(": main\n hires").unwrap_err(),
+            "line 2: hires isn't supported, it needs SUPER-CHIP or XO-CHIP"
+        );
+        assert_eq!(
+            compile(": main\n loop").unwrap_err(),
+            "a loop is missing its again"
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use std::time::{Duration, Instant};
+
+use crate::chip8::Quirks;
+use crate::overlay::{Ink, Overlay, ROWS};
+
+// how long a message stays on screen, at 60 frames per second
+const MESSAGE_FRAMES: u32 = 120;
+// older messages are dropped when there are more
+const MAX_MESSAGES: usize = 3;
+
+// width of the stats panel in characters
+const PANEL_WIDTH: usize = 20;
+
+// the COSMAC VIP keypad, as the keys are laid out in the stats panel
+const KEYPAD: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// Measures how fast the emulator runs, averaged over a second
+pub struct FrameStats {
+    since: Instant,
+    frames: u32,
+    instructions: u64,
+    busy: Duration,
+    /// frames per second
+    pub fps: f64,
+    /// instructions run per second
+    pub ips: f64,
+    /// time spent running and drawing a frame
+    pub frame_time: Duration,
+}
+
+impl FrameStats {
+    pub fn new(now: Instant) -> FrameStats {
+        FrameStats {
+            since: now,
+            frames: 0,
+            instructions: 0,
+            busy: Duration::ZERO,
+            fps: 0.0,
+            ips: 0.0,
+            frame_time: Duration::ZERO,
+        }
+    }
+
+    /// Counts a frame that ran the given number of instructions and took
+    /// `busy` to run and draw, the averages are updated once a second
+    pub fn frame(&mut self, instructions: u32, busy: Duration, now: Instant) {
+        self.frames += 1;
+        self.instructions += instructions as u64;
+        self.busy += busy;
+        let seconds = now.duration_since(self.since).as_secs_f64();
+        if seconds >= 1.0 {
+            self.fps = self.frames as f64 / seconds;
+            self.ips = self.instructions as f64 / seconds;
+            self.frame_time = self.busy / self.frames;
+            *self = FrameStats {
+                since: now,
+                frames: 0,
+                instructions: 0,
+                busy: Duration::ZERO,
+                ..*self
+            };
+        }
+    }
+}
+
+/// What the stats panel shows besides the speed
+pub struct StatsInfo<'a> {
+    pub frame_stats: &'a FrameStats,
+    pub platform: &'a str,
+    pub quirks: Quirks,
+    pub key_down: [bool; 16],
+}
+
+/// The on-screen display: messages that go away after a while, for things
+/// that happen while playing like switching the palette, and a panel with
+/// stats that can be turned on
+#[derive(Default)]
+pub struct Osd {
+    /// text and frames left, oldest first
+    messages: Vec<(String, u32)>,
+    pub stats_visible: bool,
+}
+
+impl Osd {
+    pub fn message(&mut self, text: &str) {
+        self.messages.push((text.to_string(), MESSAGE_FRAMES));
+        if self.messages.len() > MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+    }
+
+    /// Counts down the time left for the messages, called once a frame
+    pub fn tick(&mut self) {
+        for (_, frames_left) in self.messages.iter_mut() {
+            *frames_left -= 1;
+        }
+        self.messages.retain(|(_, frames_left)| *frames_left > 0);
+    }
+
+    /// Whether there's anything to draw
+    pub fn is_visible(&self) -> bool {
+        self.stats_visible || !self.messages.is_empty()
+    }
+
+    /// Draws the messages at the bottom left of the overlay and the stats
+    /// panel at the top left, leaving the rest of the overlay as it is
+    pub fn draw(&self, stats: &StatsInfo, overlay: &mut Overlay) {
+        if self.stats_visible {
+            draw_stats(stats, overlay);
+        }
+        let first_row = ROWS - self.messages.len();
+        for (i, (text, _)) in self.messages.iter().enumerate() {
+            overlay.fill(0, first_row + i, text.len() + 2, Ink::Background);
+            overlay.text(1, first_row + i, text, Ink::Foreground);
+        }
+    }
+}
+
+fn draw_stats(stats: &StatsInfo, overlay: &mut Overlay) {
+    let frame_stats = stats.frame_stats;
+    let mut lines = vec![
+        format!("FPS   {:.1}", frame_stats.fps),
+        format!("IPS   {:.0}", frame_stats.ips),
+        format!(
+            "FRAME {:.2} MS",
+            frame_stats.frame_time.as_secs_f64() * 1000.0
+        ),
+        stats.platform.to_string(),
+    ];
+    lines.extend(quirk_lines(&stats.quirks));
+    let keypad_top = lines.len() + 1;
+
+    overlay.fill(0, 0, PANEL_WIDTH, Ink::Background);
+    for (row, line) in lines.iter().enumerate() {
+        overlay.fill(0, row + 1, PANEL_WIDTH, Ink::Background);
+        overlay.text(1, row + 1, line, Ink::Foreground);
+    }
+    // the keypad, with the keys that are down highlighted
+    for (row, keys) in KEYPAD.iter().enumerate() {
+        overlay.fill(0, keypad_top + row, PANEL_WIDTH, Ink::Background);
+        for (column, key) in keys.iter().enumerate() {
+            let label = format!("{:X}", key);
+            let column = 1 + column * 2;
+            if stats.key_down[*key] {
+                overlay.fill(column, keypad_top + row, 1, Ink::Foreground);
+                overlay.text(column, keypad_top + row, &label, Ink::Background);
+            } else {
+                overlay.text(column, keypad_top + row, &label, Ink::Foreground);
+            }
+        }
+    }
+    overlay.fill(0, keypad_top + KEYPAD.len(), PANEL_WIDTH, Ink::Background);
+}
+
+/// The quirks that are enabled, a few to a line so they fit in the panel
+fn quirk_lines(quirks: &Quirks) -> Vec<String> {
+    let enabled: Vec<&str> = [
+        ("SHIFT", quirks.shift),
+        ("I+X", quirks.memory_increment_by_x),
+        ("I KEPT", quirks.memory_leave_i_unchanged),
+        ("WRAP", quirks.wrap),
+        ("JUMP", quirks.jump),
+        ("VBLANK", quirks.vblank),
+        ("LOGIC", quirks.logic),
+        ("KEY UP", quirks.key_wait_release),
+    ]
+    .iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| *name)
+    .collect();
+    if enabled.is_empty() {
+        return vec!["NO QUIRKS".to_string()];
+    }
+    let mut lines: Vec<String> = Vec::new();
+    for name in enabled {
+        match lines.last_mut() {
+            Some(line) if line.len() + 1 + name.len() < PANEL_WIDTH - 1 => {
+                line.push(' ');
+                line.push_str(name);
+            }
+            _ => lines.push(name.to_string()),
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_expire() {
+        let mut osd = Osd::default();
+        assert!(!osd.is_visible());
+        osd.message("first");
+        for _ in 0..MESSAGE_FRAMES / 2 {
+            osd.tick();
+        }
+        osd.message("second");
+        osd.tick();
+        assert_eq!(osd.messages.len(), 2);
+        for _ in 0..MESSAGE_FRAMES / 2 {
+            osd.tick();
+        }
+        assert_eq!(osd.messages[0].0, "second");
+        assert_eq!(osd.messages.len(), 1);
+        for _ in 0..MESSAGE_FRAMES / 2 {
+            osd.tick();
+        }
+        assert!(!osd.is_visible());
+
+        for text in ["1", "2", "3", "4"] {
+            osd.message(text);
+        }
+        assert_eq!(osd.messages.len(), MAX_MESSAGES);
+        assert_eq!(osd.messages[0].0, "2");
+    }
+
+    #[test]
+    fn frame_stats_works() {
+        let start = Instant::now();
+        let mut stats = FrameStats::new(start);
+        for frame in 1..=30 {
+            let now = start + Duration::from_millis(frame * 1000 / 30);
+            stats.frame(10, Duration::from_millis(2), now);
+        }
+        assert!((stats.fps - 30.0).abs() < 0.1);
+        assert!((stats.ips - 300.0).abs() < 1.0);
+        assert_eq!(stats.frame_time, Duration::from_millis(2));
+        assert_eq!(stats.frames, 0);
+    }
+
+    #[test]
+    fn quirk_lines_fit_the_panel() {
+        let quirks = Quirks {
+            shift: true,
+            memory_increment_by_x: true,
+            memory_leave_i_unchanged: true,
+            wrap: true,
+            jump: true,
+            vblank: true,
+            logic: true,
+            key_wait_release: true,
+        };
+        let lines = quirk_lines(&quirks);
+        assert_eq!(lines[0], "SHIFT I+X I KEPT");
+        assert!(lines.iter().all(|line| line.len() < PANEL_WIDTH - 1));
+        assert_eq!(
+            lines.join(" "),
+            "SHIFT I+X I KEPT WRAP JUMP VBLANK LOGIC KEY UP"
+        );
+
+        let quirks = Quirks {
+            shift: false,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: false,
+            wrap: false,
+            jump: false,
+            vblank: false,
+            logic: false,
+            key_wait_release: false,
+        };
+        assert_eq!(quirk_lines(&quirks), ["NO QUIRKS"]);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::chip8::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// The overlay is drawn at 4 times the resolution of the display, so it has
+/// the same aspect ratio and is scaled up the same way
+pub const OVERLAY_WIDTH: usize = SCREEN_WIDTH as usize * 4;
+pub const OVERLAY_HEIGHT: usize = SCREEN_HEIGHT as usize * 4;
+
+// characters are 3x5 pixels, with a pixel of space to the right and below
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+pub const CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+pub const CELL_HEIGHT: usize = GLYPH_HEIGHT + 1;
+
+/// Number of characters that fit in a row of the overlay over the display
+pub const COLUMNS: usize = OVERLAY_WIDTH / CELL_WIDTH;
+/// Number of rows of text that fit in the overlay over the display
+pub const ROWS: usize = OVERLAY_HEIGHT / CELL_HEIGHT;
+
+/// Pixels of the characters from space to underscore, a row of 3 bits per
+/// line with the leftmost pixel in the highest bit. Lowercase letters are
+/// drawn as uppercase.
+const GLYPHS: [[u8; GLYPH_HEIGHT]; 64] = [
+    [0, 0, 0, 0, 0], // space
+    [2, 2, 2, 0, 2], // !
+    [5, 5, 0, 0, 0], // "
+    [5, 7, 5, 7, 5], // #
+    [3, 6, 7, 3, 6], // $
+    [5, 1, 2, 4, 5], // %
+    [2, 5, 2, 5, 3], // &
+    [2, 2, 0, 0, 0], // '
+    [1, 2, 2, 2, 1], // (
+    [4, 2, 2, 2, 4], // )
+    [0, 5, 2, 5, 0], // *
+    [0, 2, 7, 2, 0], // +
+    [0, 0, 0, 2, 4], // ,
+    [0, 0, 7, 0, 0], // -
+    [0, 0, 0, 0, 2], // .
+    [1, 1, 2, 4, 4], // /
+    [7, 5, 5, 5, 7], // 0
+    [2, 6, 2, 2, 7], // 1
+    [7, 1, 7, 4, 7], // 2
+    [7, 1, 3, 1, 7], // 3
+    [5, 5, 7, 1, 1], // 4
+    [7, 4, 7, 1, 7], // 5
+    [7, 4, 7, 5, 7], // 6
+    [7, 1, 1, 2, 2], // 7
+    [7, 5, 7, 5, 7], // 8
+    [7, 5, 7, 1, 7], // 9
+    [0, 2, 0, 2, 0], // :
+    [0, 2, 0, 2, 4], // ;
+    [1, 2, 4, 2, 1], // <
+    [0, 7, 0, 7, 0], // =
+    [4, 2, 1, 2, 4], // >
+    [7, 1, 3, 0, 2], // ?
+    [2, 5, 7, 4, 3], // @
+    [2, 5, 7, 5, 5], // A
+    [6, 5, 6, 5, 6], // B
+    [3, 4, 4, 4, 3], // C
+    [6, 5, 5, 5, 6], // D
+    [7, 4, 6, 4, 7], // E
+    [7, 4, 6, 4, 4], // F
+    [3, 4, 5, 5, 3], // G
+    [5, 5, 7, 5, 5], // H
+    [7, 2, 2, 2, 7], // I
+    [1, 1, 1, 5, 2], // J
+    [5, 5, 6, 5, 5], // K
+    [4, 4, 4, 4, 7], // L
+    [5, 7, 7, 5, 5], // M
+    [6, 5, 5, 5, 5], // N
+    [2, 5, 5, 5, 2], // O
+    [6, 5, 6, 4, 4], // P
+    [2, 5, 5, 6, 3], // Q
+    [6, 5, 6, 5, 5], // R
+    [3, 4, 2, 1, 6], // S
+    [7, 2, 2, 2, 2], // T
+    [5, 5, 5, 5, 7], // U
+    [5, 5, 5, 5, 2], // V
+    [5, 5, 7, 7, 5], // W
+    [5, 5, 2, 5, 5], // X
+    [5, 5, 2, 2, 2], // Y
+    [7, 1, 2, 4, 7], // Z
+    [3, 2, 2, 2, 3], // [
+    [4, 4, 2, 1, 1], // \
+    [6, 2, 2, 2, 6], // ]
+    [2, 5, 0, 0, 0], // ^
+    [0, 0, 0, 0, 7], // _
+];
+
+/// What a pixel of the overlay shows, colors come from the palette
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ink {
+    /// the display shows through
+    Clear,
+    Background,
+    Foreground,
+}
+
+/// Text drawn over the display, laid out in a grid of `COLUMNS` x `ROWS`
+/// characters. Overlays of other sizes hold other views, like the debugger.
+pub struct Overlay {
+    width: usize,
+    height: usize,
+    pixels: Vec<Ink>,
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        Overlay::with_size(OVERLAY_WIDTH, OVERLAY_HEIGHT)
+    }
+
+    pub fn with_size(width: usize, height: usize) -> Overlay {
+        Overlay {
+            width,
+            height,
+            pixels: vec![Ink::Clear; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn pixels(&self) -> &[Ink] {
+        &self.pixels
+    }
+
+    fn columns(&self) -> usize {
+        self.width / CELL_WIDTH
+    }
+
+    fn rows(&self) -> usize {
+        self.height / CELL_HEIGHT
+    }
+
+    /// Sets a single pixel, for drawing things other than text
+    pub fn set_pixel(&mut self, x: usize, y: usize, ink: Ink) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = ink;
+        }
+    }
+
+    pub fn fill_all(&mut self, ink: Ink) {
+        self.pixels.fill(ink);
+    }
+
+    /// Fills `width` characters of a row, parts outside of the overlay are
+    /// left out
+    pub fn fill(&mut self, column: usize, row: usize, width: usize, ink: Ink) {
+        let x_range = column * CELL_WIDTH..((column + width) * CELL_WIDTH).min(self.width);
+        for y in row * CELL_HEIGHT..((row + 1) * CELL_HEIGHT).min(self.height) {
+            for x in x_range.clone() {
+                self.pixels[y * self.width + x] = ink;
+            }
+        }
+    }
+
+    /// Draws the pixels of the characters in the given ink, the space around
+    /// them is left as it was. Text running past the right edge is cut off,
+    /// characters without a glyph are drawn as "?".
+    pub fn text(&mut self, column: usize, row: usize, text: &str, ink: Ink) {
+        if row >= self.rows() {
+            return;
+        }
+        for (i, character) in text
+            .chars()
+            .enumerate()
+            .take(self.columns().saturating_sub(column))
+        {
+            let glyph = glyph(character);
+            let left = (column + i) * CELL_WIDTH;
+            let top = row * CELL_HEIGHT;
+            for (dy, line) in glyph.iter().enumerate() {
+                for dx in 0..GLYPH_WIDTH {
+                    if line >> (GLYPH_WIDTH - 1 - dx) & 1 == 1 {
+                        self.pixels[(top + dy) * self.width + left + dx] = ink;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fills a row with the background and draws the text on it, or the
+    /// other way around when it's highlighted
+    pub fn line(&mut self, row: usize, text: &str, highlighted: bool) {
+        let (background, foreground) = match highlighted {
+            true => (Ink::Foreground, Ink::Background),
+            false => (Ink::Background, Ink::Foreground),
+        };
+        self.fill(0, row, self.columns(), background);
+        self.text(0, row, text, foreground);
+    }
+}
+
+fn glyph(character: char) -> &'static [u8; GLYPH_HEIGHT] {
+    let index = (character.to_ascii_uppercase() as usize).wrapping_sub(' ' as usize);
+    GLYPHS.get(index).unwrap_or(&GLYPHS[(b'?' - b' ') as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_works() {
+        let mut overlay = Overlay::new();
+        overlay.fill(1, 1, 2, Ink::Background);
+        overlay.text(1, 1, "Ti", Ink::Foreground);
+        let px = |x: usize, y: usize| overlay.pixels()[y * OVERLAY_WIDTH + x];
+
+        // the top of the T and the I
+        let top = CELL_HEIGHT;
+        assert_eq!(px(CELL_WIDTH, top), Ink::Foreground);
+        assert_eq!(px(CELL_WIDTH + 2, top), Ink::Foreground);
+        assert_eq!(px(CELL_WIDTH + 3, top), Ink::Background);
+        assert_eq!(px(CELL_WIDTH * 2, top), Ink::Foreground);
+        // the stem of the T
+        assert_eq!(px(CELL_WIDTH, top + 1), Ink::Background);
+        assert_eq!(px(CELL_WIDTH + 1, top + 1), Ink::Foreground);
+        // outside of the filled cells
+        assert_eq!(px(CELL_WIDTH * 3, top), Ink::Clear);
+        assert_eq!(px(CELL_WIDTH, top - 1), Ink::Clear);
+    }
+
+    #[test]
+    fn text_is_cut_off() {
+        let mut overlay = Overlay::new();
+        overlay.text(COLUMNS - 1, ROWS - 1, "||", Ink::Foreground);
+        overlay.text(0, ROWS, "X", Ink::Foreground);
+        // the first character is drawn as "?", the second is left out
+        let top = (ROWS - 1) * CELL_HEIGHT;
+        let left = (COLUMNS - 1) * CELL_WIDTH;
+        assert_eq!(
+            overlay.pixels()[top * OVERLAY_WIDTH + left],
+            Ink::Foreground
+        );
+        assert_eq!(
+            overlay.pixels()[(top + 3) * OVERLAY_WIDTH + left],
+            Ink::Clear
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+
+/// A color with 8 bits per channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+}
+
+/// A named set of colors used to draw the display.
+/// Index 0 is the background, index 1 a lit pixel and any further colors
+/// are used for pixel values of multi-plane modes (2 = second plane only,
+/// 3 = both planes, ...).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<Color>,
+}
+
+impl Palette {
+    pub fn new(name: &str, colors: Vec<Color>) -> Result<Palette, String> {
+        if colors.len() < 2 {
+            return Err(format!(
+                "Palette \"{}\" needs at least 2 colors, got {}",
+                name,
+                colors.len()
+            ));
+        }
+        Ok(Palette {
+            name: name.to_string(),
+            colors,
+        })
+    }
+
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+
+    /// Color of a pixel value, palettes with fewer colors than the pixel
+    /// value needs fall back to their last color
+    pub fn color(&self, px: u8) -> Color {
+        match self.colors.get(px as usize) {
+            Some(color) => *color,
+            None => self.colors[self.colors.len() - 1],
+        }
+    }
+}
+
+pub const DEFAULT_PALETTE: &str = "default";
+
+pub fn builtin_palettes() -> Vec<Palette> {
+    let palettes = [
+        (DEFAULT_PALETTE, [0x101D42, 0xF78764, 0x5A8CA8, 0xFFD9C9]),
+        ("phosphor", [0x0A140A, 0x33FF66, 0x1A8033, 0xB3FFC6]),
+        ("amber", [0x140C00, 0xFFB000, 0x805800, 0xFFE0A0]),
+        ("lcd", [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F]),
+        ("high-contrast", [0x000000, 0xFFFFFF, 0xFF0000, 0xFFFF00]),
+    ];
+    palettes
+        .iter()
+        .map(|(name, colors)| Palette {
+            name: name.to_string(),
+            colors: colors.iter().map(|rgb| rgb_to_color(*rgb)).collect(),
+        })
+        .collect()
+}
+
+/// Parses a color in the "#RRGGBB" (or "RRGGBB") format
+pub fn parse_color(s: &str) -> Result<Color, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 {
+        return Err(format!("Invalid color \"{}\", expected #RRGGBB", s));
+    }
+    match u32::from_str_radix(hex, 16) {
+        Ok(rgb) => Ok(rgb_to_color(rgb)),
+        Err(_) => Err(format!("Invalid color \"{}\", expected #RRGGBB", s)),
+    }
+}
+
+/// Mixes two colors, `t` = 0.0 gives `from` and `t` = 1.0 gives `to`
+pub fn blend(from: Color, to: Color, t: f32) -> Color {
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    Color::rgb(mix(from.r, to.r), mix(from.g, to.g), mix(from.b, to.b))
+}
+
+fn rgb_to_color(rgb: u32) -> Color {
+    Color::rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+}
+
+/// All palettes available to the frontend, with one of them selected.
+/// Custom palettes from the config file come after the built-in ones and
+/// replace built-ins that have the same name.
+pub struct Palettes {
+    palettes: Vec<Palette>,
+    current: usize,
+}
+
+impl Palettes {
+    pub fn new(custom: &BTreeMap<String, Vec<String>>) -> Result<Palettes, String> {
+        let mut palettes = builtin_palettes();
+        for (name, color_strs) in custom.iter() {
+            let colors = color_strs
+                .iter()
+                .map(|s| parse_color(s))
+                .collect::<Result<Vec<Color>, String>>()?;
+            let palette = Palette::new(name, colors)?;
+            match palettes.iter_mut().find(|p| p.name == *name) {
+                Some(existing) => *existing = palette,
+                None => palettes.push(palette),
+            }
+        }
+        Ok(Palettes {
+            palettes,
+            current: 0,
+        })
+    }
+
+    pub fn current(&self) -> &Palette {
+        &self.palettes[self.current]
+    }
+
+    pub fn select(&mut self, name: &str) -> Result<(), String> {
+        match self.palettes.iter().position(|p| p.name == name) {
+            Some(i) => {
+                self.current = i;
+                Ok(())
+            }
+            None => Err(format!(
+                "Unknown palette \"{}\", available palettes are: {}",
+                name,
+                self.names().join(", ")
+            )),
+        }
+    }
+
+    pub fn cycle(&mut self) -> &Palette {
+        self.current = (self.current + 1) % self.palettes.len();
+        self.current()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.palettes.iter().map(|p| p.name.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_color_works() {
+        assert_eq!(parse_color("#F78764"), Ok(Color::rgb(0xF7, 0x87, 0x64)));
+        assert_eq!(parse_color("101d42"), Ok(Color::rgb(0x10, 0x1D, 0x42)));
+        assert!(parse_color("#FFF").is_err());
+        assert!(parse_color("#GGGGGG").is_err());
+    }
+
+    #[test]
+    fn blend_works() {
+        let from = Color::rgb(0x00, 0x10, 0xFF);
+        let to = Color::rgb(0xFF, 0x20, 0x00);
+        assert_eq!(blend(from, to, 0.0), from);
+        assert_eq!(blend(from, to, 1.0), to);
+        assert_eq!(blend(from, to, 0.5), Color::rgb(0x80, 0x18, 0x80));
+    }
+
+    #[test]
+    fn palette_color_falls_back_to_last() {
+        let (black, white) = (Color::rgb(0, 0, 0), Color::rgb(0xFF, 0xFF, 0xFF));
+        let palette = Palette::new("bw", vec![black, white]).unwrap();
+        assert_eq!(palette.background(), black);
+        assert_eq!(palette.color(1), white);
+        assert_eq!(palette.color(3), white);
+        assert!(Palette::new("mono", vec![black]).is_err());
+    }
+
+    #[test]
+    fn palettes_select_and_cycle_work() {
+        let mut custom = BTreeMap::new();
+        custom.insert(
+            "mine".to_string(),
+            vec!["#000000".to_string(), "#FFFFFF".to_string()],
+        );
+        custom.insert(
+            "amber".to_string(),
+            vec!["#000000".to_string(), "#FF8000".to_string()],
+        );
+        let mut palettes = Palettes::new(&custom).unwrap();
+        assert_eq!(palettes.current().name, DEFAULT_PALETTE);
+
+        palettes.select("amber").unwrap();
+        assert_eq!(palettes.current().color(1), Color::rgb(0xFF, 0x80, 0x00));
+        assert!(palettes.select("nope").is_err());
+
+        palettes.select("mine").unwrap();
+        assert_eq!(palettes.cycle().name, DEFAULT_PALETTE);
+    }
+}
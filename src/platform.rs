@@ -0,0 +1,167 @@
+use serde::{Serialize, Serializer};
+
+use crate::chip8::{Quirks, DEFAULT_STACK_DEPTH, MEMORY_SIZE};
+
+/// The CHIP-8 interpreters whose behavior can be emulated, identified like
+/// in the community chip-8-database
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Platform {
+    /// the original interpreter on the COSMAC VIP
+    OriginalChip8,
+    /// how most modern interpreters and emulators behave
+    ModernChip8,
+    /// SUPER-CHIP 1.1 on the HP 48
+    SuperChip,
+    /// Octo's XO-CHIP extensions
+    XoChip,
+}
+
+pub const PLATFORMS: [Platform; 4] = [
+    Platform::OriginalChip8,
+    Platform::ModernChip8,
+    Platform::SuperChip,
+    Platform::XoChip,
+];
+
+impl Platform {
+    pub fn from_id(id: &str) -> Result<Platform, String> {
+        match PLATFORMS.iter().find(|platform| platform.id() == id) {
+            Some(platform) => Ok(*platform),
+            None => Err(format!(
+                "Unknown platform \"{}\", available platforms are: {}",
+                id,
+                PLATFORMS.map(|platform| platform.id()).join(", ")
+            )),
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Platform::OriginalChip8 => "originalChip8",
+            Platform::ModernChip8 => "modernChip8",
+            Platform::SuperChip => "superchip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::OriginalChip8 => "CHIP-8 (COSMAC VIP)",
+            Platform::ModernChip8 => "CHIP-8 (modern)",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+
+    /// Name that fits into 7 characters, for lists
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            Platform::OriginalChip8 => "VIP",
+            Platform::ModernChip8 => "CHIP-8",
+            Platform::SuperChip => "SCHIP",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        let quirks = Quirks {
+            shift: false,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: false,
+            wrap: false,
+            jump: false,
+            vblank: false,
+            logic: false,
+            key_wait_release: true,
+        };
+        match self {
+            Platform::OriginalChip8 => Quirks {
+                vblank: true,
+                logic: true,
+                ..quirks
+            },
+            Platform::ModernChip8 => quirks,
+            Platform::SuperChip => Quirks {
+                shift: true,
+                memory_leave_i_unchanged: true,
+                jump: true,
+                ..quirks
+            },
+            Platform::XoChip => Quirks {
+                wrap: true,
+                ..quirks
+            },
+        }
+    }
+
+    /// Instructions per second games for the platform usually expect
+    pub fn default_ips(&self) -> u32 {
+        let tickrate = match self {
+            Platform::OriginalChip8 => 15,
+            Platform::ModernChip8 => 12,
+            Platform::SuperChip => 30,
+            Platform::XoChip => 100,
+        };
+        tickrate * 60
+    }
+
+    /// Bytes of memory programs have on the platform
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::XoChip => 0x10000,
+            _ => MEMORY_SIZE,
+        }
+    }
+
+    /// Calls that can be nested on the platform
+    pub fn stack_depth(&self) -> usize {
+        match self {
+            Platform::OriginalChip8 => 12,
+            _ => DEFAULT_STACK_DEPTH,
+        }
+    }
+
+    /// Picks XO-CHIP for ROMs that are too big for the 4K of the other
+    /// platforms, as it's the only one they can be meant for
+    pub fn detect(rom_size: usize, start_address: u16) -> Option<Platform> {
+        if start_address as usize + rom_size > MEMORY_SIZE {
+            Some(Platform::XoChip)
+        } else {
+            None
+        }
+    }
+}
+
+impl Serialize for Platform {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_id_works() {
+        for platform in PLATFORMS {
+            assert_eq!(Platform::from_id(platform.id()), Ok(platform));
+        }
+        assert!(Platform::from_id("megachip8").is_err());
+    }
+
+    #[test]
+    fn quirks_work() {
+        assert!(Platform::OriginalChip8.quirks().vblank);
+        assert!(!Platform::ModernChip8.quirks().vblank);
+        assert!(Platform::SuperChip.quirks().jump);
+        assert!(Platform::XoChip.quirks().wrap);
+    }
+
+    #[test]
+    fn detect_works() {
+        assert_eq!(Platform::detect(3584, 0x200), None);
+        assert_eq!(Platform::detect(3585, 0x200), Some(Platform::XoChip));
+        assert_eq!(Platform::detect(0x200, 0xF00), Some(Platform::XoChip));
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::chip8::Instruction;
+
+/// Counts of the instructions a program ran, by the address they're at and
+/// by kind, to see which code ran and where the time went
+pub struct Profile {
+    /// times the instruction starting at each address was run
+    counts: Vec<u64>,
+    /// times each kind of instruction was run, by variant name
+    kinds: BTreeMap<&'static str, u64>,
+}
+
+impl Profile {
+    pub fn new(memory_size: usize) -> Profile {
+        Profile {
+            counts: vec![0; memory_size],
+            kinds: BTreeMap::new(),
+        }
+    }
+
+    /// Counts a run of the instruction at the address
+    pub fn record(&mut self, address: u16, instruction: &Instruction) {
+        self.counts[address as usize] += 1;
+        *self.kinds.entry(instruction.name()).or_insert(0) += 1;
+    }
+
+    /// Times the instruction at each address was run
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn kinds(&self) -> &BTreeMap<&'static str, u64> {
+        &self.kinds
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Whether a run instruction covered the byte, as its first or second
+    pub fn covered(&self, address: usize) -> bool {
+        self.counts[address] > 0 || address > 0 && self.counts[address - 1] > 0
+    }
+
+    /// The runs of bytes within `range` no instruction that ran covered,
+    /// which are data or code that never ran
+    pub fn unexecuted(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        let mut regions: Vec<Range<usize>> = Vec::new();
+        for address in range.start..range.end.min(self.counts.len()) {
+            if self.covered(address) {
+                continue;
+            }
+            match regions.last_mut() {
+                Some(region) if region.end == address => region.end += 1,
+                _ => regions.push(address..address + 1),
+            }
+        }
+        regions
+    }
+
+    /// The addresses of the instructions run the most, with their counts,
+    /// the most run first
+    pub fn hot_spots(&self, count: usize) -> Vec<(u16, u64)> {
+        let mut spots: Vec<(u16, u64)> = (self.counts.iter().enumerate())
+            .filter(|(_, runs)| **runs > 0)
+            .map(|(address, runs)| (address as u16, *runs))
+            .collect();
+        spots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        spots.truncate(count);
+        spots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_works() {
+        let mut profile = Profile::new(16);
+        let ld = Instruction::Ld { reg: 0, val: 1 };
+        profile.record(2, &ld);
+        profile.record(4, &Instruction::Jmp { address: 2 });
+        profile.record(2, &ld);
+        assert_eq!(profile.counts()[2], 2);
+        assert_eq!(profile.counts()[4], 1);
+        assert_eq!(profile.total(), 3);
+        assert_eq!(
+            profile.kinds().iter().collect::<Vec<_>>(),
+            [(&"Jmp", &1), (&"Ld", &2)]
+        );
+        assert_eq!(profile.hot_spots(1), [(2, 2)]);
+        assert_eq!(profile.hot_spots(5), [(2, 2), (4, 1)]);
+    }
+
+    #[test]
+    fn unexecuted_works() {
+        let mut profile = Profile::new(16);
+        profile.record(2, &Instruction::Cls);
+        // an instruction at an odd address covers the byte after it too
+        profile.record(7, &Instruction::Cls);
+        assert!(profile.covered(3));
+        assert!(!profile.covered(4));
+        assert_eq!(profile.unexecuted(0..12), [0..2, 4..7, 9..12]);
+        assert_eq!(profile.unexecuted(2..4), []);
+        // cut off at the end of memory
+        let to_the_end = 10..16;
+        assert_eq!(profile.unexecuted(10..20), [to_the_end]);
+    }
+}
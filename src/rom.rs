@@ -0,0 +1,91 @@
+use crate::chip8::decode_instruction;
+
+// signatures of files that get picked by mistake
+const SIGNATURES: [(&[u8], &str); 7] = [
+    (b"\x89PNG", "a PNG image"),
+    (b"GIF8", "a GIF image"),
+    (b"\xFF\xD8\xFF", "a JPEG image"),
+    (b"PK\x03\x04", "a ZIP archive"),
+    (b"\x1F\x8B", "a gzip archive"),
+    (b"\x7FELF", "a program for Linux"),
+    (b"%PDF", "a PDF document"),
+];
+
+// instructions looked at from the start of the ROM, after that code and
+// data are too mixed to tell anything
+const CHECKED_INSTRUCTIONS: usize = 16;
+
+/// Guesses whether a ROM is something else than CHIP-8 code, like an image
+/// or the source of a program. Gives a warning for each reason to think so,
+/// none of them are certain so the ROM can be run anyway.
+pub fn check(rom: &[u8]) -> Vec<String> {
+    if let Some((_, kind)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| rom.starts_with(signature))
+    {
+        return vec![format!("looks like {} rather than a CHIP-8 ROM", kind)];
+    }
+    if rom.len() >= 16
+        && rom
+            .iter()
+            .all(|byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace())
+    {
+        return vec![
+            "looks like a text file, maybe the source of a program rather than its ROM".to_string(),
+        ];
+    }
+
+    let mut warnings = Vec::new();
+    let instructions: Vec<u16> = rom
+        .chunks_exact(2)
+        .take(CHECKED_INSTRUCTIONS)
+        .map(|bytes| (bytes[0] as u16) << 8 | bytes[1] as u16)
+        .collect();
+    if let Some(first) = instructions.first() {
+        if decode_instruction(*first).is_err() {
+            warnings.push(format!(
+                "starts with {:04X}, which isn't a CHIP-8 instruction",
+                first
+            ));
+        }
+    }
+    let unknown = instructions
+        .iter()
+        .filter(|instruction| decode_instruction(**instruction).is_err())
+        .count();
+    if unknown * 2 > instructions.len() {
+        warnings.push(format!(
+            "{} of its first {} instructions aren't CHIP-8 instructions",
+            unknown,
+            instructions.len()
+        ));
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_works() {
+        // 6005 A20A D015 1206 followed by a sprite
+        let rom = [0x60, 0x05, 0xA2, 0x0A, 0xD0, 0x15, 0x12, 0x06, 0xF0, 0x90];
+        assert!(check(&rom).is_empty());
+        assert!(check(&[0x00]).is_empty());
+
+        assert_eq!(
+            check(b"\x89PNG\r\n\x1A\n"),
+            ["looks like a PNG image rather than a CHIP-8 ROM"]
+        );
+        assert_eq!(check(b"loop:\n  JP loop\n").len(), 1);
+        assert_eq!(
+            check(&[0xFF, 0xFF, 0x60, 0x05]),
+            ["starts with FFFF, which isn't a CHIP-8 instruction"]
+        );
+        assert_eq!(
+            check(&[0x60, 0x05, 0xFF, 0xFF, 0x00, 0x00, 0xE1, 0xFF]),
+            ["3 of its first 4 instructions aren't CHIP-8 instructions"]
+        );
+    }
+}
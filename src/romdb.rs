@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use crate::chip8::Quirks;
+use crate::platform::Platform;
+
+/// A program in the database, which can have several ROMs (versions)
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Program {
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub release: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub roms: BTreeMap<String, RomEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RomEntry {
+    #[serde(default)]
+    pub file: Option<String>,
+    /// ids of the platforms the ROM runs on, the preferred one first
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    /// quirks the ROM needs on top of the ones of its platforms
+    #[serde(default)]
+    pub quirky_platforms: BTreeMap<String, DbQuirks>,
+    /// instructions per frame
+    #[serde(default)]
+    pub tickrate: Option<u32>,
+    /// CHIP-8 keys for the roles "up", "down", "left", "right", "a" and "b"
+    #[serde(default)]
+    pub keys: BTreeMap<String, u8>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DbQuirks {
+    pub shift: Option<bool>,
+    pub memory_increment_by_x: Option<bool>,
+    pub memory_leave_i_unchanged: Option<bool>,
+    pub wrap: Option<bool>,
+    pub jump: Option<bool>,
+    pub vblank: Option<bool>,
+    pub logic: Option<bool>,
+}
+
+impl RomEntry {
+    /// The first platform of the ROM that can be emulated
+    pub fn platform(&self) -> Option<Platform> {
+        self.platforms
+            .iter()
+            .find_map(|id| Platform::from_id(id).ok())
+    }
+
+    /// Applies the quirks the ROM needs on the given platform, on top of
+    /// the platform's own quirks
+    pub fn apply_quirks(&self, platform: Platform, quirks: &mut Quirks) {
+        if let Some(overrides) = self.quirky_platforms.get(platform.id()) {
+            let fields = [
+                (&mut quirks.shift, overrides.shift),
+                (
+                    &mut quirks.memory_increment_by_x,
+                    overrides.memory_increment_by_x,
+                ),
+                (
+                    &mut quirks.memory_leave_i_unchanged,
+                    overrides.memory_leave_i_unchanged,
+                ),
+                (&mut quirks.wrap, overrides.wrap),
+                (&mut quirks.jump, overrides.jump),
+                (&mut quirks.vblank, overrides.vblank),
+                (&mut quirks.logic, overrides.logic),
+            ];
+            for (quirk, value) in fields {
+                if let Some(value) = value {
+                    *quirk = value;
+                }
+            }
+        }
+    }
+
+    /// Instructions per second, the database counts them per frame
+    pub fn ips(&self) -> Option<u32> {
+        self.tickrate.map(|tickrate| tickrate * 60)
+    }
+}
+
+/// A ROM found in the database
+pub struct RomMatch<'a> {
+    pub program: &'a Program,
+    pub rom: &'a RomEntry,
+}
+
+/// The database built into the emulator, used unless the config picks
+/// another one
+const BUILT_IN: &str = include_str!("../data/programs.json");
+
+/// ROM database in the format of the community chip-8-database
+/// (https://github.com/chip-8-community/chip-8-database), which is a
+/// `programs.json` listing programs and their ROMs by SHA-1 hash
+pub struct RomDb {
+    programs: Vec<Program>,
+    // SHA-1 -> index into programs
+    index: HashMap<String, usize>,
+}
+
+impl RomDb {
+    pub fn parse(json: &str) -> Result<RomDb, String> {
+        let programs: Vec<Program> = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        let mut index = HashMap::new();
+        for (i, program) in programs.iter().enumerate() {
+            for sha1 in program.roms.keys() {
+                index.insert(sha1.to_lowercase(), i);
+            }
+        }
+        Ok(RomDb { programs, index })
+    }
+
+    /// The database that comes with the emulator
+    pub fn built_in() -> RomDb {
+        RomDb::parse(BUILT_IN).expect("the built-in ROM database is valid")
+    }
+
+    pub fn load(path: &Path) -> Result<RomDb, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read ROM database {}: {}", path.display(), err))?;
+        RomDb::parse(&json)
+            .map_err(|err| format!("Invalid ROM database {}: {}", path.display(), err))
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomMatch<'_>> {
+        let sha1 = sha1_hex(rom);
+        let program = &self.programs[*self.index.get(&sha1)?];
+        let rom = program
+            .roms
+            .iter()
+            .find(|(hash, _)| hash.to_lowercase() == sha1)
+            .map(|(_, rom)| rom)?;
+        Some(RomMatch { program, rom })
+    }
+}
+
+pub fn sha1_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATABASE: &str = r##"[
+        {
+            "title": "Test Game",
+            "authors": ["Someone"],
+            "release": "2024",
+            "roms": {
+                "a9993e364706816aba3e25717850c26c9cd0d89d": {
+                    "file": "test.ch8",
+                    "platforms": ["megachip8", "superchip", "xochip"],
+                    "quirkyPlatforms": {
+                        "superchip": { "shift": false, "wrap": true }
+                    },
+                    "tickrate": 20,
+                    "keys": { "up": 5, "a": 6 },
+                    "colors": { "pixels": ["#000000", "#ffffff"] }
+                }
+            }
+        },
+        {
+            "title": "Other Game",
+            "roms": {
+                "DA39A3EE5E6B4B0D3255BFEF95601890AFD80709": {}
+            }
+        }
+    ]"##;
+
+    #[test]
+    fn sha1_hex_works() {
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn lookup_works() {
+        let db = RomDb::parse(DATABASE).unwrap();
+
+        let found = db.lookup(b"abc").unwrap();
+        assert_eq!(found.program.title, "Test Game");
+        assert_eq!(found.rom.platform(), Some(Platform::SuperChip));
+        assert_eq!(found.rom.ips(), Some(1200));
+        assert_eq!(found.rom.keys["up"], 5);
+
+        let mut quirks = Platform::SuperChip.quirks();
+        found.rom.apply_quirks(Platform::SuperChip, &mut quirks);
+        assert!(!quirks.shift);
+        assert!(quirks.wrap);
+        assert!(quirks.jump);
+        let mut quirks = Platform::XoChip.quirks();
+        found.rom.apply_quirks(Platform::XoChip, &mut quirks);
+        assert_eq!(quirks, Platform::XoChip.quirks());
+
+        // hashes are matched regardless of case
+        let found = db.lookup(b"").unwrap();
+        assert_eq!(found.program.title, "Other Game");
+        assert_eq!(found.rom.platform(), None);
+        assert_eq!(found.rom.ips(), None);
+
+        assert!(db.lookup(b"abcd").is_none());
+        assert!(RomDb::parse("{}").is_err());
+    }
+
+    /// The IBM logo ROM, which is in the built-in database
+    const IBM_LOGO: [u8; 132] = [
+        0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08, 0xD0, 0x1F, 0x70, 0x09, 0xA2, 0x39, 0xD0,
+        0x1F, 0xA2, 0x48, 0x70, 0x08, 0xD0, 0x1F, 0x70, 0x04, 0xA2, 0x57, 0xD0, 0x1F, 0x70, 0x08,
+        0xA2, 0x66, 0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x75, 0xD0, 0x1F, 0x12, 0x28, 0xFF, 0x00, 0xFF,
+        0x00, 0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0xFF, 0x00, 0xFF, 0xFF, 0x00, 0xFF,
+        0x00, 0x38, 0x00, 0x3F, 0x00, 0x3F, 0x00, 0x38, 0x00, 0xFF, 0x00, 0xFF, 0x80, 0x00, 0xE0,
+        0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0xF8, 0x00, 0xFC,
+        0x00, 0x3E, 0x00, 0x3F, 0x00, 0x3B, 0x00, 0x39, 0x00, 0xF8, 0x00, 0xF8, 0x03, 0x00, 0x07,
+        0x00, 0x0F, 0x00, 0xBF, 0x00, 0xFB, 0x00, 0xF3, 0x00, 0xE3, 0x00, 0x43, 0xE0, 0x00, 0xE0,
+        0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0xE0, 0x00, 0xE0,
+    ];
+
+    #[test]
+    fn built_in_works() {
+        let db = RomDb::built_in();
+        assert_eq!(
+            sha1_hex(&IBM_LOGO),
+            "1ba58656810b67fd131eb9af3e3987863bf26c90"
+        );
+        let found = db.lookup(&IBM_LOGO).unwrap();
+        assert_eq!(found.program.title, "IBM Logo");
+        assert_eq!(found.rom.platform(), Some(Platform::OriginalChip8));
+        assert!(db.lookup(&IBM_LOGO[..130]).is_none());
+    }
+}
@@ -0,0 +1,676 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::chip8::{emulation_step_until, Chip8};
+use crate::display::Rect;
+use crate::jit::Jit;
+use crate::settings::Settings;
+use crate::symbols::Symbols;
+
+const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// Sound is made at this many samples per second, one channel
+pub const SAMPLE_RATE: u32 = 44100;
+const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+// the beep is a square wave
+const BEEP_FREQUENCY: f32 = 440.0;
+const BEEP_VOLUME: f32 = 0.2;
+
+/// How the instructions are run. Both run programs the same, instruction by
+/// instruction, the JIT is just faster.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// decodes each instruction (once, they're cached) and runs it
+    Interpreter,
+    /// compiles runs of instructions into closures, see `jit::Jit`
+    Jit,
+}
+
+pub const ENGINES: [Engine; 2] = [Engine::Interpreter, Engine::Jit];
+
+impl Engine {
+    pub fn from_name(name: &str) -> Result<Engine, String> {
+        match ENGINES.iter().find(|engine| engine.name() == name) {
+            Some(engine) => Ok(*engine),
+            None => Err(format!(
+                "Unknown engine \"{}\", available engines are: {}",
+                name,
+                ENGINES.map(|engine| engine.name()).join(", ")
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Engine::Interpreter => "interpreter",
+            Engine::Jit => "jit",
+        }
+    }
+}
+
+/// The title of the window or the terminal playing the ROM
+pub fn window_title(rom_path: Option<&str>) -> String {
+    let file_name = rom_path
+        .map(Path::new)
+        .and_then(|path| path.file_name())
+        .map(|name| name.to_string_lossy().to_string());
+    match file_name {
+        Some(name) => format!("chip-8 - {}", name),
+        None => "chip-8 - drop a ROM to play".to_string(),
+    }
+}
+
+/// A ROM loaded into a machine, with the settings it's run with
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub settings: Settings,
+    pub chip8: Chip8,
+    /// names of addresses, when the program's source is known
+    pub symbols: Symbols,
+}
+
+impl Program {
+    /// Starts the ROM over on a fresh machine, the machine is kept when
+    /// that fails
+    pub fn reset(&mut self, seed: Option<u64>) -> Result<(), String> {
+        self.chip8 = make_chip8(&self.rom, &self.settings, seed)?;
+        Ok(())
+    }
+
+    /// The emulation error with the address it happened at named by the
+    /// symbols, e.g. "Unknown instruction 0000 at draw_player+6 (2AA)".
+    /// Errors end with the address of the instruction that failed, which is
+    /// where the machine is left.
+    pub fn describe_error(&self, err: String) -> String {
+        let pc = self.chip8.regs.pc;
+        match err.strip_suffix(&format!(" at {:03X}", pc)) {
+            Some(message) => format!("{} at {}", message, self.symbols.describe(pc)),
+            None => err,
+        }
+    }
+}
+
+/// Makes a machine for the settings and loads the ROM into it
+pub fn make_chip8(rom: &[u8], settings: &Settings, seed: Option<u64>) -> Result<Chip8, String> {
+    let mut chip8 = Chip8::with_memory_size(settings.memory_size());
+    chip8.load_font();
+    chip8.load_rom(rom, settings.start_address)?;
+    chip8.regs.pc = settings.start_address;
+    chip8.quirks = settings.quirks;
+    chip8.stack_depth = settings.stack_depth;
+    if let Some(seed) = seed {
+        chip8.rng = fastrand::Rng::with_seed(seed);
+    }
+    if settings.engine == Engine::Jit {
+        // the JIT decodes instructions when it compiles them
+        chip8.instruction_cache = None;
+        chip8.jit = Some(Jit::new(chip8.memory.len()));
+    }
+    Ok(chip8)
+}
+
+/// What to do with the frame after the input was handled
+#[derive(Debug, PartialEq)]
+pub enum Poll {
+    Run,
+    /// skip running the frame, it's still presented
+    Pause,
+    Quit,
+}
+
+/// Why a frame was cut short
+pub enum Stop {
+    Breakpoint,
+    Error(String),
+}
+
+/// The parts of the emulator the player sees and touches. The runner asks
+/// the frontend for input, runs the frame and hands it the result.
+pub trait Frontend {
+    /// Handles the input since the last frame and sets the keys that are
+    /// held. The program can be changed too, e.g. reset or switched for
+    /// another ROM.
+    fn poll_input(&mut self, program: &mut Program) -> Poll;
+
+    /// Shows the display, after a frame was run (or paused) with `executed`
+    /// instructions. `dirty` is the part of the display that changed since
+    /// the last frame, frontends don't need to draw it again without one.
+    fn present(&mut self, program: &Program, executed: u32, dirty: Option<Rect>);
+
+    /// Plays the sound of a frame, `SAMPLE_RATE` samples a second
+    fn output_audio(&mut self, _samples: &[f32]) {}
+
+    /// Whether to stop before the instruction at the address, for breakpoints
+    fn should_stop(&mut self, _pc: u16) -> bool {
+        false
+    }
+
+    /// Called when a frame was cut short, giving back an error ends the run
+    fn stopped(&mut self, _program: &Program, stop: Stop) -> Result<(), String> {
+        match stop {
+            Stop::Breakpoint => Ok(()),
+            Stop::Error(err) => Err(err),
+        }
+    }
+
+    /// Whether frames are run at 60 per second, or as fast as they can be
+    fn paced(&self) -> bool {
+        true
+    }
+}
+
+/// A frontend without input or output, that quits after some frames or
+/// after some time
+pub struct NullFrontend {
+    frames_left: u32,
+    /// when to quit, no matter how many frames are left
+    deadline: Option<Instant>,
+    /// frames and instructions run so far
+    pub frames: u32,
+    pub instructions: u64,
+}
+
+impl NullFrontend {
+    pub fn new(frames: u32) -> NullFrontend {
+        NullFrontend {
+            frames_left: frames,
+            deadline: None,
+            frames: 0,
+            instructions: 0,
+        }
+    }
+
+    /// Runs for the duration, counted from now
+    pub fn timed(duration: Duration) -> NullFrontend {
+        NullFrontend {
+            deadline: Some(Instant::now() + duration),
+            ..NullFrontend::new(u32::MAX)
+        }
+    }
+}
+
+impl Frontend for NullFrontend {
+    fn poll_input(&mut self, _program: &mut Program) -> Poll {
+        let timed_out = self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline);
+        if self.frames_left == 0 || timed_out {
+            return Poll::Quit;
+        }
+        self.frames_left -= 1;
+        Poll::Run
+    }
+
+    fn present(&mut self, _program: &Program, executed: u32, _dirty: Option<Rect>) {
+        self.frames += 1;
+        self.instructions += executed as u64;
+    }
+
+    fn paced(&self) -> bool {
+        false
+    }
+}
+
+/// Runs a program frame by frame through a frontend
+pub struct Runner<F: Frontend> {
+    pub frontend: F,
+    pub program: Program,
+    samples: Vec<f32>,
+    /// where the beep's wave is at, in cycles
+    phase: f32,
+}
+
+impl<F: Frontend> Runner<F> {
+    pub fn new(frontend: F, program: Program) -> Runner<F> {
+        Runner {
+            frontend,
+            program,
+            samples: vec![0.0; SAMPLES_PER_FRAME],
+            phase: 0.0,
+        }
+    }
+
+    /// Runs until the frontend quits, or until an error it doesn't handle
+    pub fn run(&mut self) -> Result<(), String> {
+        let mut next_frame = Instant::now();
+        loop {
+            let (executed, running) = match self.frontend.poll_input(&mut self.program) {
+                Poll::Run => (self.run_frame()?, true),
+                Poll::Pause => (0, false),
+                Poll::Quit => return Ok(()),
+            };
+            // the sound timer is left as it is while paused, so it's silenced
+            self.make_sound(running && self.program.chip8.regs.st > 0);
+            self.frontend.output_audio(&self.samples);
+            let dirty = self.program.chip8.display.take_dirty();
+            self.frontend.present(&self.program, executed, dirty);
+
+            if self.frontend.paced() {
+                // frames are started at 60 per second, no matter how long
+                // they take, unless they fall behind by more than a frame
+                next_frame += FRAME_TIME;
+                let now = Instant::now();
+                match next_frame.checked_duration_since(now) {
+                    Some(wait) => std::thread::sleep(wait),
+                    None if now - next_frame > FRAME_TIME => next_frame = now,
+                    None => {}
+                }
+            }
+        }
+    }
+
+    fn run_frame(&mut self) -> Result<u32, String> {
+        let instructions_per_frame = self.program.settings.instructions_per_frame();
+        let frontend = &mut self.frontend;
+        let step = emulation_step_until(&mut self.program.chip8, instructions_per_frame, |pc| {
+            frontend.should_stop(pc)
+        });
+        match step {
+            Ok((executed, false)) => Ok(executed),
+            Ok((executed, true)) => {
+                self.frontend.stopped(&self.program, Stop::Breakpoint)?;
+                Ok(executed)
+            }
+            Err(err) => {
+                let err = self.program.describe_error(err);
+                self.frontend.stopped(&self.program, Stop::Error(err))?;
+                Ok(0)
+            }
+        }
+    }
+
+    fn make_sound(&mut self, beeping: bool) {
+        if !beeping {
+            self.samples.fill(0.0);
+            return;
+        }
+        let step = BEEP_FREQUENCY / SAMPLE_RATE as f32;
+        for sample in self.samples.iter_mut() {
+            *sample = if self.phase < 0.5 {
+                BEEP_VOLUME
+            } else {
+                -BEEP_VOLUME
+            };
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::StackFrame;
+    use crate::cli::Options;
+    use crate::config::Config;
+    use crate::profile::Profile;
+    use chip_8::display::Display;
+
+    /// A program running 4 instructions a frame
+    fn program(rom: &[u8]) -> Program {
+        program_with(
+            rom,
+            Options {
+                ips: Some(240),
+                ..Options::default()
+            },
+        )
+    }
+
+    fn program_with(rom: &[u8], options: Options) -> Program {
+        let settings =
+            Settings::resolve(&Config::default(), None, None, rom.len(), &options).unwrap();
+        Program {
+            rom: rom.to_vec(),
+            chip8: make_chip8(rom, &settings, options.seed).unwrap(),
+            settings,
+            symbols: Symbols::default(),
+        }
+    }
+
+    /// The state of the machine after a frame
+    #[derive(Debug, PartialEq)]
+    struct Frame {
+        executed: Result<(u32, bool), String>,
+        pc: u16,
+        general: [u8; 16],
+        i: u16,
+        timers: (u8, u8),
+        stack: Vec<StackFrame>,
+        display: Display,
+        memory: Vec<u8>,
+    }
+
+    /// Runs a ROM for some frames with the engine. The key 5 is held every
+    /// other frame, and every third frame stops at a breakpoint at 0x2F0.
+    fn trace(rom: &[u8], platform: &str, engine: &str, frames: u32) -> Vec<Frame> {
+        let options = Options {
+            platform: Some(platform.to_string()),
+            engine: Some(engine.to_string()),
+            seed: Some(7),
+            ..Options::default()
+        };
+        let mut program = program_with(rom, options);
+        let chip8 = &mut program.chip8;
+        let instructions_per_frame = program.settings.instructions_per_frame();
+        (0..frames)
+            .map(|frame| {
+                chip8.key_down[5] = frame % 2 == 1;
+                Frame {
+                    executed: emulation_step_until(chip8, instructions_per_frame, |pc| pc == 0x2F0),
+                    pc: chip8.regs.pc,
+                    general: chip8.regs.general,
+                    i: chip8.regs.i,
+                    timers: (chip8.regs.dt, chip8.regs.st),
+                    stack: chip8.stack.clone(),
+                    display: chip8.display.clone(),
+                    memory: chip8.memory.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Runs some frames and keeps track of what the runner hands it
+    struct TestFrontend {
+        frames_left: u32,
+        breakpoint: Option<u16>,
+        executed: Vec<u32>,
+        dirty: Vec<Option<Rect>>,
+        beeping: Vec<bool>,
+        breakpoints_hit: u32,
+    }
+
+    impl TestFrontend {
+        fn new(frames: u32) -> TestFrontend {
+            TestFrontend {
+                frames_left: frames,
+                breakpoint: None,
+                executed: Vec::new(),
+                dirty: Vec::new(),
+                beeping: Vec::new(),
+                breakpoints_hit: 0,
+            }
+        }
+    }
+
+    impl Frontend for TestFrontend {
+        fn poll_input(&mut self, _program: &mut Program) -> Poll {
+            if self.frames_left == 0 {
+                return Poll::Quit;
+            }
+            self.frames_left -= 1;
+            Poll::Run
+        }
+
+        fn present(&mut self, _program: &Program, executed: u32, dirty: Option<Rect>) {
+            self.executed.push(executed);
+            self.dirty.push(dirty);
+        }
+
+        fn output_audio(&mut self, samples: &[f32]) {
+            assert_eq!(samples.len(), SAMPLES_PER_FRAME);
+            self.beeping
+                .push(samples.iter().any(|sample| *sample != 0.0));
+        }
+
+        fn should_stop(&mut self, pc: u16) -> bool {
+            self.breakpoint == Some(pc)
+        }
+
+        fn stopped(&mut self, _program: &Program, stop: Stop) -> Result<(), String> {
+            match stop {
+                Stop::Breakpoint => self.breakpoints_hit += 1,
+                Stop::Error(err) => return Err(err),
+            }
+            Ok(())
+        }
+
+        fn paced(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn null_frontend_works() {
+        // V0 = 5, sound timer = V0, then a loop jumping to itself
+        let mut runner = Runner::new(
+            NullFrontend::new(3),
+            program(&[0x60, 5, 0xF0, 0x18, 0x12, 0x04]),
+        );
+        assert_eq!(runner.run(), Ok(()));
+        assert_eq!(runner.frontend.frames, 3);
+        assert_eq!(runner.frontend.instructions, 12);
+        assert_eq!(runner.program.chip8.regs.st, 2);
+        assert_eq!(runner.program.chip8.regs.pc, 0x204);
+
+        let mut runner = Runner::new(NullFrontend::timed(Duration::ZERO), program(&[0x12, 0x00]));
+        assert_eq!(runner.run(), Ok(()));
+        assert_eq!(runner.frontend.frames, 0);
+
+        let mut runner = Runner::new(NullFrontend::new(3), program(&[0x01, 0x23]));
+        assert_eq!(
+            runner.run(),
+            Err("Unknown instruction 0123 at 200".to_string())
+        );
+    }
+
+    #[test]
+    fn reset_works() {
+        let mut runner = Runner::new(NullFrontend::new(3), program(&[0x60, 5, 0x12, 0x02]));
+        runner.run().unwrap();
+        assert_eq!(runner.program.chip8.regs.general[0], 5);
+        assert_eq!(runner.program.reset(None), Ok(()));
+        assert_eq!(runner.program.chip8.regs.general[0], 0);
+        assert_eq!(runner.program.chip8.regs.pc, 0x200);
+
+        // a machine that can't be made leaves the old one running
+        runner.program.chip8.regs.general[0] = 5;
+        runner.program.rom = vec![0; 0x1000];
+        assert!(runner.program.reset(None).is_err());
+        assert_eq!(runner.program.chip8.regs.general[0], 5);
+    }
+
+    #[test]
+    fn errors_name_their_address() {
+        let rom = [
+            0x22, 0x04, // call draw
+            0x12, 0x02, // loop forever
+            0x60, 0x01, // draw: V0 = 1
+            0x01, 0x23, // unknown
+        ];
+        let mut program = program(&rom);
+        program.symbols = Symbols::parse("0x204 draw").unwrap();
+        let mut runner = Runner::new(NullFrontend::new(1), program);
+        assert_eq!(
+            runner.run(),
+            Err("Unknown instruction 0123 at draw+2 (206)".to_string())
+        );
+
+        // errors that don't end with where the machine stopped are left alone
+        let program = &runner.program;
+        assert_eq!(
+            program.describe_error("Unknown instruction 0123 at 200".to_string()),
+            "Unknown instruction 0123 at 200"
+        );
+        assert_eq!(
+            program.describe_error("program counter ran past the end of memory (FFF)".to_string()),
+            "program counter ran past the end of memory (FFF)"
+        );
+    }
+
+    #[test]
+    fn self_modifying_code_works() {
+        let rom = [
+            0x60, 0x63, // V0 = 0x63
+            0x61, 0x07, // V1 = 0x07
+            0x22, 0x10, // call 0x210
+            0xA2, 0x10, // I = 0x210
+            0xF1, 0x55, // write V0 and V1 at I, which makes 0x210 "V3 = 7"
+            0x22, 0x10, // call 0x210 again
+            0x12, 0x0C, // loop
+            0x12, 0x0E, // never run
+            0x63, 0x05, // 0x210: V3 = 5
+            0x00, 0xEE, // return
+        ];
+        for engine in ["cached", "uncached", "jit"] {
+            let mut program = program(&rom);
+            match engine {
+                "uncached" => program.chip8.instruction_cache = None,
+                "jit" => program.chip8.jit = Some(Jit::new(program.chip8.memory.len())),
+                _ => {}
+            }
+            let mut runner = Runner::new(NullFrontend::new(3), program);
+            runner.run().unwrap();
+            assert_eq!(runner.program.chip8.regs.general[3], 7);
+            assert_eq!(runner.program.chip8.regs.pc, 0x20C);
+        }
+    }
+
+    #[test]
+    fn engines_profile_the_same() {
+        let rom = [
+            0x60, 0x00, // V0 = 0
+            0x22, 0x0A, // call 0x20A
+            0x30, 0x10, // skip if V0 == 16
+            0x12, 0x02, // loop
+            0x12, 0x08, // done
+            0x70, 0x01, // 0x20A: V0 += 1
+            0x00, 0xEE, // return
+            0x00, 0xE0, // never run
+        ];
+        let profiles: Vec<_> = ["cached", "uncached", "jit"]
+            .into_iter()
+            .map(|engine| {
+                let options = Options {
+                    ips: Some(6000),
+                    ..Options::default()
+                };
+                let mut program = program_with(&rom, options);
+                match engine {
+                    "uncached" => program.chip8.instruction_cache = None,
+                    "jit" => program.chip8.jit = Some(Jit::new(program.chip8.memory.len())),
+                    _ => {}
+                }
+                program.chip8.profile = Some(Profile::new(program.chip8.memory.len()));
+                let mut runner = Runner::new(NullFrontend::new(2), program);
+                runner.run().unwrap();
+                runner.program.chip8.profile.unwrap()
+            })
+            .collect();
+        let profile = &profiles[0];
+        assert_eq!(profile.counts()[0x200], 1);
+        assert_eq!(profile.counts()[0x20A], 16);
+        assert_eq!(profile.kinds()["Call"], 16);
+        let never_run = 0x20E..0x210;
+        assert_eq!(profile.unexecuted(0x200..0x210), [never_run]);
+        for other in &profiles[1..] {
+            assert_eq!(other.counts(), profile.counts());
+            assert_eq!(other.kinds(), profile.kinds());
+        }
+    }
+
+    #[test]
+    fn engines_run_the_same() {
+        let rom = [
+            0xC0, 0x3F, // V0 = random 0 - 63
+            0xC1, 0x1F, // V1 = random 0 - 31
+            0xF2, 0x29, // I = digit V2
+            0xD0, 0x15, // draw it at V0, V1
+            0x72, 0x01, // V2 += 1
+            0xA3, 0x00, // I = 0x300
+            0xF2, 0x33, // write V2 as decimal digits at I
+            0x32, 0x10, // skip if V2 == 16
+            0x12, 0x00, // loop
+            0x65, 0x05, // V5 = 5
+            0xE5, 0x9E, // skip if key 5 is held
+            0xF3, 0x0A, // wait for a key into V3
+            0xF3, 0x15, // delay timer = V3
+            0x22, 0xF0, // call 0x2F0
+            0x12, 0x1C, // loop
+        ];
+        // without it the call runs into empty memory
+        let mut with_subroutine = rom.to_vec();
+        with_subroutine.resize(0xF0, 0);
+        with_subroutine.extend_from_slice(&[0x00, 0xEE]); // 0x2F0: return
+        let roms = [
+            crate::waiting::rom().unwrap(),
+            rom.to_vec(),
+            with_subroutine,
+            // an unknown instruction after a jump
+            vec![0x60, 0x05, 0x12, 0x06, 0x01, 0x23, 0x12, 0x04],
+            // recursion until the stack overflows, at a depth that depends
+            // on the platform
+            vec![0x70, 0x01, 0x22, 0x00],
+            // a return without a call
+            vec![0x60, 0x05, 0x00, 0xEE],
+        ];
+        for rom in &roms {
+            for platform in ["originalChip8", "modernChip8", "xochip"] {
+                assert_eq!(
+                    trace(rom, platform, "jit", 60),
+                    trace(rom, platform, "interpreter", 60),
+                    "{:02X?} on {}",
+                    rom,
+                    platform
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sound_follows_the_sound_timer() {
+        let mut runner = Runner::new(
+            TestFrontend::new(3),
+            program(&[0x60, 2, 0xF0, 0x18, 0x12, 0x04]),
+        );
+        runner.run().unwrap();
+        assert_eq!(runner.frontend.beeping, [true, false, false]);
+    }
+
+    #[test]
+    fn unchanged_frames_arent_dirty() {
+        let rom = [
+            0x00, 0xE0, // clear the screen
+            0xD0, 0x15, // draw the 0 of the font
+            0x60, 0x00, // V0 = 0
+            0x12, 0x00, // loop, every frame draws the same
+        ];
+        let mut runner = Runner::new(TestFrontend::new(3), program(&rom));
+        runner.run().unwrap();
+        let all = Rect {
+            x: 0,
+            y: 0,
+            width: 64,
+            height: 32,
+        };
+        assert_eq!(runner.frontend.dirty, [Some(all), None, None]);
+
+        // drawn every frame without clearing, the sprite blinks
+        let rom = [0xD0, 0x15, 0x60, 0x00, 0x60, 0x00, 0x12, 0x00];
+        let mut runner = Runner::new(TestFrontend::new(3), program(&rom));
+        runner.run().unwrap();
+        let sprite = Rect {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 5,
+        };
+        assert_eq!(
+            runner.frontend.dirty,
+            [Some(all), Some(sprite), Some(sprite)]
+        );
+    }
+
+    #[test]
+    fn breakpoints_cut_frames_short() {
+        let mut frontend = TestFrontend::new(2);
+        frontend.breakpoint = Some(0x202);
+        let mut runner = Runner::new(frontend, program(&[0x60, 2, 0xF0, 0x18, 0x12, 0x04]));
+        runner.run().unwrap();
+        assert_eq!(runner.frontend.breakpoints_hit, 2);
+        assert_eq!(runner.frontend.executed, [1, 0]);
+        assert_eq!(runner.program.chip8.regs.pc, 0x202);
+    }
+}
@@ -0,0 +1,301 @@
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
+
+use crate::chip8::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::debugger::{DEBUG_VIEW_HEIGHT, DEBUG_VIEW_WIDTH};
+use crate::filter::FilteredPx;
+use crate::overlay::{Ink, Overlay, OVERLAY_HEIGHT, OVERLAY_WIDTH};
+use crate::palette::{self, Palette};
+
+const BYTES_PER_PX: usize = 3;
+const OVERLAY_BYTES_PER_PX: usize = 4;
+
+/// How the display is scaled up to the window size, the space that isn't
+/// covered by the display is filled with the background color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScaleMode {
+    /// largest whole multiple of the display size, keeps pixels square and sharp
+    Integer,
+    /// as large as fits, keeping the aspect ratio
+    Fit,
+}
+
+impl ScaleMode {
+    pub fn from_name(name: &str) -> Result<ScaleMode, String> {
+        match name {
+            "integer" => Ok(ScaleMode::Integer),
+            "fit" => Ok(ScaleMode::Fit),
+            _ => Err(format!(
+                "Unknown scale mode \"{}\", available modes are: integer, fit",
+                name
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScaleMode::Integer => "integer",
+            ScaleMode::Fit => "fit",
+        }
+    }
+}
+
+/// The SDL window the display is drawn into. The display is drawn into a
+/// streaming texture which is uploaded once per frame and scaled up to the
+/// window by the GPU. Text can be drawn over it, from a second texture with
+/// transparency. With the debugger open, the window is split: the display
+/// takes the top left quarter and the debugger's view the rest.
+pub struct Screen<'a> {
+    canvas: Canvas<Window>,
+    texture: Texture<'a>,
+    overlay_texture: Texture<'a>,
+    debug_texture: Texture<'a>,
+    scale_mode: ScaleMode,
+}
+
+impl<'a> Screen<'a> {
+    pub fn new(
+        canvas: Canvas<Window>,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        scale_mode: ScaleMode,
+    ) -> Result<Screen<'a>, String> {
+        let texture = texture_creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                SCREEN_WIDTH as u32,
+                SCREEN_HEIGHT as u32,
+            )
+            .map_err(|err| err.to_string())?;
+        let mut overlay_texture = texture_creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGBA32,
+                OVERLAY_WIDTH as u32,
+                OVERLAY_HEIGHT as u32,
+            )
+            .map_err(|err| err.to_string())?;
+        overlay_texture.set_blend_mode(BlendMode::Blend);
+        let debug_texture = texture_creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGBA32,
+                DEBUG_VIEW_WIDTH as u32,
+                DEBUG_VIEW_HEIGHT as u32,
+            )
+            .map_err(|err| err.to_string())?;
+        Ok(Screen {
+            canvas,
+            texture,
+            overlay_texture,
+            debug_texture,
+            scale_mode,
+        })
+    }
+
+    pub fn toggle_scale_mode(&mut self) -> ScaleMode {
+        self.scale_mode = match self.scale_mode {
+            ScaleMode::Integer => ScaleMode::Fit,
+            ScaleMode::Fit => ScaleMode::Integer,
+        };
+        self.scale_mode
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.canvas.window().fullscreen_state() != FullscreenType::Off
+    }
+
+    pub fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), String> {
+        let state = if fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Off
+        };
+        self.canvas.window_mut().set_fullscreen(state)
+    }
+
+    pub fn set_title(&mut self, title: &str) -> Result<(), String> {
+        self.canvas
+            .window_mut()
+            .set_title(title)
+            .map_err(|err| err.to_string())
+    }
+
+    /// Makes the window at least as big as the given size, unless it's
+    /// fullscreen
+    pub fn ensure_size(&mut self, width: u32, height: u32) -> Result<(), String> {
+        if self.is_fullscreen() {
+            return Ok(());
+        }
+        let window = self.canvas.window_mut();
+        let (current_width, current_height) = window.size();
+        window
+            .set_size(current_width.max(width), current_height.max(height))
+            .map_err(|err| err.to_string())
+    }
+
+    /// Position in the debugger's view of a point in the window, given in
+    /// window coordinates like mouse events
+    pub fn debug_view_position(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        let view_size = (DEBUG_VIEW_WIDTH as u32, DEBUG_VIEW_HEIGHT as u32);
+        let rect = letterbox(self.canvas.window().size(), view_size, self.scale_mode);
+        if !rect.contains_point((x, y)) {
+            return None;
+        }
+        Some((
+            ((x - rect.x()) as u32 * view_size.0 / rect.width()) as usize,
+            ((y - rect.y()) as u32 * view_size.1 / rect.height()) as usize,
+        ))
+    }
+
+    /// Draws a filtered frame with the given palette, and the overlay on top
+    /// of it if there is one. With a debugger view, the display goes into a
+    /// corner of it.
+    pub fn present(
+        &mut self,
+        pixels: &[FilteredPx],
+        palette: &Palette,
+        overlay: Option<&Overlay>,
+        debug_view: Option<&Overlay>,
+    ) -> Result<(), String> {
+        let background = palette.background();
+        self.texture
+            .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                for (i, px) in pixels.iter().enumerate() {
+                    let x = i % SCREEN_WIDTH as usize;
+                    let y = i / SCREEN_WIDTH as usize;
+                    let color = if px.intensity > 0.0 {
+                        palette::blend(background, palette.color(px.value), px.intensity)
+                    } else {
+                        background
+                    };
+                    let offset = y * pitch + x * BYTES_PER_PX;
+                    buffer[offset] = color.r;
+                    buffer[offset + 1] = color.g;
+                    buffer[offset + 2] = color.b;
+                }
+            })?;
+
+        let output_size = self.canvas.output_size()?;
+        self.canvas
+            .set_draw_color((background.r, background.g, background.b));
+        self.canvas.clear();
+        let display_rect = match debug_view {
+            Some(debug_view) => {
+                let view_size = (DEBUG_VIEW_WIDTH as u32, DEBUG_VIEW_HEIGHT as u32);
+                let view_rect = letterbox(output_size, view_size, self.scale_mode);
+                upload_overlay(&mut self.debug_texture, debug_view, palette)?;
+                self.canvas.copy(&self.debug_texture, None, view_rect)?;
+                Rect::new(
+                    view_rect.x(),
+                    view_rect.y(),
+                    view_rect.width() / 2,
+                    view_rect.height() / 2,
+                )
+            }
+            None => {
+                let display_size = (SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+                letterbox(output_size, display_size, self.scale_mode)
+            }
+        };
+        self.canvas.copy(&self.texture, None, display_rect)?;
+        if let Some(overlay) = overlay {
+            upload_overlay(&mut self.overlay_texture, overlay, palette)?;
+            self.canvas
+                .copy(&self.overlay_texture, None, display_rect)?;
+        }
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+/// Copies an overlay into a texture of the same size, in the colors of the
+/// palette
+fn upload_overlay(
+    texture: &mut Texture,
+    overlay: &Overlay,
+    palette: &Palette,
+) -> Result<(), String> {
+    let background = palette.background();
+    let foreground = palette.color(1);
+    texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+        for (i, ink) in overlay.pixels().iter().enumerate() {
+            let x = i % overlay.width();
+            let y = i / overlay.width();
+            let (color, alpha) = match ink {
+                Ink::Clear => (background, 0),
+                Ink::Background => (background, 255),
+                Ink::Foreground => (foreground, 255),
+            };
+            let offset = y * pitch + x * OVERLAY_BYTES_PER_PX;
+            buffer[offset] = color.r;
+            buffer[offset + 1] = color.g;
+            buffer[offset + 2] = color.b;
+            buffer[offset + 3] = alpha;
+        }
+    })
+}
+
+/// Calculates where the display goes inside of the window: scaled up
+/// according to the scale mode and centered
+pub fn letterbox(output: (u32, u32), display: (u32, u32), mode: ScaleMode) -> Rect {
+    let scale = f32::min(
+        output.0 as f32 / display.0 as f32,
+        output.1 as f32 / display.1 as f32,
+    );
+    let scale = match mode {
+        ScaleMode::Integer => scale.floor().max(1.0),
+        ScaleMode::Fit => scale,
+    };
+    let width = (display.0 as f32 * scale) as u32;
+    let height = (display.1 as f32 * scale) as u32;
+    Rect::new(
+        (output.0 as i32 - width as i32) / 2,
+        (output.1 as i32 - height as i32) / 2,
+        width,
+        height,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn letterbox_works() {
+        // exact multiple fills the window in both modes
+        assert_eq!(
+            letterbox((512, 256), (64, 32), ScaleMode::Integer),
+            Rect::new(0, 0, 512, 256)
+        );
+        assert_eq!(
+            letterbox((512, 256), (64, 32), ScaleMode::Fit),
+            Rect::new(0, 0, 512, 256)
+        );
+
+        // bars on the top and bottom
+        assert_eq!(
+            letterbox((640, 480), (64, 32), ScaleMode::Fit),
+            Rect::new(0, 80, 640, 320)
+        );
+        // integer scaling leaves bars on all sides
+        assert_eq!(
+            letterbox((600, 480), (64, 32), ScaleMode::Integer),
+            Rect::new(12, 96, 576, 288)
+        );
+        // never scaled below 1x
+        assert_eq!(
+            letterbox((32, 16), (64, 32), ScaleMode::Integer),
+            Rect::new(-16, -8, 64, 32)
+        );
+    }
+
+    #[test]
+    fn scale_mode_names_work() {
+        assert_eq!(ScaleMode::from_name("fit"), Ok(ScaleMode::Fit));
+        assert_eq!(
+            ScaleMode::from_name(ScaleMode::Integer.name()),
+            Ok(ScaleMode::Integer)
+        );
+        assert!(ScaleMode::from_name("stretch").is_err());
+    }
+}
@@ -0,0 +1,470 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::chip8::{Quirks, DEFAULT_STACK_DEPTH, MEMORY_SIZE};
+use crate::cli::Options;
+use crate::config::{Config, RomConfig};
+use crate::keymap::DEFAULT_KEYMAP;
+use crate::palette::DEFAULT_PALETTE;
+use crate::platform::Platform;
+use crate::romdb::RomEntry;
+use crate::runner::Engine;
+
+// roughly 11 instructions per frame, going by folklore
+pub const DEFAULT_IPS: u32 = 660;
+// programs start right after the memory used by the interpreter on the COSMAC VIP
+pub const DEFAULT_START_ADDRESS: u16 = 0x200;
+// name of the gamepad mapping preset used unless another one is picked
+pub const DEFAULT_PAD_MAPPING: &str = "default";
+// name of the palette made of the colors set for a ROM
+pub const ROM_PALETTE: &str = "rom";
+
+/// The settings a ROM is run with. Each setting is taken from the first of
+/// these that has it:
+/// 1. the command line
+/// 2. the ROM's section of the config file
+/// 3. the ROM database
+/// 4. the global config
+/// 5. the platform, for quirks, speed and stack depth
+/// 6. the defaults
+///
+/// When none of them sets the platform, it's detected from the size of the
+/// ROM, see `Platform::detect`.
+///
+/// Quirks are merged one by one in the reverse order, so e.g. a quirk set in
+/// the ROM's section only overrides that quirk.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Settings {
+    pub palette: String,
+    /// colors of the ROM's own palette, selected as `ROM_PALETTE`
+    #[serde(skip)]
+    pub colors: Option<Vec<String>>,
+    pub filter: String,
+    pub window_scale: u32,
+    pub scale_mode: String,
+    pub fullscreen: bool,
+    pub keymap: String,
+    pub pad_mapping: String,
+    pub stick_threshold: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+    pub ips: u32,
+    /// calls that can be nested before the stack overflows
+    pub stack_depth: usize,
+    pub engine: Engine,
+    /// keys the ROM database lists for the ROM, bound on top of the keymap
+    /// unless the keymap was picked for the ROM. The config file can't set
+    /// these, so they're left out of the dump.
+    #[serde(skip)]
+    pub key_roles: BTreeMap<String, u8>,
+    /// same as `key_roles`, for the pad mapping
+    #[serde(skip)]
+    pub pad_roles: BTreeMap<String, u8>,
+    pub quirks: Quirks,
+    pub start_address: u16,
+}
+
+impl Settings {
+    pub fn resolve(
+        config: &Config,
+        rom_config: Option<&RomConfig>,
+        db_rom: Option<&RomEntry>,
+        rom_size: usize,
+        options: &Options,
+    ) -> Result<Settings, String> {
+        let default_rom_config = RomConfig::default();
+        let rom_config = rom_config.unwrap_or(&default_rom_config);
+
+        let colors = match options.palette {
+            Some(_) => None,
+            None => rom_config.colors.clone(),
+        };
+        let palette = match colors {
+            Some(_) => ROM_PALETTE,
+            None => options
+                .palette
+                .as_ref()
+                .or(rom_config.palette.as_ref())
+                .or(config.palette.as_ref())
+                .map_or(DEFAULT_PALETTE, |name| name.as_str()),
+        };
+        let filter = options
+            .filter
+            .as_ref()
+            .or(rom_config.filter.as_ref())
+            .or(config.filter.as_ref())
+            .map_or("none", |name| name.as_str());
+
+        let rom_keymap = options.keymap.as_ref().or(rom_config.keymap.as_ref());
+        let keymap = rom_keymap
+            .or(config.keymap.as_ref())
+            .map_or(DEFAULT_KEYMAP, |name| name.as_str());
+        let pad_mapping = rom_config
+            .pad_mapping
+            .as_ref()
+            .or(config.pad_mapping.as_ref())
+            .map_or(DEFAULT_PAD_MAPPING, |name| name.as_str());
+        let db_keys = db_rom.map(|rom| rom.keys.clone()).unwrap_or_default();
+        let key_roles = if rom_keymap.is_none() {
+            db_keys.clone()
+        } else {
+            BTreeMap::new()
+        };
+        let pad_roles = if rom_config.pad_mapping.is_none() {
+            db_keys
+        } else {
+            BTreeMap::new()
+        };
+
+        let start_address = options
+            .start_address
+            .or(rom_config.start_address)
+            .or(config.start_address)
+            .unwrap_or(DEFAULT_START_ADDRESS);
+
+        let platform_id = options.platform.as_ref().or(rom_config.platform.as_ref());
+        let platform = match (platform_id, db_rom.and_then(|rom| rom.platform())) {
+            (Some(id), _) => Some(Platform::from_id(id)?),
+            (None, Some(platform)) => Some(platform),
+            (None, None) => match &config.platform {
+                Some(id) => Some(Platform::from_id(id)?),
+                None => Platform::detect(rom_size, start_address),
+            },
+        };
+
+        let ips = options
+            .ips
+            .or(rom_config.ips)
+            .or(db_rom.and_then(|rom| rom.ips()))
+            .or(config.ips)
+            .or(platform.map(|platform| platform.default_ips()))
+            .unwrap_or(DEFAULT_IPS);
+
+        let stack_depth = options
+            .stack_depth
+            .or(rom_config.stack_depth)
+            .or(config.stack_depth)
+            .or(platform.map(|platform| platform.stack_depth()))
+            .unwrap_or(DEFAULT_STACK_DEPTH);
+        if stack_depth == 0 {
+            return Err("The stack depth can't be 0, calls need room on the stack".to_string());
+        }
+
+        let engine = match options.engine.as_ref().or(config.engine.as_ref()) {
+            Some(name) => Engine::from_name(name)?,
+            None => Engine::Interpreter,
+        };
+
+        let mut quirks = platform.map_or(Quirks::default(), |platform| platform.quirks());
+        config.quirks.apply(&mut quirks);
+        if let (Some(rom), Some(platform)) = (db_rom, platform) {
+            rom.apply_quirks(platform, &mut quirks);
+        }
+        rom_config.quirks.apply(&mut quirks);
+        options.quirks.apply(&mut quirks);
+
+        Ok(Settings {
+            palette: palette.to_string(),
+            colors,
+            filter: filter.to_string(),
+            window_scale: options.scale.unwrap_or(config.window_scale),
+            scale_mode: config
+                .scale_mode
+                .clone()
+                .unwrap_or_else(|| "integer".to_string()),
+            fullscreen: config.fullscreen,
+            keymap: keymap.to_string(),
+            pad_mapping: pad_mapping.to_string(),
+            stick_threshold: config.stick_threshold,
+            platform,
+            ips,
+            stack_depth,
+            engine,
+            key_roles,
+            pad_roles,
+            quirks,
+            start_address,
+        })
+    }
+
+    /// Bytes of memory the machine gets
+    pub fn memory_size(&self) -> usize {
+        self.platform
+            .map_or(MEMORY_SIZE, |platform| platform.memory_size())
+    }
+
+    /// Instructions executed each frame at 60 frames per second
+    pub fn instructions_per_frame(&self) -> u32 {
+        ((self.ips + 30) / 60).max(1)
+    }
+
+    /// The settings as TOML, in the format of the config file. The ROM's own
+    /// colors go into `[palettes]`, under the name the palette is picked by.
+    pub fn to_toml(&self) -> Result<String, String> {
+        let mut toml = toml::to_string(self).map_err(|err| err.to_string())?;
+        if let Some(colors) = &self.colors {
+            let palettes = BTreeMap::from([(ROM_PALETTE, colors)]);
+            let palettes = BTreeMap::from([("palettes", palettes)]);
+            toml.push('\n');
+            toml.push_str(&toml::to_string(&palettes).map_err(|err| err.to_string())?);
+        }
+        Ok(toml)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QuirkOverrides;
+    use crate::romdb::RomDb;
+
+    fn no_options() -> Options {
+        Options::default()
+    }
+
+    #[test]
+    fn defaults_work() {
+        let settings = Settings::resolve(&Config::default(), None, None, 0, &no_options()).unwrap();
+        assert_eq!(settings.palette, DEFAULT_PALETTE);
+        assert_eq!(settings.filter, "none");
+        assert_eq!(settings.keymap, DEFAULT_KEYMAP);
+        assert_eq!(settings.platform, None);
+        assert_eq!(settings.ips, DEFAULT_IPS);
+        assert_eq!(settings.instructions_per_frame(), 11);
+        assert_eq!(settings.quirks, Quirks::default());
+        assert_eq!(settings.start_address, DEFAULT_START_ADDRESS);
+        assert_eq!(settings.memory_size(), MEMORY_SIZE);
+        assert_eq!(settings.engine, Engine::Interpreter);
+        assert_eq!(settings.stack_depth, DEFAULT_STACK_DEPTH);
+    }
+
+    #[test]
+    fn platform_is_detected_from_rom_size() {
+        let settings =
+            Settings::resolve(&Config::default(), None, None, 4000, &no_options()).unwrap();
+        assert_eq!(settings.platform, Some(Platform::XoChip));
+        assert_eq!(settings.ips, Platform::XoChip.default_ips());
+        assert_eq!(settings.memory_size(), 0x10000);
+
+        // a platform that's set isn't replaced
+        let config = Config::parse("platform = \"superchip\"").unwrap();
+        let settings = Settings::resolve(&config, None, None, 4000, &no_options()).unwrap();
+        assert_eq!(settings.platform, Some(Platform::SuperChip));
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let config = Config::parse(
+            r#"
+            ips = 1000
+            stack_depth = 24
+            window_scale = 4
+            start_address = 0x300
+            engine = "jit"
+
+            [roms."game.ch8"]
+            platform = "superchip"
+            quirks = { logic = true }
+            "#,
+        )
+        .unwrap();
+        let rom_config = config.rom("game.ch8", "");
+        let options = Options {
+            platform: Some("xochip".to_string()),
+            ips: Some(600),
+            stack_depth: Some(8),
+            quirks: QuirkOverrides {
+                logic: Some(false),
+                ..QuirkOverrides::default()
+            },
+            start_address: Some(0x600),
+            engine: Some("interpreter".to_string()),
+            scale: Some(2),
+            ..no_options()
+        };
+        let settings = Settings::resolve(&config, rom_config, None, 0, &options).unwrap();
+        assert_eq!(settings.platform, Some(Platform::XoChip));
+        assert_eq!(settings.ips, 600);
+        assert_eq!(settings.stack_depth, 8);
+        assert!(!settings.quirks.logic);
+        assert_eq!(settings.start_address, 0x600);
+        assert_eq!(settings.window_scale, 2);
+        assert_eq!(settings.engine, Engine::Interpreter);
+
+        let settings = Settings::resolve(&config, rom_config, None, 0, &no_options()).unwrap();
+        assert_eq!(settings.start_address, 0x300);
+        assert_eq!(settings.stack_depth, 24);
+        assert_eq!(settings.window_scale, 4);
+        assert_eq!(settings.engine, Engine::Jit);
+    }
+
+    #[test]
+    fn layers_take_precedence_in_order() {
+        let config = Config::parse(
+            r#"
+            palette = "amber"
+            filter = "blend"
+            keymap = "keypad"
+            platform = "originalChip8"
+
+            [quirks]
+            logic = false
+            wrap = true
+
+            [roms."game.ch8"]
+            palette = "lcd"
+            platform = "superchip"
+            quirks = { jump = false }
+            "#,
+        )
+        .unwrap();
+        let rom_config = config.rom("game.ch8", "");
+        let options = Options {
+            filter: Some("phosphor".to_string()),
+            ..no_options()
+        };
+
+        let settings = Settings::resolve(&config, rom_config, None, 0, &options).unwrap();
+        assert_eq!(settings.palette, "lcd");
+        assert_eq!(settings.filter, "phosphor");
+        assert_eq!(settings.keymap, "keypad");
+        assert_eq!(settings.platform, Some(Platform::SuperChip));
+        assert_eq!(settings.ips, Platform::SuperChip.default_ips());
+        assert_eq!(settings.stack_depth, 16);
+        assert_eq!(
+            settings.quirks,
+            Quirks {
+                wrap: true,
+                jump: false,
+                ..Platform::SuperChip.quirks()
+            }
+        );
+
+        // without the ROM's section the global config applies
+        let settings = Settings::resolve(&config, None, None, 0, &options).unwrap();
+        assert_eq!(settings.palette, "amber");
+        assert_eq!(settings.platform, Some(Platform::OriginalChip8));
+        assert_eq!(settings.stack_depth, 12);
+        assert!(!settings.quirks.logic);
+        assert!(settings.quirks.vblank);
+
+        // colors set for the ROM make its own palette, unless one is picked
+        // on the command line
+        let rom_config = RomConfig {
+            colors: Some(vec!["#000000".to_string(), "#FFFFFF".to_string()]),
+            ..RomConfig::default()
+        };
+        let settings = Settings::resolve(&config, Some(&rom_config), None, 0, &options).unwrap();
+        assert_eq!(settings.palette, ROM_PALETTE);
+        assert_eq!(settings.colors, rom_config.colors);
+        let options = Options {
+            palette: Some("amber".to_string()),
+            ..no_options()
+        };
+        let settings = Settings::resolve(&config, Some(&rom_config), None, 0, &options).unwrap();
+        assert_eq!(settings.palette, "amber");
+        assert_eq!(settings.colors, None);
+
+        let config = Config::parse("platform = \"megachip8\"").unwrap();
+        assert!(Settings::resolve(&config, None, None, 0, &options).is_err());
+    }
+
+    #[test]
+    fn rom_database_works() {
+        let db = RomDb::parse(
+            r#"[{
+                "title": "Game",
+                "roms": {
+                    "a9993e364706816aba3e25717850c26c9cd0d89d": {
+                        "platforms": ["xochip"],
+                        "quirkyPlatforms": { "xochip": { "logic": true } },
+                        "tickrate": 20,
+                        "keys": { "up": 5 }
+                    }
+                }
+            }]"#,
+        )
+        .unwrap();
+        let found = db.lookup(b"abc").unwrap();
+
+        let config = Config::parse(
+            r#"
+            platform = "originalChip8"
+            ips = 500
+            "#,
+        )
+        .unwrap();
+        let settings = Settings::resolve(&config, None, Some(found.rom), 0, &no_options()).unwrap();
+        assert_eq!(settings.platform, Some(Platform::XoChip));
+        assert_eq!(settings.ips, 1200);
+        assert!(settings.quirks.logic);
+        assert_eq!(settings.key_roles["up"], 5);
+        assert_eq!(settings.pad_roles["up"], 5);
+
+        // a keymap picked on the command line isn't changed
+        let options = Options {
+            keymap: Some("arrows".to_string()),
+            ..no_options()
+        };
+        let settings = Settings::resolve(&config, None, Some(found.rom), 0, &options).unwrap();
+        assert!(settings.key_roles.is_empty());
+        assert_eq!(settings.pad_roles["up"], 5);
+
+        // the ROM's section of the config file wins over the database
+        let config = Config::parse(
+            r#"
+            [roms."a9993e364706816aba3e25717850c26c9cd0d89d"]
+            ips = 600
+            "#,
+        )
+        .unwrap();
+        let rom_config = config.rom("abc.ch8", "a9993e364706816aba3e25717850c26c9cd0d89d");
+        let settings =
+            Settings::resolve(&config, rom_config, Some(found.rom), 0, &no_options()).unwrap();
+        assert_eq!(settings.ips, 600);
+        assert_eq!(settings.instructions_per_frame(), 10);
+    }
+
+    #[test]
+    fn to_toml_works() {
+        let settings = Settings::resolve(&Config::default(), None, None, 0, &no_options()).unwrap();
+        let toml = settings.to_toml().unwrap();
+        assert!(toml.contains("palette = \"default\""));
+        assert!(toml.contains("ips = 660"));
+        assert!(!toml.contains("platform"));
+        assert!(toml.contains("[quirks]\nshift = true"));
+        // the dump can be used as a config file
+        let config = Config::parse(&toml).unwrap();
+        assert_eq!(config.ips, Some(660));
+        assert_eq!(config.engine, Some("interpreter".to_string()));
+
+        // also with the ROM's own colors and keys from the database
+        let colors = vec!["#000000".to_string(), "#FFFFFF".to_string()];
+        let rom_config = RomConfig {
+            colors: Some(colors.clone()),
+            ..RomConfig::default()
+        };
+        let db = RomDb::parse(
+            r#"[{ "title": "Game", "roms": {
+                "a9993e364706816aba3e25717850c26c9cd0d89d": { "keys": { "up": 5 } }
+            } }]"#,
+        )
+        .unwrap();
+        let db_rom = db.lookup(b"abc").unwrap().rom;
+        let settings = Settings::resolve(
+            &Config::default(),
+            Some(&rom_config),
+            Some(db_rom),
+            0,
+            &no_options(),
+        )
+        .unwrap();
+        assert_eq!(settings.key_roles["up"], 5);
+        let config = Config::parse(&settings.to_toml().unwrap()).unwrap();
+        assert_eq!(config.palette, Some(ROM_PALETTE.to_string()));
+        assert_eq!(config.palettes[ROM_PALETTE], colors);
+        let reloaded = Settings::resolve(&config, None, None, 0, &no_options()).unwrap();
+        assert_eq!(reloaded.palette, ROM_PALETTE);
+    }
+}
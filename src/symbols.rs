@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Names of addresses in a program and the parts of it that are data, from
+/// the labels of its source or from a symbol file. Shown next to addresses,
+/// e.g. in listings and the debugger's call stack.
+///
+/// Symbol files have a label or a data region a line, addresses are hex
+/// with or without `0x`, comments go from `;` to the end of the line:
+/// ```text
+/// ; address and name
+/// 0x2A4 draw_player
+/// ; first address and length in bytes
+/// data 0x2F0 8
+/// ```
+#[derive(Default, Debug, PartialEq)]
+pub struct Symbols {
+    names: BTreeMap<u16, String>,
+    data: Vec<Range<usize>>,
+}
+
+impl Symbols {
+    /// Symbols for the labels, by name. Where labels share an address the
+    /// first by name is used.
+    pub fn from_labels<'a>(labels: impl IntoIterator<Item = (&'a String, &'a u16)>) -> Symbols {
+        let mut labels: Vec<(&String, &u16)> = labels.into_iter().collect();
+        labels.sort();
+        let mut symbols = Symbols::default();
+        for (name, address) in labels {
+            symbols.insert(*address, name);
+        }
+        symbols
+    }
+
+    /// Parses a symbol file, see above
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("");
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match tokens[..] {
+                [] => Ok(()),
+                ["data", start, length] => parse_address(start).and_then(|start| {
+                    let length: usize = (length.parse().ok())
+                        .filter(|length| *length > 0)
+                        .ok_or(format!("invalid length {}", length))?;
+                    symbols.add_data(start as usize..start as usize + length);
+                    Ok(())
+                }),
+                [address, name] => parse_address(address).map(|address| {
+                    symbols.insert(address, name);
+                }),
+                _ => Err(
+                    "expected an address and a name, or data, an address and a length".to_string(),
+                ),
+            };
+            parsed.map_err(|err| format!("line {}: {}", line_no + 1, err))?;
+        }
+        Ok(symbols)
+    }
+
+    /// The symbols in the format `parse` reads
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (address, name) in &self.names {
+            text.push_str(&format!("0x{:03X} {}\n", address, name));
+        }
+        for region in &self.data {
+            text.push_str(&format!("data 0x{:03X} {}\n", region.start, region.len()));
+        }
+        text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.data.is_empty()
+    }
+
+    /// Names the address, unless it has a name already
+    pub fn insert(&mut self, address: u16, name: &str) {
+        self.names
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+    }
+
+    /// Marks bytes as data, regions that touch are joined
+    pub fn add_data(&mut self, region: Range<usize>) {
+        match self.data.last_mut() {
+            Some(last) if last.end == region.start => last.end = region.end,
+            _ => self.data.push(region),
+        }
+    }
+
+    /// The symbols of both, `self`'s name taken where both name an address
+    pub fn merge(mut self, other: Symbols) -> Symbols {
+        for (address, name) in other.names {
+            self.insert(address, &name);
+        }
+        for region in other.data {
+            self.add_data(region);
+        }
+        self
+    }
+
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(|name| name.as_str())
+    }
+
+    pub fn is_data(&self, address: usize) -> bool {
+        self.data.iter().any(|region| region.contains(&address))
+    }
+
+    /// The address in hex, with the label at or before it in front when
+    /// there's one: "draw_player (2A4)", "draw_player+6 (2AA)"
+    pub fn describe(&self, address: u16) -> String {
+        match self.names.range(..=address).next_back() {
+            Some((start, name)) if *start == address => format!("{} ({:03X})", name, address),
+            Some((start, name)) => format!("{}+{:X} ({:03X})", name, address - start, address),
+            None => format!("{:03X}", address),
+        }
+    }
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn from_labels_works() {
+        let labels = HashMap::from([
+            ("main".to_string(), 0x202),
+            ("loop".to_string(), 0x202),
+            ("draw_player".to_string(), 0x2A4),
+        ]);
+        let symbols = Symbols::from_labels(&labels);
+        assert_eq!(symbols.name(0x202), Some("loop"));
+        assert_eq!(symbols.name(0x200), None);
+        assert_eq!(symbols.describe(0x2A4), "draw_player (2A4)");
+        assert_eq!(symbols.describe(0x2AA), "draw_player+6 (2AA)");
+        assert_eq!(symbols.describe(0x200), "200");
+    }
+
+    #[test]
+    fn parse_works() {
+        let symbols = Symbols::parse(
+            "; pong
+            0x2A4 draw_player
+            2f0 ball   ; the sprite
+            data 0x2F0 4
+
+            data 2F4 2",
+        )
+        .unwrap();
+        assert_eq!(symbols.name(0x2A4), Some("draw_player"));
+        assert_eq!(symbols.name(0x2F0), Some("ball"));
+        assert!(!symbols.is_data(0x2EF));
+        assert!(symbols.is_data(0x2F0));
+        assert!(symbols.is_data(0x2F5));
+        assert!(!symbols.is_data(0x2F6));
+        assert_eq!(
+            symbols.to_text(),
+            "0x2A4 draw_player\n0x2F0 ball\ndata 0x2F0 6\n"
+        );
+        assert_eq!(Symbols::parse(&symbols.to_text()), Ok(symbols));
+
+        assert_eq!(
+            Symbols::parse("0x200 main\nmain 0x200"),
+            Err("line 2: invalid address main".to_string())
+        );
+        assert!(Symbols::parse("data 0x200 0").is_err());
+        assert!(Symbols::parse("0x200").is_err());
+        assert!(Symbols::parse("0x10000 past_the_end").is_err());
+    }
+
+    #[test]
+    fn merge_works() {
+        let mut symbols = Symbols::default();
+        symbols.insert(0x200, "main");
+        let mut other = Symbols::default();
+        other.insert(0x200, "start");
+        other.insert(0x210, "draw");
+        other.add_data(0x300..0x308);
+        let merged = symbols.merge(other);
+        assert_eq!(merged.name(0x200), Some("main"));
+        assert_eq!(merged.name(0x210), Some("draw"));
+        assert!(merged.is_data(0x307));
+    }
+}
@@ -0,0 +1,392 @@
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use crate::chip8::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::display::Rect;
+use crate::keymap::{Key, Keymap};
+use crate::palette::{Palette, Palettes};
+use crate::runner::{Frontend, Poll, Program};
+
+// Terminals only tell when a key is typed, a held key is typed again by the
+// key repeat of the terminal. So a key counts as held for a while after it's
+// typed, long enough for the repeat to kick in, and a bit less once it's
+// repeating.
+const FIRST_HOLD: Duration = Duration::from_millis(300);
+const REPEAT_HOLD: Duration = Duration::from_millis(100);
+
+// the upper half of a cell is drawn in the foreground color, the lower half
+// in the background color, so a line of text shows two rows of pixels
+const UPPER_HALF_BLOCK: char = '▀';
+
+/// Plays in the terminal, for when there's no display to open a window on
+pub struct TuiFrontend {
+    terminal: Terminal,
+    palettes: Palettes,
+    keymap: Keymap,
+    held_keys: HeldKeys,
+    /// shown under the display
+    status: String,
+    beeping: bool,
+    ring_bell: bool,
+}
+
+impl TuiFrontend {
+    pub fn new(palettes: Palettes, keymap: Keymap, status: String) -> Result<TuiFrontend, String> {
+        Ok(TuiFrontend {
+            terminal: Terminal::new()?,
+            palettes,
+            keymap,
+            held_keys: HeldKeys::default(),
+            status,
+            beeping: false,
+            ring_bell: false,
+        })
+    }
+}
+
+impl Frontend for TuiFrontend {
+    fn poll_input(&mut self, program: &mut Program) -> Poll {
+        let now = Instant::now();
+        for input in self.terminal.read_input() {
+            match input {
+                Input::Quit => return Poll::Quit,
+                Input::Key(key) => {
+                    if let Some(button) = self.keymap.button(key) {
+                        self.held_keys.press(button, now);
+                    }
+                }
+            }
+        }
+        program.chip8.key_down = self.held_keys.key_down(now);
+        Poll::Run
+    }
+
+    fn present(&mut self, program: &Program, _executed: u32, dirty: Option<Rect>) {
+        if dirty.is_none() && !self.ring_bell {
+            return;
+        }
+        let palette = self.palettes.current();
+        let pixels = program.chip8.display.to_bytes();
+        self.terminal
+            .draw(&pixels, palette, &self.status, self.ring_bell);
+    }
+
+    fn output_audio(&mut self, samples: &[f32]) {
+        // the bell can't be held, so it rings when the sound starts
+        let sound = samples.iter().any(|sample| *sample != 0.0);
+        self.ring_bell = sound && !self.beeping;
+        self.beeping = sound;
+    }
+}
+
+/// Something typed in the terminal
+#[derive(Debug, PartialEq)]
+enum Input {
+    /// a key, the one typing the character on a US keyboard so keymaps work
+    /// like in the window
+    Key(Key),
+    /// Esc or Ctrl+C
+    Quit,
+}
+
+/// The terminal in raw mode, showing the alternate screen. Everything is put
+/// back the way it was when it's dropped.
+struct Terminal {
+    original: libc::termios,
+    /// the last frame written, to skip writing frames that didn't change
+    last_frame: String,
+    /// the start of an escape sequence the last read ended in the middle of
+    pending: Vec<u8>,
+}
+
+impl Terminal {
+    fn new() -> Result<Terminal, String> {
+        let mut original = std::mem::MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: tcgetattr fills in the struct when it succeeds
+        let original = unsafe {
+            if libc::tcgetattr(libc::STDIN_FILENO, original.as_mut_ptr()) != 0 {
+                return Err("The terminal frontend needs stdin to be a terminal".to_string());
+            }
+            original.assume_init()
+        };
+        let mut raw = original;
+        // SAFETY: raw is a valid termios, from tcgetattr
+        unsafe { libc::cfmakeraw(&mut raw) };
+        // reads return right away, with whatever was typed so far
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: raw is a valid termios
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(format!(
+                "Failed to put the terminal in raw mode, error is \"{}\"",
+                std::io::Error::last_os_error()
+            ));
+        }
+        let terminal = Terminal {
+            original,
+            last_frame: String::new(),
+            pending: Vec::new(),
+        };
+        // alternate screen, hidden cursor, cleared
+        terminal.write("\x1b[?1049h\x1b[?25l\x1b[2J");
+        Ok(terminal)
+    }
+
+    /// Everything typed since the last call
+    fn read_input(&mut self) -> Vec<Input> {
+        let mut bytes = std::mem::take(&mut self.pending);
+        let pending = bytes.len();
+        let mut buffer = [0; 64];
+        while let Ok(count @ 1..) = std::io::stdin().read(&mut buffer) {
+            bytes.extend_from_slice(&buffer[..count]);
+        }
+        // when nothing came after it, an escape is the Esc key and not the
+        // start of a sequence
+        let (inputs, parsed) = parse_input(&bytes, bytes.len() == pending);
+        self.pending = bytes.split_off(parsed);
+        inputs
+    }
+
+    /// Draws the display with a line of text under it, `beep` rings the bell
+    fn draw(&mut self, pixels: &[u8], palette: &Palette, status: &str, beep: bool) {
+        let frame = format!("{}{}\x1b[K", render(pixels, palette), status);
+        if beep {
+            self.write("\x07");
+        }
+        if frame != self.last_frame {
+            self.write(&format!("\x1b[H{}", frame));
+            self.last_frame = frame;
+        }
+    }
+
+    fn write(&self, text: &str) {
+        // there's nowhere to report a terminal that can't be written to
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(text.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        self.write("\x1b[0m\x1b[?25h\x1b[?1049l");
+        // SAFETY: original is the termios the terminal had before
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// Works out which keys are held from the keys typed
+#[derive(Default)]
+struct HeldKeys {
+    /// when each key is let go if it's not typed again
+    until: [Option<Instant>; 16],
+}
+
+impl HeldKeys {
+    fn press(&mut self, key: usize, now: Instant) {
+        let hold = match self.until[key] {
+            Some(until) if until > now => REPEAT_HOLD,
+            _ => FIRST_HOLD,
+        };
+        self.until[key] = Some(now + hold);
+    }
+
+    fn key_down(&mut self, now: Instant) -> [bool; 16] {
+        let mut key_down = [false; 16];
+        for (down, until) in key_down.iter_mut().zip(self.until.iter_mut()) {
+            match until {
+                Some(time) if *time > now => *down = true,
+                _ => *until = None,
+            }
+        }
+        key_down
+    }
+}
+
+/// Turns the bytes typed into keys. Arrow keys come as escape sequences,
+/// other sequences (function keys etc.) and Alt chords are skipped, and an
+/// escape with nothing after it is the Esc key. Unless `last` says no more
+/// bytes are coming, an escape or a sequence at the end is left for the next
+/// call. Gives the keys and how many bytes were parsed.
+fn parse_input(bytes: &[u8], last: bool) -> (Vec<Input>, usize) {
+    let mut inputs = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let byte = bytes[i];
+        i += 1;
+        let key = match byte {
+            0x1b => match bytes.get(i) {
+                None if !last => return (inputs, start),
+                None => {
+                    inputs.push(Input::Quit);
+                    None
+                }
+                Some(b'[' | b'O') => {
+                    // the sequence ends with a letter or a "~"
+                    let end = match bytes[i + 1..]
+                        .iter()
+                        .position(|byte| (0x40..=0x7e).contains(byte))
+                    {
+                        Some(end) => i + 1 + end,
+                        None if !last => return (inputs, start),
+                        None => bytes.len(),
+                    };
+                    let final_byte = bytes.get(end).copied();
+                    i = end + 1;
+                    match final_byte {
+                        Some(b'A') => Some(Key::UP),
+                        Some(b'B') => Some(Key::DOWN),
+                        Some(b'C') => Some(Key::RIGHT),
+                        Some(b'D') => Some(Key::LEFT),
+                        _ => None,
+                    }
+                }
+                // Alt and the key
+                Some(_) => {
+                    i += 1;
+                    None
+                }
+            },
+            0x03 => {
+                inputs.push(Input::Quit);
+                None
+            }
+            b' ' => Some(Key::SPACE),
+            b'\r' | b'\n' => Some(Key::RETURN),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => Key::from_name(&(byte as char).to_string()),
+            _ => None,
+        };
+        if let Some(key) = key {
+            inputs.push(Input::Key(key));
+        }
+    }
+    (inputs, bytes.len())
+}
+
+/// Draws the display as lines of half blocks colored with ANSI escapes,
+/// two rows of pixels to a line
+fn render(pixels: &[u8], palette: &Palette) -> String {
+    let width = SCREEN_WIDTH as usize;
+    let mut out = String::new();
+    for y in (0..SCREEN_HEIGHT as usize).step_by(2) {
+        // colors are only set when they change along the line
+        let mut colors = None;
+        for x in 0..width {
+            let upper = palette.color(pixels[y * width + x]);
+            let lower = palette.color(pixels[(y + 1) * width + x]);
+            if colors != Some((upper, lower)) {
+                out.push_str(&format!(
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                    upper.r, upper.g, upper.b, lower.r, lower.g, lower.b
+                ));
+                colors = Some((upper, lower));
+            }
+            out.push(UPPER_HALF_BLOCK);
+        }
+        out.push_str("\x1b[0m\r\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::builtin_palettes;
+
+    fn key(name: &str) -> Key {
+        Key::from_name(name).unwrap()
+    }
+
+    #[test]
+    fn parse_input_works() {
+        let keys = |bytes| parse_input(bytes, true).0;
+        assert_eq!(
+            keys(b"q4 \r"),
+            [
+                Input::Key(key("Q")),
+                Input::Key(key("4")),
+                Input::Key(Key::SPACE),
+                Input::Key(Key::RETURN),
+            ]
+        );
+        assert_eq!(
+            keys(b"\x1b[A\x1bOD\x1b[15~W"),
+            [
+                Input::Key(Key::UP),
+                Input::Key(Key::LEFT),
+                Input::Key(key("W")),
+            ]
+        );
+        assert_eq!(keys(b"a\x03"), [Input::Key(key("A")), Input::Quit]);
+        assert_eq!(
+            keys(b"z0Z9-"),
+            [
+                Input::Key(key("Z")),
+                Input::Key(key("0")),
+                Input::Key(key("Z")),
+                Input::Key(key("9")),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_input_handles_escapes() {
+        // only an escape with nothing after it quits
+        assert_eq!(parse_input(b"\x1b", true), (vec![Input::Quit], 1));
+        assert_eq!(parse_input(b"\x1bq\x1b\x1b", true), (vec![], 4));
+        assert_eq!(parse_input(b"\x1b[", true), (vec![], 2));
+
+        // escapes and sequences cut off by the end of a read wait for the
+        // rest of them
+        assert_eq!(
+            parse_input(b"a\x1b", false),
+            (vec![Input::Key(key("A"))], 1)
+        );
+        assert_eq!(parse_input(b"\x1b[1", false), (vec![], 0));
+        assert_eq!(
+            parse_input(b"\x1b[1;3A", false),
+            (vec![Input::Key(Key::UP)], 6)
+        );
+        assert_eq!(parse_input(b"\x1bq", false), (vec![], 2));
+    }
+
+    #[test]
+    fn held_keys_work() {
+        let start = Instant::now();
+        let mut held = HeldKeys::default();
+        held.press(0x5, start);
+        assert!(held.key_down(start)[0x5]);
+        assert!(!held.key_down(start)[0x6]);
+        // the key repeat starts before the first hold runs out
+        let repeat = start + FIRST_HOLD - Duration::from_millis(10);
+        held.press(0x5, repeat);
+        assert!(held.key_down(repeat + REPEAT_HOLD - Duration::from_millis(1))[0x5]);
+        assert!(!held.key_down(repeat + REPEAT_HOLD)[0x5]);
+        // typed again after it was let go, it's a new press
+        let later = repeat + FIRST_HOLD;
+        held.press(0x5, later);
+        assert!(held.key_down(later + FIRST_HOLD - Duration::from_millis(1))[0x5]);
+    }
+
+    #[test]
+    fn render_works() {
+        let palette = &builtin_palettes()[0];
+        let mut pixels = [0; SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize];
+        pixels[0] = 1;
+        let out = render(&pixels, palette);
+        assert_eq!(out.lines().count(), SCREEN_HEIGHT as usize / 2);
+        assert_eq!(
+            out.chars().filter(|c| *c == UPPER_HALF_BLOCK).count(),
+            SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize / 2
+        );
+        // the first cell is lit on top, the rest of the line is background
+        let (lit, dark) = (palette.color(1), palette.color(0));
+        let first_line = out.lines().next().unwrap();
+        assert!(first_line.starts_with(&format!(
+            "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m{}",
+            lit.r, lit.g, lit.b, dark.r, dark.g, dark.b, UPPER_HALF_BLOCK
+        )));
+        assert_eq!(first_line.matches("\x1b[38;2").count(), 2);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::octo;
+
+/// The program shown while the window waits for a ROM to be dropped on it:
+/// "DROP A ROM" with a blinking arrow under it
+const SOURCE: &str = "
+:alias x v0
+:alias y v1
+:alias timer v2
+
+:macro letter sprite-label width {
+    i := sprite-label
+    sprite x y 5
+    x += width
+}
+
+: main
+    x := 9
+    y := 10
+    letter d 5  letter r 5  letter o 5  letter p 8
+    letter a 8
+    letter r 5  letter o 5  letter m 6
+
+    x := 29
+    y := 19
+    loop
+        i := arrow
+        sprite x y 5
+        timer := 30
+        delay := timer
+        loop
+            timer := delay
+            if timer != 0 then
+        again
+    again
+
+: d     0xE0 0x90 0x90 0x90 0xE0
+: r     0xE0 0x90 0xE0 0xA0 0x90
+: o     0x60 0x90 0x90 0x90 0x60
+: p     0xE0 0x90 0xE0 0x80 0x80
+: a     0x60 0x90 0xF0 0x90 0x90
+: m     0x88 0xD8 0xA8 0x88 0x88
+: arrow 0x20 0x20 0xA8 0x70 0x20
+";
+
+pub fn rom() -> Result<Vec<u8>, String> {
+    octo::compile(SOURCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{step_instruction, Chip8};
+
+    #[test]
+    fn rom_draws_text() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&rom().unwrap(), 0x200).unwrap();
+        chip8.regs.pc = 0x200;
+        for _ in 0..100 {
+            step_instruction(&mut chip8).unwrap();
+        }
+        // the top left corner of the D
+        assert_eq!(chip8.get_px(9, 10), 1);
+        assert_eq!(chip8.get_px(8, 10), 0);
+    }
+}
@@ -0,0 +1,621 @@
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::controller::Button;
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::mouse::MouseButton;
+use sdl2::{EventPump, Sdl};
+
+use crate::browser::{self, Action, Browser, Choice, Entry, EntryCache, History};
+use crate::chip8::step_instruction;
+use crate::cli::Options;
+use crate::config::{self, Config};
+use crate::debugger::{Debugger, DEBUG_VIEW_HEIGHT, DEBUG_VIEW_WIDTH};
+use crate::display::Rect;
+use crate::filter::FrameFilter;
+use crate::gamepad::Gamepads;
+use crate::keymap::{Key, Keymap};
+use crate::osd::{FrameStats, Osd, StatsInfo};
+use crate::overlay::{Ink, Overlay, CELL_HEIGHT, CELL_WIDTH};
+use crate::palette::Palettes;
+use crate::romdb::RomDb;
+use crate::runner::{window_title, Frontend, Poll, Program, Stop, SAMPLE_RATE};
+use crate::screen::Screen;
+use crate::settings::Settings;
+use crate::RomFrontend;
+
+// sound queued beyond this many samples is dropped, so it doesn't lag behind
+const MAX_QUEUED_SAMPLES: u32 = SAMPLE_RATE / 10;
+
+/// Plays in the SDL window, with the keyboard and controllers, and the ROM
+/// browser, on-screen display and debugger on top
+pub struct SdlFrontend<'a> {
+    options: &'a Options,
+    config: &'a Config,
+    screen: Screen<'a>,
+    event_pump: EventPump,
+    gamepads: Option<Gamepads>,
+    audio: Option<AudioQueue<f32>>,
+    palettes: Palettes,
+    filter: FrameFilter,
+    keymap: Keymap,
+    keyboard_down: [bool; 16],
+    history: History,
+    browser: Option<Browser>,
+    /// loaded the first time the browser opens, kept for the next times
+    rom_db: Option<RomDb>,
+    browser_entries: EntryCache,
+    overlay: Overlay,
+    osd: Osd,
+    frame_stats: FrameStats,
+    frame_start: Instant,
+    debugger: Option<Debugger>,
+    debug_view: Overlay,
+    /// something else than the display changed, e.g. the palette or the
+    /// size of the window, so it has to be drawn again
+    redraw: bool,
+    /// whether the last frame drawn had the overlay on it
+    overlay_shown: bool,
+}
+
+impl<'a> SdlFrontend<'a> {
+    pub fn new(
+        sdl_context: &Sdl,
+        screen: Screen<'a>,
+        rom_frontend: RomFrontend,
+        settings: &Settings,
+        options: &'a Options,
+        config: &'a Config,
+    ) -> Result<SdlFrontend<'a>, String> {
+        // playing without controllers or sound is fine, so failing to set
+        // them up isn't fatal
+        let gamepads = match sdl_context.game_controller() {
+            Ok(subsystem) => Some(Gamepads::new(
+                subsystem,
+                rom_frontend.pad_mapping,
+                settings.stick_threshold,
+            )),
+            Err(err) => {
+                println!("Controllers are not available, error is \"{}\"", err);
+                None
+            }
+        };
+        let audio = match open_audio(sdl_context) {
+            Ok(audio) => Some(audio),
+            Err(err) => {
+                println!("Sound is not available, error is \"{}\"", err);
+                None
+            }
+        };
+        let now = Instant::now();
+        Ok(SdlFrontend {
+            options,
+            config,
+            screen,
+            event_pump: sdl_context.event_pump()?,
+            gamepads,
+            audio,
+            palettes: rom_frontend.palettes,
+            filter: rom_frontend.filter,
+            keymap: rom_frontend.keymap,
+            keyboard_down: [false; 16],
+            history: load_history(),
+            browser: None,
+            rom_db: None,
+            browser_entries: EntryCache::default(),
+            overlay: Overlay::new(),
+            osd: Osd::default(),
+            frame_stats: FrameStats::new(now),
+            frame_start: now,
+            debugger: None,
+            debug_view: Overlay::with_size(DEBUG_VIEW_WIDTH, DEBUG_VIEW_HEIGHT),
+            redraw: true,
+            overlay_shown: false,
+        })
+    }
+
+    /// Lists the ROMs of the ROM directory and of the history in the ROM
+    /// browser, the running ROM is paused while it's open. ROMs are only
+    /// read again when their file changed since the browser was last open.
+    pub fn open_browser(&mut self) {
+        let (options, config) = (self.options, self.config);
+        let rom_db = &*self
+            .rom_db
+            .get_or_insert_with(|| crate::load_database(config));
+        let entries = &mut self.browser_entries;
+        let mut describe =
+            |path: &PathBuf| entries.get(path, |path| browser_entry(path, rom_db, options, config));
+        let paths = match &self.config.rom_dir {
+            Some(rom_dir) => browser::scan(rom_dir).unwrap_or_else(|err| {
+                println!("{}", err);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        let all = paths.iter().filter_map(&mut describe).collect();
+        let remembered = self
+            .history
+            .recent
+            .iter()
+            .chain(&self.history.favorites)
+            .filter_map(&mut describe)
+            .collect();
+        self.browser = Some(Browser::new(all, remembered));
+        self.keyboard_down = [false; 16];
+    }
+
+    pub fn record_played(&mut self, rom_path: &str) {
+        if let Ok(path) = std::fs::canonicalize(rom_path) {
+            self.history.played(path);
+            save_history(&self.history);
+        }
+    }
+
+    /// Handles an event for the ROM browser, gives whether it was one
+    fn handle_browser_event(&mut self, event: &Event, next_rom: &mut Option<String>) -> bool {
+        let (browser, action) = match (self.browser.as_mut(), browser_action(event)) {
+            (Some(browser), Some(action)) => (browser, action),
+            _ => return false,
+        };
+        match browser.handle(action, &mut self.history) {
+            Some(Choice::Play(path)) => *next_rom = Some(path.to_string_lossy().to_string()),
+            Some(Choice::Close) => self.browser = None,
+            Some(Choice::FavoritesChanged) => save_history(&self.history),
+            None => {}
+        }
+        true
+    }
+
+    /// Switches to another ROM, a ROM that fails to load leaves the current
+    /// one running. A symbol file given with `--symbols` is for the first
+    /// ROM, others only get the symbols next to them.
+    fn switch_rom(&mut self, rom_path: &str, program: &mut Program) {
+        println!("Loading ROM at path {}", rom_path);
+        let options = Options {
+            symbols: None,
+            ..self.options.clone()
+        };
+        let loaded = match crate::load_program(rom_path, &options, self.config) {
+            Ok(loaded) => loaded,
+            Err(_) => {
+                self.osd.message("Failed to load the ROM, see the console");
+                return;
+            }
+        };
+        let rom_frontend = match RomFrontend::new(&loaded.settings, self.config) {
+            Ok(rom_frontend) => rom_frontend,
+            Err(err) => {
+                notify(&mut self.osd, &err);
+                return;
+            }
+        };
+        *program = loaded;
+        self.palettes = rom_frontend.palettes;
+        self.filter = rom_frontend.filter;
+        self.keymap = rom_frontend.keymap;
+        if let Some(gamepads) = self.gamepads.as_mut() {
+            gamepads.set_mapping(rom_frontend.pad_mapping);
+        }
+        self.keyboard_down = [false; 16];
+        self.browser = None;
+        if self.debugger.is_some() {
+            self.debugger = Some(Debugger::new(&program.chip8));
+        }
+        self.record_played(rom_path);
+        if let Err(err) = self.screen.set_title(&window_title(Some(rom_path))) {
+            println!("Failed to set the window title, error is \"{}\"", err);
+        }
+        let file_name = Path::new(rom_path).file_name().unwrap_or_default();
+        self.osd
+            .message(&format!("Playing {}", file_name.to_string_lossy()));
+    }
+
+    /// Draws the display, with the browser or the OSD over it and the
+    /// debugger next to it
+    fn draw(&mut self, program: &Program, overlay_visible: bool) {
+        let pixels = self.filter.apply(&program.chip8.display.to_bytes());
+        if overlay_visible {
+            match &self.browser {
+                Some(browser) => browser.draw(&self.history, &mut self.overlay),
+                None => self.overlay.fill_all(Ink::Clear),
+            }
+            let stats = StatsInfo {
+                frame_stats: &self.frame_stats,
+                platform: program
+                    .settings
+                    .platform
+                    .map_or("CHIP-8", |platform| platform.name()),
+                quirks: program.chip8.quirks,
+                key_down: program.chip8.key_down,
+            };
+            self.osd.draw(&stats, &mut self.overlay);
+        }
+        let shown_debug_view = self.debugger.as_ref().map(|debugger| {
+            debugger.draw(&program.chip8, &program.symbols, &mut self.debug_view);
+            &self.debug_view
+        });
+        self.screen
+            .present(
+                pixels,
+                self.palettes.current(),
+                Some(&self.overlay).filter(|_| overlay_visible),
+                shown_debug_view,
+            )
+            .unwrap();
+    }
+}
+
+impl Frontend for SdlFrontend<'_> {
+    fn poll_input(&mut self, program: &mut Program) -> Poll {
+        self.frame_start = Instant::now();
+        // path of a ROM to switch to, dropped on the window or picked in the browser
+        let mut next_rom: Option<String> = None;
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+        // most events change something on the window, telling which don't
+        // isn't worth it
+        self.redraw |= !events.is_empty();
+        for event in events {
+            if let Some(gamepads) = self.gamepads.as_mut() {
+                gamepads.handle_event(&event);
+            }
+            if self.handle_browser_event(&event, &mut next_rom) {
+                continue;
+            }
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return Poll::Quit,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    ..
+                } => self.osd.stats_visible = !self.osd.stats_visible,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    ..
+                } => {
+                    let palette = self.palettes.cycle();
+                    notify(
+                        &mut self.osd,
+                        &format!("Switched to palette {}", palette.name),
+                    );
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => {
+                    let kind = self.filter.cycle();
+                    notify(
+                        &mut self.osd,
+                        &format!("Switched to filter {}", kind.name()),
+                    );
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => {
+                    let mode = self.screen.toggle_scale_mode();
+                    notify(
+                        &mut self.osd,
+                        &format!("Switched to {} scaling", mode.name()),
+                    );
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => match program.reset(self.options.seed) {
+                    Ok(()) => {
+                        if let Some(debugger) = self.debugger.as_mut() {
+                            debugger.restart(&program.chip8);
+                        }
+                        notify(&mut self.osd, "Reset");
+                    }
+                    Err(err) => notify(&mut self.osd, &format!("Failed to reset: {}", err)),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                }
+                | Event::ControllerButtonDown {
+                    button: Button::Guide,
+                    ..
+                } => self.open_browser(),
+                Event::DropFile { filename, .. } => next_rom = Some(filename),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } if self.debugger.is_some() => self.debugger = None,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => {
+                    self.debugger = Some(Debugger::new(&program.chip8));
+                    // the debugger needs room next to the display
+                    let (width, height) = (DEBUG_VIEW_WIDTH as u32, DEBUG_VIEW_HEIGHT as u32);
+                    if let Err(err) = self.screen.ensure_size(width * 2, height * 2) {
+                        println!("Failed to resize the window, error is \"{}\"", err);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => {
+                    if let Some(debugger) = self.debugger.as_mut() {
+                        debugger.toggle_pause();
+                        match debugger.paused {
+                            true => notify(
+                                &mut self.osd,
+                                &format!(
+                                    "Paused at {}",
+                                    program.symbols.describe(program.chip8.regs.pc)
+                                ),
+                            ),
+                            false => notify(&mut self.osd, "Running"),
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } if self
+                    .debugger
+                    .as_ref()
+                    .is_some_and(|debugger| debugger.paused) =>
+                {
+                    if let Err(err) = step_instruction(&mut program.chip8) {
+                        let err = program.describe_error(err);
+                        notify(&mut self.osd, &format!("Emulation error: {}", err));
+                    }
+                    program.chip8.vblank_wait = false;
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    let debugger = match self.debugger.as_mut() {
+                        Some(debugger) => debugger,
+                        None => continue,
+                    };
+                    let address = self.screen.debug_view_position(x, y).and_then(|(x, y)| {
+                        debugger.address_at(&program.chip8, x / CELL_WIDTH, y / CELL_HEIGHT)
+                    });
+                    if let Some(address) = address {
+                        let place = program.symbols.describe(address);
+                        let message = match debugger.toggle_breakpoint(address) {
+                            true => format!("Breakpoint set at {}", place),
+                            false => format!("Breakpoint removed at {}", place),
+                        };
+                        notify(&mut self.osd, &message);
+                    }
+                }
+                Event::MouseWheel { y, .. } => {
+                    if let Some(debugger) = self.debugger.as_mut() {
+                        debugger.scroll_memory(-y * 2);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => {
+                    let fullscreen = !self.screen.is_fullscreen();
+                    if let Err(err) = self.screen.set_fullscreen(fullscreen) {
+                        println!("Failed to toggle fullscreen, error is \"{}\"", err);
+                    }
+                }
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    ..
+                } if self.browser.is_none() => {
+                    if let Some(key) = keymap_button(&self.keymap, scancode) {
+                        self.keyboard_down[key] = true;
+                    }
+                }
+                Event::KeyUp {
+                    scancode: Some(scancode),
+                    ..
+                } => {
+                    if let Some(key) = keymap_button(&self.keymap, scancode) {
+                        self.keyboard_down[key] = false;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(rom_path) = next_rom {
+            self.switch_rom(&rom_path, program);
+        }
+
+        // the ROM is paused while the browser is open
+        let paused = self
+            .debugger
+            .as_ref()
+            .is_some_and(|debugger| debugger.paused);
+        if self.browser.is_some() || paused {
+            return Poll::Pause;
+        }
+        // a key is down if it's held on the keyboard or on any controller
+        let pad_down = self
+            .gamepads
+            .as_ref()
+            .map_or([false; 16], |pads| pads.key_down());
+        for (key, down) in program.chip8.key_down.iter_mut().enumerate() {
+            *down = self.keyboard_down[key] || pad_down[key];
+        }
+        Poll::Run
+    }
+
+    fn present(&mut self, program: &Program, executed: u32, dirty: Option<Rect>) {
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.watch_memory(&program.chip8.memory);
+        }
+
+        // a window that would look the same isn't drawn again, so idle menus
+        // don't keep the CPU busy
+        let overlay_visible = self.browser.is_some() || self.osd.is_visible();
+        let redraw = std::mem::take(&mut self.redraw);
+        let unchanged = dirty.is_none()
+            && !redraw
+            && self.filter.is_settled()
+            && !overlay_visible
+            && !self.overlay_shown
+            && self.debugger.is_none();
+        self.overlay_shown = overlay_visible;
+        if !unchanged {
+            self.draw(program, overlay_visible);
+        }
+        self.osd.tick();
+        self.frame_stats
+            .frame(executed, self.frame_start.elapsed(), Instant::now());
+    }
+
+    fn output_audio(&mut self, samples: &[f32]) {
+        if let Some(audio) = self.audio.as_ref() {
+            let queued = audio.size() / std::mem::size_of::<f32>() as u32;
+            if queued < MAX_QUEUED_SAMPLES {
+                if let Err(err) = audio.queue_audio(samples) {
+                    println!("Failed to play sound, error is \"{}\"", err);
+                }
+            }
+        }
+    }
+
+    fn should_stop(&mut self, pc: u16) -> bool {
+        self.debugger
+            .as_mut()
+            .is_some_and(|debugger| debugger.should_stop(pc))
+    }
+
+    fn stopped(&mut self, program: &Program, stop: Stop) -> Result<(), String> {
+        match (stop, self.debugger.as_mut()) {
+            (Stop::Breakpoint, Some(debugger)) => {
+                debugger.paused = true;
+                let pc = program.chip8.regs.pc;
+                let address = program.symbols.describe(pc);
+                notify(&mut self.osd, &format!("Breakpoint at {}", address));
+            }
+            (Stop::Breakpoint, None) => {}
+            // with the debugger open, errors can be looked into
+            (Stop::Error(err), Some(debugger)) => {
+                debugger.paused = true;
+                notify(&mut self.osd, &format!("Emulation error: {}", err));
+            }
+            (Stop::Error(err), None) => return Err(err),
+        }
+        Ok(())
+    }
+}
+
+fn open_audio(sdl_context: &Sdl) -> Result<AudioQueue<f32>, String> {
+    let desired = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: None,
+    };
+    let audio = sdl_context.audio()?.open_queue(None, &desired)?;
+    audio.resume();
+    Ok(audio)
+}
+
+/// The CHIP-8 key the keymap has for a key of the keyboard
+fn keymap_button(keymap: &Keymap, scancode: Scancode) -> Option<usize> {
+    Key::from_name(scancode.name()).and_then(|key| keymap.button(key))
+}
+
+/// Tells about something that happened while playing, on the console and
+/// on the screen
+fn notify(osd: &mut Osd, message: &str) {
+    println!("{}", message);
+    osd.message(message);
+}
+
+/// What a key or controller button does in the ROM browser
+fn browser_action(event: &Event) -> Option<Action> {
+    match event {
+        Event::KeyDown {
+            keycode: Some(keycode),
+            keymod,
+            ..
+        } => match *keycode {
+            Keycode::Up => Some(Action::Up),
+            Keycode::Down => Some(Action::Down),
+            Keycode::PageUp => Some(Action::PageUp),
+            Keycode::PageDown => Some(Action::PageDown),
+            Keycode::Home => Some(Action::First),
+            Keycode::End => Some(Action::Last),
+            Keycode::Tab if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                Some(Action::PreviousTab)
+            }
+            Keycode::Tab | Keycode::Right => Some(Action::NextTab),
+            Keycode::Left => Some(Action::PreviousTab),
+            Keycode::Space => Some(Action::ToggleFavorite),
+            Keycode::Return | Keycode::KpEnter => Some(Action::Play),
+            Keycode::Escape | Keycode::F6 => Some(Action::Close),
+            _ => None,
+        },
+        Event::ControllerButtonDown { button, .. } => match button {
+            Button::DPadUp => Some(Action::Up),
+            Button::DPadDown => Some(Action::Down),
+            Button::DPadLeft | Button::LeftShoulder => Some(Action::PreviousTab),
+            Button::DPadRight | Button::RightShoulder => Some(Action::NextTab),
+            Button::Y => Some(Action::ToggleFavorite),
+            Button::A | Button::Start => Some(Action::Play),
+            Button::B | Button::Guide => Some(Action::Close),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Describes a ROM for the browser, files that can't be read are left out.
+/// The platform is the one the ROM would be run as.
+fn browser_entry(path: &Path, rom_db: &RomDb, options: &Options, config: &Config) -> Option<Entry> {
+    let data = crate::load_file(&path.to_string_lossy()).ok()?;
+    let size = data.len() as u64;
+    let platform = crate::rom_from_file(data)
+        .ok()
+        .and_then(|(rom, cartridge_config, _)| {
+            let rom_path = path.to_string_lossy();
+            let (settings, _) = crate::rom_settings(
+                &rom_path,
+                &rom,
+                cartridge_config.as_ref(),
+                rom_db,
+                options,
+                config,
+            )
+            .ok()?;
+            settings.platform
+        });
+    Some(Entry {
+        path: path.to_path_buf(),
+        size,
+        platform,
+    })
+}
+
+/// Loads the recently played and favorite ROMs, problems with them are only
+/// reported
+fn load_history() -> History {
+    let path = match config::history_path() {
+        Some(path) => path,
+        None => return History::default(),
+    };
+    History::load(&path).unwrap_or_else(|err| {
+        println!("{}", err);
+        History::default()
+    })
+}
+
+fn save_history(history: &History) {
+    if let Some(path) = config::history_path() {
+        if let Err(err) = history.save(&path) {
+            println!("{}", err);
+        }
+    }
+}